@@ -0,0 +1,572 @@
+use std::collections::VecDeque;
+
+use crate::entities::Entity;
+use crate::game::Map;
+use crate::inventory::{AmmoKind, Item};
+use crate::level::Level;
+use crate::pickups::Pickup;
+use crate::rng::Rng;
+use crate::sprites::Sprite;
+use crate::thin_wall::ThinWall;
+use crate::weapon::{WeaponKind, SWORD_TEXTURE};
+
+/// Texture id of the exit switch panel generated levels are finished at.
+pub const EXIT_TEXTURE: u8 = 18;
+
+// Placeholder cell values while carving; walls get their real textures at the end
+const FLOOR: u8 = 0;
+const WALL: u8 = 1;
+
+/// Chance that a wall uses one of the accent textures rather than the main one.
+const ACCENT_CHANCE: f64 = 0.1;
+/// Caves whose largest open region covers less of the map than this are thrown away and regrown.
+const MIN_CAVE_FRACTION: f64 = 0.35;
+const MAX_CAVE_ATTEMPTS: usize = 20;
+/// Items are never placed closer to the spawn point than this many steps.
+const MIN_PLACEMENT_DISTANCE: u32 = 3;
+/// Smallest side of a generated map: one open cell with a wall either side of it.
+const MIN_SIZE: usize = 3;
+/// What waits in each secret closet.
+const SECRET_REWARD: Item = Item::Treasure(500);
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum DungeonMode {
+    /// Rectangular rooms from a binary space partition, joined by corridors.
+    Rooms,
+    /// Organic caverns grown with a cellular automaton.
+    Caves,
+}
+
+pub struct DungeonConfig {
+    pub mode: DungeonMode,
+    pub width: usize,
+    pub height: usize,
+    /// Smallest side of a room, in cells. Rooms mode only.
+    pub min_room_size: usize,
+    /// Fraction of cells that start out as wall before the automaton runs. Caves mode only.
+    pub cave_fill: f64,
+    pub cave_iterations: usize,
+    /// Texture ids for walls. The first is used for most of them and the rest for variety.
+    pub wall_textures: Vec<u8>,
+    pub contents: Contents,
+}
+
+/// How many of each kind of thing to scatter through a generated level.
+pub struct Contents {
+    pub barrels: usize,
+    /// Includes one of each weapon; the rest are random supplies.
+    pub pickups: usize,
+    pub decorations: usize,
+    /// Hidden closets behind secret walls, each holding some treasure. Fewer are made if the
+    /// walls aren't thick enough anywhere to fit them.
+    pub secrets: usize,
+}
+
+impl Default for DungeonConfig {
+    fn default() -> Self {
+        DungeonConfig {
+            mode: DungeonMode::Rooms,
+            width: 32,
+            height: 32,
+            min_room_size: 4,
+            cave_fill: 0.45,
+            cave_iterations: 5,
+            wall_textures: vec![1, 2, 3],
+            contents: Contents {
+                barrels: 6,
+                pickups: 12,
+                decorations: 4,
+                secrets: 2,
+            },
+        }
+    }
+}
+
+/// A rectangle of cells being partitioned into rooms.
+#[derive(Clone, Copy)]
+struct Area {
+    x: usize,
+    y: usize,
+    width: usize,
+    height: usize,
+}
+
+/// Generates a connected level entirely enclosed by walls, with a spawn point, an exit switch on
+/// the wall furthest from it, and items scattered through the open space. Sizes below MIN_SIZE
+/// are raised to it.
+pub fn generate(config: &DungeonConfig, rng: &mut Rng) -> Level {
+    let (width, height) = (config.width.max(MIN_SIZE), config.height.max(MIN_SIZE));
+    let mut map = Map::filled(width, height, WALL);
+    match config.mode {
+        DungeonMode::Rooms => carve_rooms(&mut map, config, rng),
+        DungeonMode::Caves => {
+            if !carve_caves(&mut map, config, rng) {
+                map = Map::filled(width, height, WALL);
+                carve_rooms(&mut map, config, rng);
+            }
+        }
+    }
+    // Rays are only guaranteed to stop if nothing open touches the edge of the map
+    enclose(&mut map);
+
+    let mut floor: Vec<usize> = (0..map.cells.len()).filter(|index| map.cells[*index] == FLOOR).collect();
+    if floor.is_empty() {
+        // Too small for a room to fit inside its walls, so leave just the middle cell open
+        let middle = height / 2 * width + width / 2;
+        map.cells[middle] = FLOOR;
+        floor.push(middle);
+    }
+    let spawn = floor[rng.below(floor.len())];
+
+    for cell in &mut map.cells {
+        if *cell == WALL {
+            *cell = if config.wall_textures.len() > 1 && rng.next_f64() < ACCENT_CHANCE {
+                config.wall_textures[1 + rng.below(config.wall_textures.len() - 1)]
+            } else {
+                config.wall_textures[0]
+            };
+        }
+    }
+
+    populate(map, spawn, &config.contents, rng)
+}
+
+/// Finishes a carved map: puts the exit switch on a wall beside the reachable cell furthest from
+/// `spawn`, hides secret closets in thick walls, then scatters `contents` through the rest of the
+/// open space.
+pub fn populate(mut map: Map, spawn: usize, contents: &Contents, rng: &mut Rng) -> Level {
+    let floor: Vec<usize> = (0..map.cells.len()).filter(|index| map.cells[*index] == FLOOR).collect();
+    let distance = distances(&map, spawn);
+
+    let exit = floor
+        .iter()
+        .filter_map(|index| Some((*index, distance[*index]?)))
+        .filter_map(|(index, steps)| Some((wall_neighbour(&map, index)?, steps)))
+        .max_by_key(|(_, steps)| *steps)
+        .map(|(wall, _)| wall);
+    if let Some(exit) = exit {
+        map.cells[exit] = EXIT_TEXTURE;
+    }
+
+    let closets = carve_secrets(&mut map, &distance, contents.secrets, rng);
+
+    let mut spots: Vec<usize> = floor
+        .iter()
+        .copied()
+        .filter(|index| distance[*index].is_some_and(|steps| steps >= MIN_PLACEMENT_DISTANCE))
+        .collect();
+    rng.shuffle(&mut spots);
+    let mut spots = spots.into_iter().map(|index| map_point(&map, index));
+
+    let entities = spots.by_ref().take(contents.barrels).map(|(x, y)| Entity::barrel(x, y)).collect();
+
+    // Always leave both weapons somewhere in the level, then fill up with random supplies
+    let supplies = [
+        Item::Health(25.0),
+        Item::Armor(50.0),
+        Item::Ammo(AmmoKind::Rockets, 5),
+        Item::Ammo(AmmoKind::Mana, 20),
+        Item::Treasure(100),
+    ];
+    let mut items = vec![Item::Weapon(WeaponKind::RocketLauncher), Item::Weapon(WeaponKind::FireStaff)];
+    while items.len() < contents.pickups {
+        items.push(supplies[rng.below(supplies.len())]);
+    }
+    items.truncate(contents.pickups);
+    let mut pickups: Vec<Pickup> = items.into_iter().zip(spots.by_ref()).map(|(item, (x, y))| Pickup::new(x, y, item)).collect();
+    pickups.extend(closets.into_iter().map(|index| {
+        let (x, y) = map_point(&map, index);
+        Pickup::new(x, y, SECRET_REWARD)
+    }));
+
+    let sprites = spots
+        .take(contents.decorations)
+        .map(|(x, y)| Sprite { x, y, storey: 0, texture_id: SWORD_TEXTURE, distance: 0.0 })
+        .collect();
+
+    let (spawn_x, spawn_y) = map_point(&map, spawn);
+    Level {
+        spawn_direction: open_direction(&map, spawn),
+        map,
+        spawn_x,
+        spawn_y,
+        entities,
+        pickups,
+        sprites,
+    }
+}
+
+/// Hollows out up to `count` closets behind walls next to reachable floor, marking the wall in
+/// front of each as a secret. Pushing it slides the wall down a two-cell passage to the back, which
+/// opens onto a nook to one side where the reward goes, out of the wall's way; those cells are
+/// returned.
+fn carve_secrets(map: &mut Map, distance: &[Option<u32>], count: usize, rng: &mut Rng) -> Vec<usize> {
+    let directions = [(1, 0), (0, 1), (-1, 0), (0, -1)];
+    let mut candidates: Vec<(usize, (i32, i32))> = (0..map.cells.len())
+        .filter(|index| distance[*index].is_some_and(|steps| steps >= MIN_PLACEMENT_DISTANCE))
+        .flat_map(|index| directions.into_iter().map(move |direction| (index, direction)))
+        .collect();
+    rng.shuffle(&mut candidates);
+
+    let mut closets = Vec::new();
+    for (index, (dx, dy)) in candidates {
+        if closets.len() >= count {
+            break;
+        }
+        let (x, y) = ((index % map.width) as i32, (index / map.width) as i32);
+        let cell = |step: i32| (x + dx * step, y + dy * step);
+        let is_plain_wall = |map: &Map, (x, y): (i32, i32)| {
+            map.get(x, y).is_some_and(|cell| cell != FLOOR && cell != EXIT_TEXTURE)
+                && map.thin_wall_at(x, y).is_none()
+                && !map.secrets.contains(&(y as usize * map.width + x as usize))
+        };
+
+        // The secret wall, the passage behind it with the nook off its first cell, and solid wall
+        // all around so the closet can't be seen or reached any other way
+        let beside = |(x, y): (i32, i32), side: i32| (x + dy * side, y + dx * side);
+        let nook = beside(cell(2), 1);
+        let closet = [cell(2), cell(3), nook];
+        let surrounding = [
+            cell(4),
+            beside(cell(1), 1),
+            beside(cell(2), -1),
+            beside(cell(3), 1),
+            beside(cell(3), -1),
+            beside(nook, 1),
+        ];
+        let fits = is_plain_wall(map, cell(1))
+            && closet.iter().all(|cell| is_plain_wall(map, *cell))
+            && surrounding.iter().all(|(x, y)| map.get(*x, *y).is_some_and(|cell| cell != FLOOR) && map.thin_wall_at(*x, *y).is_none());
+        if !fits {
+            continue;
+        }
+
+        for (x, y) in closet {
+            map.set(x as usize, y as usize, FLOOR);
+        }
+        let (wall_x, wall_y) = cell(1);
+        map.secrets.push(wall_y as usize * map.width + wall_x as usize);
+        closets.push(nook.1 as usize * map.width + nook.0 as usize);
+    }
+    closets
+}
+
+fn carve_rooms(map: &mut Map, config: &DungeonConfig, rng: &mut Rng) {
+    let area = Area { x: 1, y: 1, width: map.width - 2, height: map.height - 2 };
+    carve_partition(map, area, config.min_room_size, rng);
+    cut_corners(map);
+}
+
+/// Cuts the corners off rooms with a diagonal wall across each corner cell. Only corners of rooms
+/// at least three cells across in both directions are cut, so nothing is ever closed off.
+fn cut_corners(map: &mut Map) {
+    // Each corner as the diagonal step towards it, and the wall that cuts it off
+    let corners = [
+        (-1, -1, ThinWall::ANTI_DIAGONAL),
+        (1, -1, ThinWall::DIAGONAL),
+        (-1, 1, ThinWall::DIAGONAL),
+        (1, 1, ThinWall::ANTI_DIAGONAL),
+    ];
+    let is_floor = |map: &Map, x: i32, y: i32| map.get(x, y) == Some(FLOOR);
+
+    // Find every corner against the uncut map first, so cutting one doesn't make its neighbours look like corners
+    let mut cuts = Vec::new();
+    for index in 0..map.cells.len() {
+        let (x, y) = ((index % map.width) as i32, (index / map.width) as i32);
+        if !is_floor(map, x, y) {
+            continue;
+        }
+        for (dx, dy, thin_wall) in corners {
+            let walled = !is_floor(map, x + dx, y) && !is_floor(map, x, y + dy);
+            let room = (1..=2).all(|step| is_floor(map, x - dx * step, y) && is_floor(map, x, y - dy * step))
+                && is_floor(map, x - dx, y - dy);
+            if walled && room {
+                cuts.push((index, thin_wall));
+            }
+        }
+    }
+
+    for (index, thin_wall) in cuts {
+        map.cells[index] = WALL;
+        map.thin_walls[index] = Some(thin_wall);
+    }
+}
+
+/// Splits `area` in two and recurses into each half, joining them with a corridor, or carves a
+/// room once it is too small to split. Returns a cell inside the space carved.
+fn carve_partition(map: &mut Map, area: Area, min_size: usize, rng: &mut Rng) -> (usize, usize) {
+    // Each half needs room for the smallest room plus a wall on either side
+    let min_leaf = min_size + 2;
+    let can_split_x = area.width >= min_leaf * 2;
+    let can_split_y = area.height >= min_leaf * 2;
+    if !can_split_x && !can_split_y {
+        return carve_room(map, area, min_size, rng);
+    }
+
+    // Prefer cutting across the longer side so rooms don't end up as long slivers
+    let split_x = if can_split_x && can_split_y { area.width >= area.height } else { can_split_x };
+    let (first, second) = if split_x {
+        let at = min_leaf + rng.below(area.width - min_leaf * 2 + 1);
+        (Area { width: at, ..area }, Area { x: area.x + at, width: area.width - at, ..area })
+    } else {
+        let at = min_leaf + rng.below(area.height - min_leaf * 2 + 1);
+        (Area { height: at, ..area }, Area { y: area.y + at, height: area.height - at, ..area })
+    };
+
+    let a = carve_partition(map, first, min_size, rng);
+    let b = carve_partition(map, second, min_size, rng);
+    carve_corridor(map, a, b, rng);
+    if rng.next_f64() < 0.5 {
+        a
+    } else {
+        b
+    }
+}
+
+/// Carves a randomly sized room inside `area`, keeping a wall between it and the area's edges.
+fn carve_room(map: &mut Map, area: Area, min_size: usize, rng: &mut Rng) -> (usize, usize) {
+    let max_width = area.width.saturating_sub(2).max(1);
+    let max_height = area.height.saturating_sub(2).max(1);
+    let width = min_size.min(max_width) + rng.below(max_width - min_size.min(max_width) + 1);
+    let height = min_size.min(max_height) + rng.below(max_height - min_size.min(max_height) + 1);
+    let x = area.x + 1 + rng.below(max_width - width + 1);
+    let y = area.y + 1 + rng.below(max_height - height + 1);
+
+    carve_span(map, (x, y), (x + width - 1, y + height - 1));
+    (x + width / 2, y + height / 2)
+}
+
+/// Joins two cells with an L-shaped corridor.
+fn carve_corridor(map: &mut Map, from: (usize, usize), to: (usize, usize), rng: &mut Rng) {
+    let corner = if rng.next_f64() < 0.5 { (to.0, from.1) } else { (from.0, to.1) };
+    carve_span(map, from, corner);
+    carve_span(map, corner, to);
+}
+
+/// Opens every cell in the rectangle with corners `a` and `b`.
+fn carve_span(map: &mut Map, a: (usize, usize), b: (usize, usize)) {
+    for y in a.1.min(b.1)..=a.1.max(b.1) {
+        for x in a.0.min(b.0)..=a.0.max(b.0) {
+            map.set(x, y, FLOOR);
+        }
+    }
+}
+
+/// Grows caves from random noise, keeping only the largest connected cavern.
+/// Returns false if no attempt produced a cavern big enough to play in.
+fn carve_caves(map: &mut Map, config: &DungeonConfig, rng: &mut Rng) -> bool {
+    let interior = (map.width - 2) * (map.height - 2);
+
+    for _ in 0..MAX_CAVE_ATTEMPTS {
+        for y in 1..map.height - 1 {
+            for x in 1..map.width - 1 {
+                map.set(x, y, if rng.next_f64() < config.cave_fill { WALL } else { FLOOR });
+            }
+        }
+        for _ in 0..config.cave_iterations {
+            smooth(map);
+        }
+        enclose(map);
+
+        let region = largest_region(map);
+        if region.len() as f64 >= interior as f64 * MIN_CAVE_FRACTION {
+            // Wall off every pocket that isn't part of the main cavern
+            let mut cells = vec![WALL; map.cells.len()];
+            for index in region {
+                cells[index] = FLOOR;
+            }
+            map.cells = cells;
+            return true;
+        }
+    }
+
+    false
+}
+
+/// One step of the cave automaton: cells surrounded by walls fill in, open areas open up further.
+fn smooth(map: &mut Map) {
+    let mut cells = map.cells.clone();
+    for y in 0..map.height as i32 {
+        for x in 0..map.width as i32 {
+            let walls = (-1..=1)
+                .flat_map(|dy| (-1..=1).map(move |dx| (dx, dy)))
+                .filter(|(dx, dy)| (*dx, *dy) != (0, 0) && map.get(x + dx, y + dy).is_none_or(|cell| cell != FLOOR))
+                .count();
+            let index = y as usize * map.width + x as usize;
+            if walls > 4 {
+                cells[index] = WALL;
+            } else if walls < 4 {
+                cells[index] = FLOOR;
+            }
+        }
+    }
+    map.cells = cells;
+}
+
+/// Turns the outermost ring of cells into wall.
+fn enclose(map: &mut Map) {
+    for x in 0..map.width {
+        map.set(x, 0, WALL);
+        map.set(x, map.height - 1, WALL);
+    }
+    for y in 0..map.height {
+        map.set(0, y, WALL);
+        map.set(map.width - 1, y, WALL);
+    }
+}
+
+/// The indices of the four cells orthogonally next to `index`, skipping those off the map.
+fn neighbours(map: &Map, index: usize) -> impl Iterator<Item = usize> + '_ {
+    let (x, y) = ((index % map.width) as i32, (index / map.width) as i32);
+    [(1, 0), (0, 1), (-1, 0), (0, -1)]
+        .into_iter()
+        .filter(move |(dx, dy)| map.get(x + dx, y + dy).is_some())
+        .map(move |(dx, dy)| (y + dy) as usize * map.width + (x + dx) as usize)
+}
+
+/// Walking distance in steps from `start` to every open cell, or None where it can't be reached.
+pub fn distances(map: &Map, start: usize) -> Vec<Option<u32>> {
+    let mut distance = vec![None; map.cells.len()];
+    let mut queue = VecDeque::from([start]);
+    distance[start] = Some(0);
+
+    while let Some(index) = queue.pop_front() {
+        let steps = distance[index].unwrap();
+        for next in neighbours(map, index) {
+            if map.cells[next] == FLOOR && distance[next].is_none() {
+                distance[next] = Some(steps + 1);
+                queue.push_back(next);
+            }
+        }
+    }
+
+    distance
+}
+
+fn largest_region(map: &Map) -> Vec<usize> {
+    let mut seen = vec![false; map.cells.len()];
+    let mut largest = Vec::new();
+
+    for start in 0..map.cells.len() {
+        if map.cells[start] != FLOOR || seen[start] {
+            continue;
+        }
+        let region: Vec<usize> = distances(map, start)
+            .iter()
+            .enumerate()
+            .filter_map(|(index, steps)| steps.map(|_| index))
+            .collect();
+        for index in &region {
+            seen[*index] = true;
+        }
+        if region.len() > largest.len() {
+            largest = region;
+        }
+    }
+
+    largest
+}
+
+fn wall_neighbour(map: &Map, index: usize) -> Option<usize> {
+    neighbours(map, index).find(|next| map.cells[*next] != FLOOR)
+}
+
+/// Faces the player along the first open direction out of their cell.
+fn open_direction(map: &Map, index: usize) -> f64 {
+    let (x, y) = ((index % map.width) as i32, (index / map.width) as i32);
+    let directions = [(1, 0), (0, 1), (-1, 0), (0, -1)];
+    directions
+        .iter()
+        .position(|(dx, dy)| map.get(x + dx, y + dy) == Some(FLOOR))
+        .map_or(0.0, |quarter| quarter as f64 * std::f64::consts::FRAC_PI_2)
+}
+
+/// The centre of a cell in map coordinates.
+fn map_point(map: &Map, index: usize) -> (f64, f64) {
+    ((index % map.width) as f64 + 0.5, (index / map.width) as f64 + 0.5)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pushwall::PUSH_DISTANCE;
+    use crate::rng::Stream;
+
+    fn levels() -> impl Iterator<Item = (Level, usize)> {
+        [DungeonMode::Rooms, DungeonMode::Caves].into_iter().flat_map(|mode| {
+            (0..20).map(move |seed| {
+                let config = DungeonConfig { mode, ..DungeonConfig::default() };
+                let level = generate(&config, &mut Rng::new(seed, Stream::Level));
+                let spawn = level.spawn_y as usize * level.map.width + level.spawn_x as usize;
+                (level, spawn)
+            })
+        })
+    }
+
+    #[test]
+    fn border_is_solid() {
+        for (level, _) in levels() {
+            let map = &level.map;
+            for index in 0..map.cells.len() {
+                let (x, y) = (index % map.width, index / map.width);
+                if x == 0 || y == 0 || x == map.width - 1 || y == map.height - 1 {
+                    assert_ne!(map.cells[index], FLOOR);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn every_floor_cell_is_reachable_from_the_spawn() {
+        for (level, spawn) in levels() {
+            // Secret walls slide out of the way, so the closets behind them count too
+            let mut map = level.map;
+            for secret in map.secrets.clone() {
+                map.cells[secret] = FLOOR;
+            }
+            let distance = distances(&map, spawn);
+            assert_eq!(map.cells[spawn], FLOOR);
+            assert!((0..map.cells.len()).all(|index| map.cells[index] != FLOOR || distance[index].is_some()));
+        }
+    }
+
+    #[test]
+    fn secret_walls_slide_clear_of_their_reward() {
+        let directions = [(1, 0), (0, 1), (-1, 0), (0, -1)];
+        let mut secrets = 0;
+        for (level, spawn) in levels() {
+            let distance = distances(&level.map, spawn);
+            for &secret in &level.map.secrets {
+                let (x, y) = ((secret % level.map.width) as i32, (secret / level.map.width) as i32);
+                // Pushed by a player standing on the reachable side, away from them
+                let (dx, dy) = directions
+                    .into_iter()
+                    .find(|(dx, dy)| {
+                        distance[(y - dy) as usize * level.map.width + (x - dx) as usize].is_some()
+                            && level.map.get(x + dx, y + dy) == Some(FLOOR)
+                    })
+                    .unwrap();
+                let occupied = |cell_x: i32, cell_y: i32| {
+                    level.pickups.iter().any(|pickup| (pickup.x as i32, pickup.y as i32) == (cell_x, cell_y))
+                };
+                let mut map = level.map.clone();
+                assert!(map.push(x, y, dx, dy, occupied));
+                assert_eq!(map.pushwall.unwrap().distance, PUSH_DISTANCE);
+                secrets += 1;
+            }
+        }
+        assert!(secrets > 0);
+    }
+
+    #[test]
+    fn tiny_maps_still_have_somewhere_to_stand() {
+        for mode in [DungeonMode::Rooms, DungeonMode::Caves] {
+            for size in 0..6 {
+                let config = DungeonConfig { mode, width: size, height: size, ..DungeonConfig::default() };
+                let level = generate(&config, &mut Rng::new(7, Stream::Level));
+                let map = &level.map;
+                assert_eq!(map.get(level.spawn_x as i32, level.spawn_y as i32), Some(FLOOR));
+            }
+        }
+    }
+}
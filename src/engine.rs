@@ -1,23 +1,73 @@
 use wasm_bindgen::prelude::*;
 use wasm_bindgen::JsCast;
-use web_sys::{window, HtmlCanvasElement, KeyboardEvent};
+use web_sys::{window, Gamepad, GamepadButton, HtmlCanvasElement, KeyboardEvent};
 use std::rc::Rc;
 use std::cell::RefCell;
-use crate::game::MAP;
-use crate::game::MAP_HEIGHT;
-use crate::game::MAP_WIDTH;
+use crate::game::{GLASS_TEXTURE, GRATE_TEXTURE};
 use crate::renderer::Renderer;
-use crate::game::Player;
 use crate::raycasting::render_scene;
+use crate::sector::render_sectors;
 use crate::utils::get_performance;
-use crate::sprites::Sprite;
+use crate::inventory::{InventoryEvent, Item};
+use crate::simulation::{Notice, Simulation, TICKS_PER_SECOND};
+use crate::level::Episode;
+use crate::hud::{Hud, HudLayout};
+use crate::font::{Align, BitmapFont, TextStyle};
+use crate::menu::{Menu, MenuCommand, MenuInput, Screen};
+use crate::settings::{key_name, Action, Settings, KEY_DOWN, KEY_ENTER, KEY_ESCAPE, KEY_LEFT, KEY_RIGHT, KEY_UP};
+use crate::save::{read_slot, write_slot, Persist, SaveReader, SaveWriter};
+use crate::rng::{Rng, Stream};
+use crate::replay::{Playback, Replay, TickInput, WEAPON_SLOTS};
 use crate::console_log;
 pub struct Engine {
-    player: Player,
+    game: Simulation,
     renderer: Renderer,
     keys: Vec<bool>,
     last_frame_time: f64, // Store the last frame's timestamp
-    sprites: Vec<Sprite>
+    /// Real time in milliseconds that has passed but not yet been simulated.
+    unsimulated_time: f64,
+    message: Option<(String, u32)>,
+    hud: Hud,
+    settings: Settings,
+    menu: Menu,
+    // Keys pressed since the last update, in order, for menus and other one-shot input
+    pressed_keys: Vec<usize>,
+    gamepad_buttons: Vec<bool>,
+    /// Input recorded since the current game started. None once a saved game is loaded,
+    /// since a replay can only start from the beginning of a game.
+    replay: Option<Replay>,
+    playback: Option<Playback>,
+    /// Randomness for effects that don't affect the simulation, kept apart from the world's
+    /// gameplay stream so drawing never changes the outcome of a replay.
+    cosmetic_rng: Rng,
+}
+
+/// Standard gamepad buttons and the keys they stand in for in menus.
+const GAMEPAD_KEYS: [(u32, usize); 7] = [
+    (12, KEY_UP),
+    (13, KEY_DOWN),
+    (14, KEY_LEFT),
+    (15, KEY_RIGHT),
+    (0, KEY_ENTER),
+    (1, KEY_ESCAPE),
+    (9, KEY_ESCAPE),
+];
+const TICK_MILLISECONDS: f64 = 1000.0 / TICKS_PER_SECOND as f64;
+/// Most ticks run to catch up in one frame, so a long stall (a hidden tab, say) doesn't make the
+/// game race ahead afterwards.
+const MAX_TICKS_PER_FRAME: u32 = 5;
+/// Ticks a pickup or inventory message stays on screen.
+const MESSAGE_TICKS: u32 = 90;
+const KEY_QUICKSAVE: usize = 116; // F5
+const KEY_QUICKLOAD: usize = 120; // F9
+const QUICKSAVE_SLOT: &str = "quicksave";
+const KEY_SAVE_REPLAY: usize = 118; // F7
+const KEY_PLAY_REPLAY: usize = 119; // F8
+const REPLAY_SLOT: &str = "replay";
+
+/// A seed for a new game, different every time.
+fn new_seed() -> u64 {
+    js_sys::Date::now().to_bits() ^ get_performance().now().to_bits()
 }
 
 impl Engine {
@@ -32,81 +82,387 @@ impl Engine {
 
         let mut renderer = Renderer::new(canvas.clone());
 
-        let texture_ids = ["texture1", "texture2", "texture3", "pillar"];
-        for texture_id in texture_ids.iter() {
-            renderer.load_texture(texture_id);
+        let texture_ids = [
+            "texture1", "texture2", "texture3", "pillar", "rocket", "fireball", "explosion", "barrel", "launcher", "staff",
+            "medkit", "armor", "rocket_ammo", "mana", "key_red", "key_blue", "treasure", "exit",
+            "grate", "glass", "mirror",
+        ];
+        // Walls made of these have gaps or are clear, so rays carry on past them
+        let see_through = [GRATE_TEXTURE, GLASS_TEXTURE];
+        for (index, texture_id) in texture_ids.iter().enumerate() {
+            renderer.load_texture(texture_id, see_through.contains(&(index as u8 + 1)));
         }
 
-        let player = Player::new();
+        let font_page = renderer.load_bitmap("font");
+        // A missing page or broken descriptor shouldn't stop the game: the built-in font still reads
+        renderer.font = match BitmapFont::parse_bmfont(include_str!("../static/font.fnt"), font_page) {
+            Ok(font) if font.page.width > 0 && font.page.height > 0 => font,
+            Ok(_) => {
+                console_log!("Font page has no pixels, using the built-in font instead");
+                BitmapFont::builtin()
+            }
+            Err(error) => {
+                console_log!("Invalid font ({}), using the built-in font instead", error);
+                BitmapFont::builtin()
+            }
+        };
 
-        let sprites = vec![
-            Sprite {x: 3.5, y: 5.5, texture_id: 4, distance: 0.0},
-        ];
+        let layout = HudLayout::parse(include_str!("../static/hud.cfg")).expect("Invalid HUD layout");
+        let hud = Hud::new(layout, &renderer);
+
+        let seed = new_seed();
 
-        let engine = Rc::new(RefCell::new(Engine {
-            player,
+        // The game itself is started by `new_game` below
+        let mut engine = Engine {
+            game: Simulation::new(Episode::Campaign, seed),
             renderer,
             keys: vec![false; 256], 
             last_frame_time: window.performance().unwrap().now(),
-            sprites: sprites
-        }));
+            unsimulated_time: 0.0,
+            message: None,
+            hud,
+            settings: Settings::default(),
+            menu: Menu::new(Screen::Title),
+            pressed_keys: Vec::new(),
+            gamepad_buttons: vec![false; GAMEPAD_KEYS.len()],
+            replay: None,
+            playback: None,
+            cosmetic_rng: Rng::new(seed, Stream::Cosmetic),
+        };
+        engine.new_game(Episode::Campaign, seed);
+        let engine = Rc::new(RefCell::new(engine));
 
         Engine::setup_input(engine.clone());
 
         engine
     }
 
+    fn is_down(&self, action: Action) -> bool {
+        self.keys[self.settings.bindings.key(action)]
+    }
+
+    /// Samples the controls the simulation cares about for this tick.
+    fn read_input(&self) -> TickInput {
+        let mut input = TickInput::default();
+        for action in Action::ALL {
+            input.set_action(action, self.is_down(action));
+        }
+        for slot in 0..WEAPON_SLOTS {
+            input.set_weapon_slot(slot, self.keys[b'1' as usize + slot]);
+        }
+        input
+    }
+
     pub fn update(&mut self) {
-        // Player movement controls
-        if self.keys[b'W' as usize] {
-            self.player.move_forward(0.1);
+        self.poll_gamepad();
+        let pressed_keys = std::mem::take(&mut self.pressed_keys);
+
+        if pressed_keys.contains(&KEY_QUICKLOAD) {
+            self.quickload();
         }
-        if self.keys[b'S' as usize] {
-            self.player.move_backward(0.1);
+
+        // The simulation is paused while a menu is open
+        if self.menu.is_open() {
+            for key_code in pressed_keys {
+                self.handle_menu_key(key_code);
+            }
+            return;
         }
-        if self.keys[b'A' as usize] {
-            self.player.turn_left(0.05);
+        if pressed_keys.contains(&KEY_ESCAPE) {
+            if self.playback.is_some() {
+                self.stop_playback("Replay stopped");
+            } else {
+                self.menu.open(Screen::Pause);
+            }
+            return;
+        }
+        if pressed_keys.contains(&KEY_QUICKSAVE) {
+            self.quicksave();
+        }
+        if pressed_keys.contains(&KEY_SAVE_REPLAY) {
+            self.save_replay();
         }
-        if self.keys[b'D' as usize] {
-            self.player.turn_right(0.05);
+        if pressed_keys.contains(&KEY_PLAY_REPLAY) {
+            self.play_replay();
         }
+
+        // While a replay plays, its recorded input stands in for the keyboard
+        let mut sensitivity = self.settings.sensitivity;
+        let input = match &mut self.playback {
+            Some(playback) => {
+                sensitivity = playback.sensitivity();
+                playback.next_input()
+            }
+            None => None,
+        };
+        let input = input.unwrap_or_else(|| {
+            if self.playback.is_some() {
+                self.stop_playback("Replay finished");
+            }
+            self.read_input()
+        });
+
+        if let Some(replay) = &mut self.replay {
+            replay.record(input);
+        }
+        if self.game.intermission.is_none() {
+            self.hud.tick();
+            if let Some((_, ticks)) = &mut self.message {
+                *ticks -= 1;
+                if *ticks == 0 {
+                    self.message = None;
+                }
+            }
+        }
+        self.game.tick(input, sensitivity);
+        self.handle_notices();
     }
 
-    pub fn render(&mut self) {
-        self.renderer.clear();
-        console_log!("AAAAA");
+    /// Shows what happened in the ticks just run.
+    fn handle_notices(&mut self) {
+        for notice in self.game.drain_notices() {
+            let text = match notice {
+                Notice::Message(text) => text,
+                Notice::Inventory(event) => {
+                    self.hud.handle_event(event);
+                    match event {
+                        InventoryEvent::Collected(Item::Weapon(kind)) => format!("You got the {}!", Item::Weapon(kind).name()),
+                        InventoryEvent::Collected(item) => format!("Picked up {}", item.name()),
+                        InventoryEvent::OutOfAmmo(kind) => format!("Out of {}", Item::Ammo(kind, 0).name()),
+                    }
+                }
+                Notice::EpisodeOver => {
+                    self.menu.open(Screen::Title);
+                    continue;
+                }
+            };
+            self.message = Some((text, MESSAGE_TICKS));
+        }
+    }
+
+    /// Starts a fresh game from `seed`, recording its input from the first tick.
+    pub fn new_game(&mut self, episode: Episode, seed: u64) {
+        self.game = Simulation::new(episode, seed);
+        self.replay = Some(Replay::new(seed, self.settings.sensitivity, episode));
+        self.message = None;
+        self.apply_settings();
+    }
+
+    /// Serializes everything the simulation depends on, so loading it continues the game exactly.
+    pub fn save_game(&self) -> Vec<u8> {
+        let mut writer = SaveWriter::new();
+        self.game.save(&mut writer);
+        writer.finish()
+    }
+
+    /// Restores a game written by `save_game`. Nothing is changed if the data is invalid.
+    pub fn load_game(&mut self, bytes: &[u8]) -> Result<(), String> {
+        let mut reader = SaveReader::new(bytes)?;
+        let game = Simulation::load(&mut reader)?;
+        reader.finish()?;
+
+        self.game = game;
+        self.replay = None;
+        self.playback = None;
+        self.apply_settings();
+        Ok(())
+    }
+
+    fn save_replay(&mut self) {
+        let text = match &self.replay {
+            Some(replay) => match write_slot(REPLAY_SLOT, &replay.to_bytes()) {
+                Ok(()) => format!("Replay saved ({} ticks)", replay.inputs.len()),
+                Err(error) => format!("Saving replay failed: {}", error),
+            },
+            None => "Nothing to save: replays cover a whole game from New Game".to_string(),
+        };
+        self.message = Some((text, MESSAGE_TICKS));
+    }
+
+    /// Restarts the game from the saved replay's seed and plays its input back.
+    fn play_replay(&mut self) {
+        match read_slot(REPLAY_SLOT).and_then(|bytes| Replay::from_bytes(&bytes)) {
+            Ok(replay) => {
+                self.new_game(replay.episode, replay.seed);
+                // Keep recording with the replay's sensitivity, so playing back then carrying on still makes a valid replay
+                self.replay = Some(Replay::new(replay.seed, replay.sensitivity, replay.episode));
+                self.playback = Some(Playback::new(replay));
+                self.message = Some(("Playing replay, Esc to stop".to_string(), MESSAGE_TICKS));
+            }
+            Err(error) => self.message = Some((format!("Loading replay failed: {}", error), MESSAGE_TICKS)),
+        }
+    }
 
+    fn quicksave(&mut self) {
+        let text = match write_slot(QUICKSAVE_SLOT, &self.save_game()) {
+            Ok(()) => "Game saved".to_string(),
+            Err(error) => format!("Save failed: {}", error),
+        };
+        self.message = Some((text, MESSAGE_TICKS));
+    }
+
+    fn quickload(&mut self) {
+        let text = match read_slot(QUICKSAVE_SLOT).and_then(|bytes| self.load_game(&bytes)) {
+            Ok(()) => {
+                self.menu.close();
+                "Game loaded".to_string()
+            }
+            Err(error) => format!("Load failed: {}", error),
+        };
+        self.message = Some((text, MESSAGE_TICKS));
+    }
+
+    /// Hands control back to the player part way through a replay.
+    fn stop_playback(&mut self, text: &str) {
+        self.playback = None;
+        self.message = Some((text.to_string(), MESSAGE_TICKS));
+        self.check_recording();
+    }
+
+    /// Turning depends on sensitivity, so a recording made across a change could not be played back.
+    fn check_recording(&mut self) {
+        let sensitivity = match &self.playback {
+            Some(playback) => playback.sensitivity(),
+            None => self.settings.sensitivity,
+        };
+        if self.replay.as_ref().is_some_and(|replay| replay.sensitivity != sensitivity) {
+            self.replay = None;
+        }
+    }
+
+    fn apply_settings(&mut self) {
+        self.check_recording();
+        self.game.fov = self.settings.fov_degrees.to_radians();
+        self.game.player.fov = self.game.fov;
+        self.renderer.set_resolution_scale(self.settings.resolution_scale);
+        self.renderer.texture_filter = self.settings.texture_filter;
+    }
+
+    fn handle_menu_key(&mut self, key_code: usize) {
+        let command = if self.menu.is_rebinding() {
+            self.menu.rebind(key_code, &mut self.settings)
+        } else {
+            MenuInput::from_key(key_code).and_then(|input| self.menu.handle_input(input, &mut self.settings))
+        };
+
+        match command {
+            Some(MenuCommand::NewGame(episode)) => {
+                self.playback = None;
+                self.new_game(episode, new_seed());
+            }
+            Some(MenuCommand::QuitToTitle) => {
+                self.playback = None;
+                self.new_game(self.game.episode, new_seed());
+            }
+            Some(MenuCommand::SaveGame) => self.quicksave(),
+            Some(MenuCommand::LoadGame) => self.quickload(),
+            Some(MenuCommand::SettingsChanged) => self.apply_settings(),
+            Some(MenuCommand::Continue) | None => {}
+        }
+    }
+
+    /// Turns newly pressed buttons on the first connected gamepad into key presses.
+    fn poll_gamepad(&mut self) {
+        let Ok(gamepads) = window().unwrap().navigator().get_gamepads() else {
+            return;
+        };
+        let Some(gamepad) = gamepads.iter().find_map(|gamepad| gamepad.dyn_into::<Gamepad>().ok()) else {
+            return;
+        };
+
+        let buttons = gamepad.buttons();
+        for (index, (button, key_code)) in GAMEPAD_KEYS.iter().enumerate() {
+            let pressed = buttons
+                .get(*button)
+                .dyn_into::<GamepadButton>()
+                .map(|button| button.pressed())
+                .unwrap_or(false);
+            if pressed && !self.gamepad_buttons[index] {
+                self.pressed_keys.push(*key_code);
+            }
+            self.gamepad_buttons[index] = pressed;
+        }
+    }
+
+    /// Runs as many ticks as real time has moved on by since the last frame, then draws once.
+    fn frame(&mut self) {
         let current_time = get_performance().now();
         let delta_time = current_time - self.last_frame_time;
         self.last_frame_time = current_time;
-        self.sort_sprites();
+        self.unsimulated_time = (self.unsimulated_time + delta_time).min(MAX_TICKS_PER_FRAME as f64 * TICK_MILLISECONDS);
+        while self.unsimulated_time >= TICK_MILLISECONDS {
+            self.update();
+            self.unsimulated_time -= TICK_MILLISECONDS;
+        }
+        self.render(delta_time);
+    }
 
-        render_scene(&self.player, &mut self.renderer, &mut self.sprites);
+    /// Draws a frame. `delta_time` is the time since the last one in milliseconds, for the FPS counter.
+    pub fn render(&mut self, delta_time: f64) {
+        self.renderer.clear();
 
-        self.renderer.draw_minimap(
-            &MAP,               
-            MAP_WIDTH,
-            MAP_HEIGHT,
-            self.player.x,
-            self.player.y,
-            self.player.direction,
-        );
+        let mut sprites = self.game.world.sprites();
+        sprites.extend_from_slice(&self.game.sprites);
+        match &self.game.map.sectors {
+            Some(sectors) => render_sectors(sectors, &self.game.player, &mut self.renderer, &mut sprites),
+            None => render_scene(&self.game.map, &self.game.player, &mut self.renderer, &mut sprites),
+        }
+        if !self.game.player.is_dead() {
+            let horizon_shift = self.game.player.horizon(self.renderer.screen_height as f64) - self.renderer.screen_height as f64 / 2.0;
+            self.game.weapon.draw(&mut self.renderer, &mut self.cosmetic_rng, horizon_shift);
+        }
+        self.hud.draw(&mut self.renderer, &self.game.player, self.game.weapon.kind);
+
+        let screen_width = self.renderer.screen_width as i32;
+        let screen_height = self.renderer.screen_height as i32;
+        // Keep text the same size on screen whatever the resolution scale
+        let text_scale = (screen_height / 300).max(1);
 
         let frame_count = 0;
         if frame_count % 10 == 0 {
             let fps = 1000.0 / delta_time;
-            self.renderer.draw_text(10.0, 20.0, &format!("FPS: {}", fps as i32));
+            let style = TextStyle { align: Align::Right, scale: text_scale, ..TextStyle::default() };
+            self.renderer.draw_text(screen_width - 10, 10, &format!("FPS: {}", fps as i32), &style);
         }    
 
-    }
+        if self.game.player.is_dead() {
+            let style = TextStyle { color: (220, 40, 30), align: Align::Center, scale: text_scale * 3 / 2, ..TextStyle::default() };
+            let text = format!("You died\nPress {} to restart", key_name(self.settings.bindings.key(Action::Use)));
+            self.renderer.draw_text(screen_width / 2, screen_height / 3, &text, &style);
+        }
 
-    fn sort_sprites(&mut self) {
-        for sprite in &mut self.sprites {
-            sprite.distance = ((self.player.x - sprite.x).powi(2) + (self.player.y - sprite.y).powi(2)).sqrt();
-            console_log!("Sprite Position: ({}, {}), Distance: {}, Texture ID: {}", sprite.x, sprite.y, sprite.distance, sprite.texture_id);
+        if let Some((text, _)) = &self.message {
+            let style = TextStyle {
+                color: (240, 220, 120),
+                align: Align::Center,
+                scale: text_scale,
+                max_width: Some(screen_width / 2),
+            };
+            self.renderer.draw_text(screen_width / 2, 10, text, &style);
+        }
 
+        if let Some(intermission) = &self.game.intermission {
+            let level_name = self.game.level_source().name();
+            let key = key_name(self.settings.bindings.key(Action::Use));
+            intermission.draw(&mut self.renderer, level_name, &key);
         }
-        self.sprites.sort_by(|a, b| b.distance.partial_cmp(&a.distance).unwrap());
+
+        self.menu.draw(&mut self.renderer, &self.settings);
+        self.renderer.flush();
+
+        if self.menu.is_open() {
+            return;
+        }
+
+        let storey = self.game.map.storey(self.game.player.storey);
+        self.renderer.draw_minimap(
+            &storey.cells,
+            storey.width,
+            storey.height,
+            self.game.player.x,
+            self.game.player.y,
+            self.game.player.direction,
+        );
     }
 
     fn setup_input(engine: Rc<RefCell<Self>>) {
@@ -117,6 +473,9 @@ impl Engine {
             let key_code = event.key_code() as usize;
             if key_code < engine.keys.len() {
                 engine.keys[key_code] = true;
+                if !event.repeat() {
+                    engine.pressed_keys.push(key_code);
+                }
             }
         }) as Box<dyn FnMut(_)>);
     
@@ -146,28 +505,23 @@ impl Engine {
     }
 }
 
-static mut ENGINE: Option<Rc<RefCell<Engine>>> = None;
-
 #[wasm_bindgen]
 pub fn run() {
-    unsafe {
-        ENGINE = Some(Engine::new());
-        let engine = ENGINE.as_ref().unwrap();
-        Engine::start(Rc::clone(engine));
-    }
+    // The frame closure keeps the engine alive for the lifetime of the page
+    Engine::start(Engine::new());
 }
 
+type FrameCallback = Rc<RefCell<Option<Closure<dyn FnMut()>>>>;
+
 impl Engine {
     pub fn start(engine: Rc<RefCell<Self>>) {
         console_log!("Hello from Rust!");
 
-        let f: Rc<RefCell<Option<Closure<dyn FnMut()>>>> = Rc::new(RefCell::new(None));
+        let f: FrameCallback = Rc::new(RefCell::new(None));
         let g = f.clone();
 
         *g.borrow_mut() = Some(Closure::wrap(Box::new(move || {
-            let mut engine = engine.borrow_mut();
-            engine.update();
-            engine.render();
+            engine.borrow_mut().frame();
 
             // Schedule the next frame
             window()
@@ -0,0 +1,403 @@
+use crate::game::{Map, Player, PLAYER_RADIUS};
+use crate::level::LevelStats;
+use crate::pickups::{Pickup, PICKUP_RADIUS};
+use crate::rng::Rng;
+use crate::sprites::Sprite;
+
+// Texture ids as loaded by `Engine::new` (1-based, like the map)
+pub const ROCKET_TEXTURE: usize = 5;
+pub const FIREBALL_TEXTURE: usize = 6;
+pub const EXPLOSION_TEXTURE: usize = 7;
+pub const BARREL_TEXTURE: usize = 8;
+
+const EXPLOSION_TICKS: u32 = 12;
+const BARREL_SPLASH_RADIUS: f64 = 1.5;
+const BARREL_SPLASH_DAMAGE: f64 = 60.0;
+/// Direct hits deal between this fraction and its inverse of a projectile's listed damage.
+const DAMAGE_SPREAD: f64 = 0.75;
+/// Half-angle of the cone in front of the attacker that a melee attack reaches.
+const MELEE_ARC: f64 = 0.6;
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum ProjectileKind {
+    Rocket,
+    Fireball,
+}
+
+impl ProjectileKind {
+    /// Distance travelled per tick, in map units.
+    pub fn speed(self) -> f64 {
+        match self {
+            ProjectileKind::Rocket => 0.25,
+            ProjectileKind::Fireball => 0.15,
+        }
+    }
+
+    pub fn radius(self) -> f64 {
+        match self {
+            ProjectileKind::Rocket => 0.1,
+            ProjectileKind::Fireball => 0.15,
+        }
+    }
+
+    /// Damage dealt to an entity struck directly.
+    pub fn damage(self) -> f64 {
+        match self {
+            ProjectileKind::Rocket => 20.0,
+            ProjectileKind::Fireball => 15.0,
+        }
+    }
+
+    pub fn splash_radius(self) -> f64 {
+        match self {
+            ProjectileKind::Rocket => 1.5,
+            ProjectileKind::Fireball => 0.8,
+        }
+    }
+
+    /// Damage at the centre of the explosion, falling off linearly to zero at the splash radius.
+    pub fn splash_damage(self) -> f64 {
+        match self {
+            ProjectileKind::Rocket => 40.0,
+            ProjectileKind::Fireball => 10.0,
+        }
+    }
+
+    pub fn texture_id(self) -> usize {
+        match self {
+            ProjectileKind::Rocket => ROCKET_TEXTURE,
+            ProjectileKind::Fireball => FIREBALL_TEXTURE,
+        }
+    }
+}
+
+pub struct Projectile {
+    pub x: f64,
+    pub y: f64,
+    /// The storey it was fired on. It flies level, so it stays there until it hits something.
+    pub storey: usize,
+    pub direction: f64,
+    pub kind: ProjectileKind,
+}
+
+/// A damageable object in the world that projectiles collide with.
+pub struct Entity {
+    pub x: f64,
+    pub y: f64,
+    pub storey: usize,
+    pub radius: f64,
+    pub health: f64,
+    pub texture_id: usize,
+    pub explosive: bool,
+    /// Whether destroying it counts towards the level's kills. Scenery like barrels doesn't.
+    pub counts_as_kill: bool,
+}
+
+impl Entity {
+    pub fn barrel(x: f64, y: f64) -> Self {
+        Entity {
+            x,
+            y,
+            storey: 0,
+            radius: 0.25,
+            health: 20.0,
+            texture_id: BARREL_TEXTURE,
+            explosive: true,
+            counts_as_kill: false,
+        }
+    }
+}
+
+pub struct Explosion {
+    pub x: f64,
+    pub y: f64,
+    pub storey: usize,
+    pub ticks_left: u32,
+}
+
+struct Impact {
+    x: f64,
+    y: f64,
+    storey: usize,
+    kind: ProjectileKind,
+    entity: Option<usize>,
+}
+
+pub struct World {
+    pub entities: Vec<Entity>,
+    pub projectiles: Vec<Projectile>,
+    pub explosions: Vec<Explosion>,
+    pub pickups: Vec<Pickup>,
+    /// Drives every random outcome in the simulation, so a run is reproducible from its seed.
+    pub rng: Rng,
+    pub stats: LevelStats,
+}
+
+impl World {
+    pub fn new(entities: Vec<Entity>, pickups: Vec<Pickup>, rng: Rng) -> Self {
+        let stats = LevelStats {
+            total_kills: entities.iter().filter(|entity| entity.counts_as_kill).count() as u32,
+            total_items: pickups.len() as u32,
+            ..LevelStats::default()
+        };
+        World {
+            entities,
+            projectiles: Vec::new(),
+            explosions: Vec::new(),
+            pickups,
+            rng,
+            stats,
+        }
+    }
+
+    pub fn spawn_projectile(&mut self, kind: ProjectileKind, x: f64, y: f64, storey: usize, direction: f64) {
+        self.projectiles.push(Projectile { x, y, storey, direction, kind });
+    }
+
+    /// Advances projectiles and explosions by one tick.
+    pub fn update(&mut self, player: &mut Player, map: &Map) {
+        self.stats.ticks += 1;
+        for explosion in &mut self.explosions {
+            explosion.ticks_left -= 1;
+        }
+        self.explosions.retain(|explosion| explosion.ticks_left > 0);
+
+        let mut impacts = Vec::new();
+        self.projectiles.retain_mut(|projectile| match projectile.advance(&self.entities, map) {
+            Some(impact) => {
+                impacts.push(impact);
+                false
+            }
+            None => true,
+        });
+
+        for impact in impacts {
+            if let Some(index) = impact.entity {
+                let roll = self.rng.range(DAMAGE_SPREAD, 1.0 / DAMAGE_SPREAD);
+                self.entities[index].health -= impact.kind.damage() * roll;
+            }
+            let (radius, damage) = (impact.kind.splash_radius(), impact.kind.splash_damage());
+            self.explode(impact.x, impact.y, impact.storey, radius, damage, player);
+        }
+
+        self.remove_dead_entities(player);
+
+        if !player.is_dead() {
+            self.collect_pickups(player);
+        }
+    }
+
+    /// Hands every pickup the player is standing on to the player, removing those they took. Only
+    /// pickups on the player's storey are within reach.
+    fn collect_pickups(&mut self, player: &mut Player) {
+        let reach = (PLAYER_RADIUS + PICKUP_RADIUS).powi(2);
+        let count = self.pickups.len();
+        self.pickups.retain(|pickup| {
+            let overlapping = pickup.storey == player.storey
+                && (pickup.x - player.x).powi(2) + (pickup.y - player.y).powi(2) <= reach;
+            !(overlapping && player.give(pickup.item))
+        });
+        self.stats.items += (count - self.pickups.len()) as u32;
+    }
+
+    /// Spawns an explosion and applies radial damage to every entity, and the player, within `radius`
+    /// on the same storey. Floors keep the blast from reaching the storeys above and below.
+    pub fn explode(&mut self, x: f64, y: f64, storey: usize, radius: f64, damage: f64, player: &mut Player) {
+        self.explosions.push(Explosion { x, y, storey, ticks_left: EXPLOSION_TICKS });
+
+        let falloff = |target_x: f64, target_y: f64, target_radius: f64| {
+            let distance = (((target_x - x).powi(2) + (target_y - y).powi(2)).sqrt() - target_radius).max(0.0);
+            if distance < radius {
+                damage * (1.0 - distance / radius)
+            } else {
+                0.0
+            }
+        };
+
+        for entity in self.entities.iter_mut().filter(|entity| entity.storey == storey) {
+            entity.health -= falloff(entity.x, entity.y, entity.radius);
+        }
+        if player.storey == storey {
+            player.damage(falloff(player.x, player.y, PLAYER_RADIUS));
+        }
+    }
+
+    /// True if the player, or an entity or pickup on their storey, overlaps the cell at (x, y).
+    pub fn occupies(&self, player: &Player, x: i32, y: i32) -> bool {
+        let overlaps = |target_x: f64, target_y: f64, radius: f64| {
+            let nearest_x = target_x.clamp(x as f64, x as f64 + 1.0);
+            let nearest_y = target_y.clamp(y as f64, y as f64 + 1.0);
+            (target_x - nearest_x).powi(2) + (target_y - nearest_y).powi(2) < radius * radius
+        };
+        overlaps(player.x, player.y, PLAYER_RADIUS)
+            || self.entities.iter().any(|entity| entity.storey == player.storey && overlaps(entity.x, entity.y, entity.radius))
+            || self.pickups.iter().any(|pickup| pickup.storey == player.storey && overlaps(pickup.x, pickup.y, PICKUP_RADIUS))
+    }
+
+    /// Damages every entity on `storey` within `range` of (x, y) inside a cone facing `direction`.
+    pub fn melee(&mut self, x: f64, y: f64, storey: usize, direction: f64, range: f64, damage: f64) {
+        for entity in self.entities.iter_mut().filter(|entity| entity.storey == storey) {
+            let dx = entity.x - x;
+            let dy = entity.y - y;
+            if (dx * dx + dy * dy).sqrt() - entity.radius > range {
+                continue;
+            }
+
+            let angle = (dy.atan2(dx) - direction + std::f64::consts::PI).rem_euclid(std::f64::consts::TAU) - std::f64::consts::PI;
+            if angle.abs() <= MELEE_ARC {
+                entity.health -= damage;
+            }
+        }
+    }
+
+    fn remove_dead_entities(&mut self, player: &mut Player) {
+        // Explosive entities can kill their neighbours, so keep going until nothing else dies
+        while let Some(index) = self.entities.iter().position(|entity| entity.health <= 0.0) {
+            let entity = self.entities.remove(index);
+            if entity.counts_as_kill {
+                self.stats.kills += 1;
+            }
+            if entity.explosive {
+                self.explode(entity.x, entity.y, entity.storey, BARREL_SPLASH_RADIUS, BARREL_SPLASH_DAMAGE, player);
+            }
+        }
+    }
+
+    pub fn sprites(&self) -> Vec<Sprite> {
+        let entities = self.entities.iter().map(|entity| (entity.x, entity.y, entity.storey, entity.texture_id));
+        let projectiles = self
+            .projectiles
+            .iter()
+            .map(|projectile| (projectile.x, projectile.y, projectile.storey, projectile.kind.texture_id()));
+        let explosions = self.explosions.iter().map(|explosion| (explosion.x, explosion.y, explosion.storey, EXPLOSION_TEXTURE));
+        let pickups = self.pickups.iter().map(|pickup| (pickup.x, pickup.y, pickup.storey, pickup.texture_id()));
+
+        entities
+            .chain(projectiles)
+            .chain(explosions)
+            .chain(pickups)
+            .map(|(x, y, storey, texture_id)| Sprite { x, y, storey, texture_id, distance: 0.0 })
+            .collect()
+    }
+}
+
+impl Projectile {
+    /// Moves the projectile one tick along its heading, returning the impact if it struck a wall or
+    /// entity. A hole in the floor stops it like a wall, since it can't leave its storey.
+    fn advance(&mut self, entities: &[Entity], map: &Map) -> Option<Impact> {
+        let map = map.storey(self.storey);
+        let radius = self.kind.radius();
+        // Sub-step so fast projectiles cannot tunnel through thin obstacles
+        let steps = (self.kind.speed() / radius).ceil().max(1.0) as usize;
+        let step = self.kind.speed() / steps as f64;
+        let (dx, dy) = (self.direction.cos() * step, self.direction.sin() * step);
+
+        for _ in 0..steps {
+            let new_x = self.x + dx;
+            let new_y = self.y + dy;
+
+            if map.is_wall(new_x, new_y) {
+                // Explode just in front of the wall rather than inside it
+                return Some(Impact { x: self.x, y: self.y, storey: self.storey, kind: self.kind, entity: None });
+            }
+
+            self.x = new_x;
+            self.y = new_y;
+
+            let hit = entities.iter().position(|entity| {
+                entity.storey == self.storey
+                    && (entity.x - self.x).powi(2) + (entity.y - self.y).powi(2) <= (entity.radius + radius).powi(2)
+            });
+            if hit.is_some() {
+                return Some(Impact { x: self.x, y: self.y, storey: self.storey, kind: self.kind, entity: hit });
+            }
+        }
+
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::game::MAX_HEALTH;
+    use crate::inventory::Item;
+    use crate::rng::Stream;
+
+    fn world(entities: Vec<Entity>, pickups: Vec<Pickup>) -> World {
+        World::new(entities, pickups, Rng::new(1, Stream::Gameplay))
+    }
+
+    #[test]
+    fn explosions_stay_on_their_storey() {
+        let mut world = world(vec![Entity::barrel(5.5, 5.5), Entity { storey: 1, ..Entity::barrel(5.5, 5.5) }], Vec::new());
+        let mut player = Player::new();
+        (player.x, player.y) = (5.0, 5.5);
+
+        world.explode(5.5, 5.5, 1, 1.5, 10.0, &mut player);
+        assert_eq!(player.health, MAX_HEALTH);
+        assert_eq!(world.entities[0].health, 20.0);
+        assert!(world.entities[1].health < 20.0);
+    }
+
+    #[test]
+    fn barrels_dont_count_as_kills() {
+        let enemy = Entity { explosive: false, counts_as_kill: true, ..Entity::barrel(2.5, 2.5) };
+        let mut world = world(vec![Entity::barrel(5.5, 5.5), enemy], Vec::new());
+        assert_eq!(world.stats.total_kills, 1);
+
+        for entity in &mut world.entities {
+            entity.health = 0.0;
+        }
+        world.remove_dead_entities(&mut Player::new());
+        assert!(world.entities.is_empty());
+        assert_eq!(world.stats.kills, 1);
+    }
+
+    #[test]
+    fn projectiles_only_hit_their_storey() {
+        let map = Map::new();
+        let mut player = Player::new();
+        for (storey, hits) in [(0, true), (1, false)] {
+            let mut world = world(vec![Entity::barrel(5.5, 1.5)], Vec::new());
+            world.spawn_projectile(ProjectileKind::Rocket, 4.2, 1.5, storey, 0.0);
+            for _ in 0..60 {
+                world.update(&mut player, &map);
+            }
+            assert!(world.projectiles.is_empty());
+            assert_eq!(world.entities.is_empty(), hits);
+        }
+    }
+
+    #[test]
+    fn occupied_cells_include_the_edges_of_things() {
+        let world = world(vec![Entity::barrel(5.5, 5.5)], vec![Pickup::new(2.5, 2.9, Item::Treasure(100))]);
+        let mut player = Player::new();
+        (player.x, player.y) = (1.1, 6.5);
+
+        assert!(world.occupies(&player, 5, 5));
+        assert!(!world.occupies(&player, 6, 5));
+        // The pickup reaches over into the cell south of it, and the player into the one west
+        assert!(world.occupies(&player, 2, 2));
+        assert!(world.occupies(&player, 2, 3));
+        assert!(world.occupies(&player, 0, 6));
+        player.storey = 1;
+        assert!(!world.occupies(&player, 5, 5));
+    }
+
+    #[test]
+    fn pickups_are_only_collected_on_their_storey() {
+        let map = Map::new();
+        let pickups = vec![Pickup { storey: 1, ..Pickup::new(1.5, 1.5, Item::Treasure(100)) }];
+        let mut world = world(Vec::new(), pickups);
+        let mut player = Player::new();
+        (player.x, player.y) = (1.5, 1.5);
+
+        world.update(&mut player, &map);
+        assert_eq!(world.pickups.len(), 1);
+        player.storey = 1;
+        world.update(&mut player, &map);
+        assert!(world.pickups.is_empty());
+        assert_eq!(player.inventory.score, 100);
+    }
+}
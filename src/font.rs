@@ -0,0 +1,404 @@
+use std::collections::HashMap;
+
+use crate::renderer::{Bitmap, Rect};
+
+const BUILTIN_GLYPH_WIDTH: i32 = 5;
+const BUILTIN_GLYPH_HEIGHT: i32 = 7;
+const BUILTIN_COLUMNS: i32 = 16;
+
+/// The built-in 5x7 glyphs, one byte per row with the leftmost pixel in bit 4.
+/// Lowercase letters reuse the uppercase shapes.
+const BUILTIN_GLYPHS: &[(char, [u8; 7])] = &[
+    (' ', [0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00]),
+    ('!', [0x04, 0x04, 0x04, 0x04, 0x04, 0x00, 0x04]),
+    ('"', [0x0A, 0x0A, 0x00, 0x00, 0x00, 0x00, 0x00]),
+    ('#', [0x0A, 0x0A, 0x1F, 0x0A, 0x1F, 0x0A, 0x0A]),
+    ('$', [0x04, 0x0F, 0x14, 0x0E, 0x05, 0x1E, 0x04]),
+    ('%', [0x18, 0x19, 0x02, 0x04, 0x08, 0x13, 0x03]),
+    ('&', [0x0C, 0x12, 0x14, 0x08, 0x15, 0x12, 0x0D]),
+    ('\'', [0x04, 0x04, 0x00, 0x00, 0x00, 0x00, 0x00]),
+    ('(', [0x02, 0x04, 0x08, 0x08, 0x08, 0x04, 0x02]),
+    (')', [0x08, 0x04, 0x02, 0x02, 0x02, 0x04, 0x08]),
+    ('*', [0x00, 0x04, 0x15, 0x0E, 0x15, 0x04, 0x00]),
+    ('+', [0x00, 0x04, 0x04, 0x1F, 0x04, 0x04, 0x00]),
+    (',', [0x00, 0x00, 0x00, 0x00, 0x0C, 0x04, 0x08]),
+    ('-', [0x00, 0x00, 0x00, 0x1F, 0x00, 0x00, 0x00]),
+    ('.', [0x00, 0x00, 0x00, 0x00, 0x00, 0x0C, 0x0C]),
+    ('/', [0x00, 0x01, 0x02, 0x04, 0x08, 0x10, 0x00]),
+    ('0', [0x0E, 0x11, 0x13, 0x15, 0x19, 0x11, 0x0E]),
+    ('1', [0x04, 0x0C, 0x04, 0x04, 0x04, 0x04, 0x0E]),
+    ('2', [0x0E, 0x11, 0x01, 0x02, 0x04, 0x08, 0x1F]),
+    ('3', [0x1F, 0x02, 0x04, 0x02, 0x01, 0x11, 0x0E]),
+    ('4', [0x02, 0x06, 0x0A, 0x12, 0x1F, 0x02, 0x02]),
+    ('5', [0x1F, 0x10, 0x1E, 0x01, 0x01, 0x11, 0x0E]),
+    ('6', [0x06, 0x08, 0x10, 0x1E, 0x11, 0x11, 0x0E]),
+    ('7', [0x1F, 0x01, 0x02, 0x04, 0x08, 0x08, 0x08]),
+    ('8', [0x0E, 0x11, 0x11, 0x0E, 0x11, 0x11, 0x0E]),
+    ('9', [0x0E, 0x11, 0x11, 0x0F, 0x01, 0x02, 0x0C]),
+    (':', [0x00, 0x0C, 0x0C, 0x00, 0x0C, 0x0C, 0x00]),
+    (';', [0x00, 0x0C, 0x0C, 0x00, 0x0C, 0x04, 0x08]),
+    ('<', [0x02, 0x04, 0x08, 0x10, 0x08, 0x04, 0x02]),
+    ('=', [0x00, 0x00, 0x1F, 0x00, 0x1F, 0x00, 0x00]),
+    ('>', [0x08, 0x04, 0x02, 0x01, 0x02, 0x04, 0x08]),
+    ('?', [0x0E, 0x11, 0x01, 0x02, 0x04, 0x00, 0x04]),
+    ('@', [0x0E, 0x11, 0x01, 0x0D, 0x15, 0x15, 0x0E]),
+    ('A', [0x0E, 0x11, 0x11, 0x1F, 0x11, 0x11, 0x11]),
+    ('B', [0x1E, 0x11, 0x11, 0x1E, 0x11, 0x11, 0x1E]),
+    ('C', [0x0E, 0x11, 0x10, 0x10, 0x10, 0x11, 0x0E]),
+    ('D', [0x1C, 0x12, 0x11, 0x11, 0x11, 0x12, 0x1C]),
+    ('E', [0x1F, 0x10, 0x10, 0x1E, 0x10, 0x10, 0x1F]),
+    ('F', [0x1F, 0x10, 0x10, 0x1E, 0x10, 0x10, 0x10]),
+    ('G', [0x0E, 0x11, 0x10, 0x17, 0x11, 0x11, 0x0F]),
+    ('H', [0x11, 0x11, 0x11, 0x1F, 0x11, 0x11, 0x11]),
+    ('I', [0x0E, 0x04, 0x04, 0x04, 0x04, 0x04, 0x0E]),
+    ('J', [0x07, 0x02, 0x02, 0x02, 0x02, 0x12, 0x0C]),
+    ('K', [0x11, 0x12, 0x14, 0x18, 0x14, 0x12, 0x11]),
+    ('L', [0x10, 0x10, 0x10, 0x10, 0x10, 0x10, 0x1F]),
+    ('M', [0x11, 0x1B, 0x15, 0x15, 0x11, 0x11, 0x11]),
+    ('N', [0x11, 0x11, 0x19, 0x15, 0x13, 0x11, 0x11]),
+    ('O', [0x0E, 0x11, 0x11, 0x11, 0x11, 0x11, 0x0E]),
+    ('P', [0x1E, 0x11, 0x11, 0x1E, 0x10, 0x10, 0x10]),
+    ('Q', [0x0E, 0x11, 0x11, 0x11, 0x15, 0x12, 0x0D]),
+    ('R', [0x1E, 0x11, 0x11, 0x1E, 0x14, 0x12, 0x11]),
+    ('S', [0x0F, 0x10, 0x10, 0x0E, 0x01, 0x01, 0x1E]),
+    ('T', [0x1F, 0x04, 0x04, 0x04, 0x04, 0x04, 0x04]),
+    ('U', [0x11, 0x11, 0x11, 0x11, 0x11, 0x11, 0x0E]),
+    ('V', [0x11, 0x11, 0x11, 0x11, 0x11, 0x0A, 0x04]),
+    ('W', [0x11, 0x11, 0x11, 0x15, 0x15, 0x15, 0x0A]),
+    ('X', [0x11, 0x11, 0x0A, 0x04, 0x0A, 0x11, 0x11]),
+    ('Y', [0x11, 0x11, 0x0A, 0x04, 0x04, 0x04, 0x04]),
+    ('Z', [0x1F, 0x01, 0x02, 0x04, 0x08, 0x10, 0x1F]),
+    ('[', [0x0E, 0x08, 0x08, 0x08, 0x08, 0x08, 0x0E]),
+    ('\\', [0x00, 0x10, 0x08, 0x04, 0x02, 0x01, 0x00]),
+    (']', [0x0E, 0x02, 0x02, 0x02, 0x02, 0x02, 0x0E]),
+    ('^', [0x04, 0x0A, 0x11, 0x00, 0x00, 0x00, 0x00]),
+    ('_', [0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x1F]),
+    ('`', [0x08, 0x04, 0x00, 0x00, 0x00, 0x00, 0x00]),
+    ('{', [0x02, 0x04, 0x04, 0x08, 0x04, 0x04, 0x02]),
+    ('|', [0x04, 0x04, 0x04, 0x04, 0x04, 0x04, 0x04]),
+    ('}', [0x08, 0x04, 0x04, 0x02, 0x04, 0x04, 0x08]),
+    ('~', [0x00, 0x00, 0x08, 0x15, 0x02, 0x00, 0x00]),
+];
+
+/// Where a character lives in the font page and how it is positioned, as in a BMFont `char` line.
+#[derive(Clone, Copy)]
+pub struct Glyph {
+    pub x: i32,
+    pub y: i32,
+    pub width: i32,
+    pub height: i32,
+    pub x_offset: i32,
+    pub y_offset: i32,
+    pub x_advance: i32,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Align {
+    Left,
+    Center,
+    Right,
+}
+
+#[derive(Clone, Copy)]
+pub struct TextStyle {
+    pub color: (u8, u8, u8),
+    pub align: Align,
+    /// Integer pixel scale applied to every glyph.
+    pub scale: i32,
+    /// Wrap lines at word boundaries so none is wider than this many screen pixels.
+    pub max_width: Option<i32>,
+}
+
+impl Default for TextStyle {
+    fn default() -> Self {
+        TextStyle {
+            color: (255, 255, 255),
+            align: Align::Left,
+            scale: 1,
+            max_width: None,
+        }
+    }
+}
+
+/// A bitmap font with a single page of glyphs. Glyph pixels are tinted by the text colour,
+/// so white glyphs take on the colour and black ones (such as shadows) stay black.
+pub struct BitmapFont {
+    pub line_height: i32,
+    pub glyphs: HashMap<char, Glyph>,
+    pub kerning: HashMap<(char, char), i32>,
+    pub page: Bitmap,
+}
+
+impl BitmapFont {
+    /// Builds the font compiled into the engine, which needs no assets to be loaded.
+    pub fn builtin() -> Self {
+        let cell_width = BUILTIN_GLYPH_WIDTH + 1;
+        let cell_height = BUILTIN_GLYPH_HEIGHT + 1;
+        let rows = (BUILTIN_GLYPHS.len() as i32 + BUILTIN_COLUMNS - 1) / BUILTIN_COLUMNS;
+        let width = (BUILTIN_COLUMNS * cell_width) as usize;
+        let height = (rows * cell_height) as usize;
+        let mut pixels = vec![0; width * height * 4];
+        let mut glyphs = HashMap::new();
+
+        for (index, (character, bits)) in BUILTIN_GLYPHS.iter().enumerate() {
+            let x = index as i32 % BUILTIN_COLUMNS * cell_width;
+            let y = index as i32 / BUILTIN_COLUMNS * cell_height;
+
+            for (row, bits) in bits.iter().enumerate() {
+                for column in 0..BUILTIN_GLYPH_WIDTH {
+                    if bits & (1 << (BUILTIN_GLYPH_WIDTH - 1 - column)) != 0 {
+                        let pixel = ((y as usize + row) * width + (x + column) as usize) * 4;
+                        pixels[pixel..pixel + 4].copy_from_slice(&[255, 255, 255, 255]);
+                    }
+                }
+            }
+
+            let glyph = Glyph {
+                x,
+                y,
+                width: BUILTIN_GLYPH_WIDTH,
+                height: BUILTIN_GLYPH_HEIGHT,
+                x_offset: 0,
+                y_offset: 0,
+                x_advance: cell_width,
+            };
+            glyphs.insert(*character, glyph);
+            if character.is_ascii_uppercase() {
+                glyphs.insert(character.to_ascii_lowercase(), glyph);
+            }
+        }
+
+        BitmapFont {
+            line_height: cell_height + 1,
+            glyphs,
+            kerning: HashMap::new(),
+            page: Bitmap { width, height, pixels },
+        }
+    }
+
+    /// Parses an AngelCode BMFont text descriptor. Only single-page fonts are supported,
+    /// and `page` must be the image named by its `page id=0` line.
+    pub fn parse_bmfont(descriptor: &str, page: Bitmap) -> Result<Self, String> {
+        let mut line_height = None;
+        let mut glyphs = HashMap::new();
+        let mut kerning = HashMap::new();
+
+        for (line_number, line) in descriptor.lines().enumerate() {
+            let mut words = line.split_whitespace();
+            let Some(tag) = words.next() else {
+                continue;
+            };
+
+            let attributes: HashMap<&str, &str> = words.filter_map(|word| word.split_once('=')).collect();
+            let int = |name: &str| -> Result<i32, String> {
+                attributes
+                    .get(name)
+                    .and_then(|value| value.parse().ok())
+                    .ok_or_else(|| format!("font line {}: missing or invalid `{}`", line_number + 1, name))
+            };
+            let character = |name: &str| -> Result<char, String> {
+                char::from_u32(int(name)? as u32).ok_or_else(|| format!("font line {}: invalid character", line_number + 1))
+            };
+
+            match tag {
+                "common" => {
+                    if int("pages")? != 1 {
+                        return Err("only single-page fonts are supported".to_string());
+                    }
+                    line_height = Some(int("lineHeight")?);
+                }
+                "char" => {
+                    let glyph = Glyph {
+                        x: int("x")?,
+                        y: int("y")?,
+                        width: int("width")?,
+                        height: int("height")?,
+                        x_offset: int("xoffset")?,
+                        y_offset: int("yoffset")?,
+                        x_advance: int("xadvance")?,
+                    };
+                    let negative = [glyph.x, glyph.y, glyph.width, glyph.height].iter().any(|value| *value < 0);
+                    if negative || glyph.x + glyph.width > page.width as i32 || glyph.y + glyph.height > page.height as i32 {
+                        return Err(format!("font line {}: glyph lies outside the page", line_number + 1));
+                    }
+                    glyphs.insert(character("id")?, glyph);
+                }
+                "kerning" => {
+                    kerning.insert((character("first")?, character("second")?), int("amount")?);
+                }
+                _ => {}
+            }
+        }
+
+        Ok(BitmapFont {
+            line_height: line_height.ok_or("font has no `common` line")?,
+            glyphs,
+            kerning,
+            page,
+        })
+    }
+
+    fn glyph(&self, character: char) -> Option<&Glyph> {
+        self.glyphs.get(&character).or_else(|| self.glyphs.get(&'?'))
+    }
+
+    /// Width of a single line of text in unscaled font pixels.
+    pub fn measure(&self, text: &str) -> i32 {
+        let mut width = 0;
+        let mut previous = None;
+        for character in text.chars() {
+            if let Some(glyph) = self.glyph(character) {
+                width += glyph.x_advance + previous.map_or(0, |previous| self.kerning_between(previous, character));
+            }
+            previous = Some(character);
+        }
+        width
+    }
+
+    fn kerning_between(&self, first: char, second: char) -> i32 {
+        self.kerning.get(&(first, second)).copied().unwrap_or(0)
+    }
+
+    /// Splits text into lines at newlines and, if given, wherever a line would exceed `max_width`
+    /// unscaled pixels. Words longer than the limit are left on a line of their own.
+    pub fn wrap(&self, text: &str, max_width: Option<i32>) -> Vec<String> {
+        let mut lines = Vec::new();
+
+        for paragraph in text.split('\n') {
+            let Some(max_width) = max_width else {
+                lines.push(paragraph.to_string());
+                continue;
+            };
+
+            let mut line = String::new();
+            for word in paragraph.split(' ') {
+                let candidate = if line.is_empty() { word.to_string() } else { format!("{} {}", line, word) };
+                if !line.is_empty() && self.measure(&candidate) > max_width {
+                    lines.push(std::mem::replace(&mut line, word.to_string()));
+                } else {
+                    line = candidate;
+                }
+            }
+            lines.push(line);
+        }
+
+        lines
+    }
+
+    /// Lays out text with its anchor at (x, y), returning the page and screen rectangle of every glyph.
+    /// The anchor is the top left, top centre or top right of the block depending on the alignment.
+    pub fn layout(&self, x: i32, y: i32, text: &str, style: &TextStyle) -> Vec<(Rect, Rect)> {
+        let scale = style.scale.max(1);
+        let mut quads = Vec::new();
+
+        for (index, line) in self.wrap(text, style.max_width.map(|width| width / scale)).iter().enumerate() {
+            let line_width = self.measure(line) * scale;
+            let mut pen_x = match style.align {
+                Align::Left => x,
+                Align::Center => x - line_width / 2,
+                Align::Right => x - line_width,
+            };
+            let pen_y = y + index as i32 * self.line_height * scale;
+
+            let mut previous = None;
+            for character in line.chars() {
+                let Some(glyph) = self.glyph(character) else {
+                    continue;
+                };
+                if let Some(previous) = previous {
+                    pen_x += self.kerning_between(previous, character) * scale;
+                }
+
+                if glyph.width > 0 && glyph.height > 0 {
+                    quads.push((
+                        Rect::new(glyph.x, glyph.y, glyph.width, glyph.height),
+                        Rect::new(
+                            pen_x + glyph.x_offset * scale,
+                            pen_y + glyph.y_offset * scale,
+                            glyph.width * scale,
+                            glyph.height * scale,
+                        ),
+                    ));
+                }
+
+                pen_x += glyph.x_advance * scale;
+                previous = Some(character);
+            }
+        }
+
+        quads
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A font whose glyphs are all 4 pixels wide with 5 pixel advances, with `?` as the fallback.
+    fn test_font(characters: &str) -> BitmapFont {
+        let mut descriptor = "common lineHeight=10 pages=1\n".to_string();
+        for (index, character) in characters.chars().enumerate() {
+            descriptor += &format!(
+                "char id={} x={} y=0 width=4 height=8 xoffset=0 yoffset=1 xadvance=5\n",
+                character as u32,
+                index * 4
+            );
+        }
+        let page = Bitmap { width: 64, height: 8, pixels: vec![255; 64 * 8 * 4] };
+        BitmapFont::parse_bmfont(&descriptor, page).unwrap()
+    }
+
+    fn left_edges(quads: &[(Rect, Rect)]) -> Vec<i32> {
+        quads.iter().map(|(_, dest)| dest.x).collect()
+    }
+
+    #[test]
+    fn alignment_anchors_each_line() {
+        let font = test_font("ab?");
+        let style = |align| TextStyle { align, ..TextStyle::default() };
+        assert_eq!(left_edges(&font.layout(100, 0, "ab", &style(Align::Left))), vec![100, 105]);
+        assert_eq!(left_edges(&font.layout(100, 0, "ab", &style(Align::Center))), vec![95, 100]);
+        assert_eq!(left_edges(&font.layout(100, 0, "ab", &style(Align::Right))), vec![90, 95]);
+
+        let scaled = TextStyle { scale: 2, ..style(Align::Right) };
+        let quads = font.layout(100, 20, "a\nab", &scaled);
+        assert_eq!(left_edges(&quads), vec![90, 80, 90]);
+        assert_eq!(quads.iter().map(|(_, dest)| dest.y).collect::<Vec<_>>(), vec![22, 42, 42]);
+    }
+
+    #[test]
+    fn wrapping_breaks_between_words() {
+        let font = test_font("ab ?");
+        assert_eq!(font.wrap("ab ab ab", None), vec!["ab ab ab"]);
+        // "ab ab" is 25 pixels wide
+        assert_eq!(font.wrap("ab ab ab", Some(25)), vec!["ab ab", "ab"]);
+        assert_eq!(font.wrap("ab ab ab", Some(24)), vec!["ab", "ab", "ab"]);
+        assert_eq!(font.wrap("aaaaaaa b", Some(10)), vec!["aaaaaaa", "b"]);
+        assert_eq!(font.wrap("ab\nab ab", Some(25)), vec!["ab", "ab ab"]);
+    }
+
+    #[test]
+    fn unknown_glyphs_fall_back_to_question_mark() {
+        let font = test_font("a?");
+        let quads = font.layout(0, 0, "a\u{e9}", &TextStyle::default());
+        assert_eq!(quads.len(), 2);
+        assert_eq!(quads[1].0.x, font.glyphs[&'?'].x);
+        assert_eq!(font.measure("a\u{e9}"), 10);
+
+        // Without a fallback they take up no room at all
+        let font = test_font("a");
+        assert_eq!(font.layout(0, 0, "\u{e9}a", &TextStyle::default()).len(), 1);
+        assert_eq!(font.measure("\u{e9}a"), 5);
+    }
+
+    #[test]
+    fn glyphs_off_the_page_are_rejected() {
+        let page = || Bitmap { width: 16, height: 16, pixels: vec![0; 16 * 16 * 4] };
+        let font = |glyph: &str| BitmapFont::parse_bmfont(&format!("common lineHeight=8 pages=1\nchar id=65 {} xoffset=0 yoffset=0 xadvance=4", glyph), page());
+        assert!(font("x=0 y=0 width=8 height=8").is_ok());
+        assert!(font("x=-4 y=0 width=8 height=8").is_err());
+        assert!(font("x=0 y=-1 width=8 height=8").is_err());
+        assert!(font("x=12 y=0 width=8 height=8").is_err());
+        assert!(font("x=0 y=0 width=-2 height=8").is_err());
+    }
+}
@@ -1,22 +1,122 @@
+use crate::dungeon::EXIT_TEXTURE;
+use crate::inventory::{Inventory, InventoryEvent, Item, StackLimits};
+use crate::lift::{Lift, LIFT_SPEED};
+use crate::portal::{Portal, PortalFace};
+use crate::pushwall::{Pushwall, PUSH_DISTANCE};
+use crate::raycasting::MIRROR_TEXTURE;
+use crate::sector::SectorMap;
+use crate::thin_wall::{ThinWall, THIN_WALL_THICKNESS};
+
+// Texture ids of MAP's see-through walls, as loaded by `Engine::new` (1-based)
+pub const GRATE_TEXTURE: u8 = 19;
+pub const GLASS_TEXTURE: u8 = 20;
+
 pub const MAP_WIDTH: usize = 8;
 pub const MAP_HEIGHT: usize = 8;
 
 pub const MAP: [u8; MAP_WIDTH * MAP_HEIGHT] = [
     1, 1, 1, 1, 1, 1, 1, 1,
-    1, 0, 0, 0, 0, 0, 0, 1,
-    1, 0, 2, 0, 3, 0, 0, 1,
-    1, 0, 0, 0, 0, 0, 0, 1,
-    1, 0, 0, 0, 0, 0, 0, 1,
-    1, 0, 0, 0, 0, 0, 0, 1,
-    1, 0, 0, 0, 0, 0, 0, 1,
+    1, 0, 0, 3, 0, 0, 0, 1,
+    1, 3, 0, 3, 2, 0, 0, MIRROR_TEXTURE,
+    1, 0, 2, 0, 0, GRATE_TEXTURE, 0, 1,
+    1, 3, 0, 0, 0, 0, 0, 1,
+    1, 0, 0, 0, GLASS_TEXTURE, 0, 0, 1,
+    1, 0, 0, 0, 0, 0, 0, EXIT_TEXTURE,
     1, 1, 1, 1, 1, 1, 1, 1,
 ];
 
+/// Cells of MAP that slide back when used. The one at (3, 1) slides west along the passage behind
+/// it, (2, 1) to (1, 1), opening up the nook at (2, 2) just south of that passage.
+pub const MAP_SECRETS: [(usize, usize); 1] = [(3, 1)];
+
+/// Walls of MAP that aren't the usual one unit tall: a half-height pillar and towers in the corners.
+pub const MAP_HEIGHTS: [(usize, usize, f64); 5] = [
+    (4, 2, 0.5),
+    (0, 0, 2.0),
+    (7, 0, 2.0),
+    (0, 7, 2.0),
+    (7, 7, 2.0),
+];
+
+/// Cells of MAP holding a thin wall rather than a solid block: a grate and a window near the
+/// spawn point, and an angled wall cutting across a corner by the west wall.
+pub const MAP_THIN_WALLS: [(usize, usize, ThinWall); 3] = [
+    (5, 3, ThinWall::VERTICAL),
+    (4, 5, ThinWall::HORIZONTAL),
+    (1, 4, ThinWall::ANTI_DIAGONAL),
+];
+
+/// Portals in MAP: walking into the north wall at the east end of the room brings you out of the
+/// west wall near the exit, heading east.
+pub const MAP_PORTALS: [Portal; 1] = [Portal {
+    from: PortalFace { x: 5, y: 0, normal: (0, 1) },
+    to: PortalFace { x: 0, y: 6, normal: (1, 0) },
+}];
+
+/// The row of MAP's upstairs gallery that has a floor. The rest of the storey is open, looking down
+/// into the room below.
+pub const MAP_GALLERY_ROW: usize = 1;
+
+/// The lift in MAP, at the east end of the gallery.
+pub const MAP_LIFT: Lift = Lift { x: 6, y: 1, storey: 0 };
+
+pub const PLAYER_RADIUS: f64 = 0.2;
+pub const MAX_HEALTH: f64 = 100.0;
+pub const MAX_ARMOR: f64 = 100.0;
+
+/// Fraction of incoming damage soaked up by armor while any remains.
+const ARMOR_ABSORPTION: f64 = 0.5;
+/// Ticks of invulnerability granted after taking damage.
+const INVULNERABLE_TICKS: u32 = 30;
+/// Ticks the camera takes to tilt down after death.
+const DEATH_TILT_TICKS: u32 = 40;
+/// How far in front of the player the Use key reaches.
+const USE_RANGE: f64 = 0.8;
+/// How far the horizon rises once the death tilt completes, as a fraction of screen height.
+const DEATH_TILT: f64 = 0.4;
+/// Furthest the player can look up or down, as a fraction of screen height the horizon moves.
+/// Shearing the view like this looks increasingly stretched beyond about this much.
+pub const MAX_PITCH: f64 = 0.35;
+/// Eye height standing and crouching, in wall heights above the floor.
+pub const EYE_HEIGHT: f64 = 0.5;
+const CROUCH_EYE_HEIGHT: f64 = 0.3;
+/// Fraction of the way into or out of a crouch covered each tick.
+const CROUCH_SPEED: f64 = 0.2;
+/// Upward speed at the start of a jump and the pull of gravity, in wall heights per tick.
+const JUMP_SPEED: f64 = 0.06;
+const GRAVITY: f64 = 0.004;
+/// How far the camera dips with each step, in wall heights, and how quickly steps come as the
+/// player moves.
+const HEAD_BOB_AMPLITUDE: f64 = 0.02;
+const HEAD_BOB_FREQUENCY: f64 = 12.0;
+/// Movement speed (map units per tick) at which the head bob reaches full amplitude.
+const HEAD_BOB_FULL_SPEED: f64 = 0.1;
+
 pub struct Player {
     pub x: f64,
     pub y: f64,
     pub direction: f64,
+    /// How far the player is looking up (positive) or down (negative). The view is sheared rather
+    /// than rotated: the horizon moves down the screen by this fraction of its height.
+    pub pitch: f64,
+    /// Height of the player's feet above the floor while jumping.
+    pub z: f64,
+    /// The storey the player is on, 0 being the ground floor.
+    pub storey: usize,
+    /// Height still to travel on a lift, in wall heights: negative while it is carrying the player
+    /// up to their new storey's floor, positive while carrying them down.
+    pub lift: f64,
+    pub vertical_speed: f64,
+    /// How far into a crouch the player is, from 0 standing to 1 fully crouched.
+    pub crouch: f64,
+    pub bob_phase: f64,
+    pub bob_amount: f64,
     pub fov: f64,
+    pub health: f64,
+    pub armor: f64,
+    pub invulnerable_ticks: u32,
+    pub death_ticks: u32,
+    pub inventory: Inventory,
 }
 
 impl Player {
@@ -25,28 +125,170 @@ impl Player {
             x: 3.5,
             y: 3.5,
             direction: 0.0,
+            pitch: 0.0,
+            z: 0.0,
+            storey: 0,
+            lift: 0.0,
+            vertical_speed: 0.0,
+            crouch: 0.0,
+            bob_phase: 0.0,
+            bob_amount: 0.0,
             fov: std::f64::consts::PI / 3.0,
+            health: MAX_HEALTH,
+            armor: 0.0,
+            invulnerable_ticks: 0,
+            death_ticks: 0,
+            inventory: Inventory::new(StackLimits::default()),
+        }
+    }
+
+    /// Gives the player an item, returning false if it was of no use and should stay where it is.
+    pub fn give(&mut self, item: Item) -> bool {
+        let taken = match item {
+            Item::Health(amount) if self.health < MAX_HEALTH => {
+                self.health = (self.health + amount).min(MAX_HEALTH);
+                true
+            }
+            Item::Armor(amount) if self.armor < MAX_ARMOR => {
+                self.armor = (self.armor + amount).min(MAX_ARMOR);
+                true
+            }
+            Item::Health(_) | Item::Armor(_) => false,
+            _ => self.inventory.add(item),
+        };
+
+        if taken {
+            self.inventory.events.push(InventoryEvent::Collected(item));
+        }
+        taken
+    }
+
+    pub fn is_dead(&self) -> bool {
+        self.health <= 0.0
+    }
+
+    /// Applies damage from an entity or hazard, returning the health actually lost.
+    /// Damage is ignored while dead or during the invulnerability window after a previous hit.
+    pub fn damage(&mut self, amount: f64) -> f64 {
+        if self.is_dead() || self.invulnerable_ticks > 0 || amount <= 0.0 {
+            return 0.0;
+        }
+
+        let absorbed = (amount * ARMOR_ABSORPTION).min(self.armor);
+        self.armor -= absorbed;
+
+        let taken = (amount - absorbed).min(self.health);
+        self.health -= taken;
+        self.invulnerable_ticks = INVULNERABLE_TICKS;
+        taken
+    }
+
+    /// Advances invulnerability and the death animation by one tick.
+    pub fn tick(&mut self) {
+        self.invulnerable_ticks = self.invulnerable_ticks.saturating_sub(1);
+        if self.is_dead() && self.death_ticks < DEATH_TILT_TICKS {
+            self.death_ticks += 1;
+        }
+    }
+
+    /// True once the death animation has finished and the player can respawn.
+    pub fn can_respawn(&self) -> bool {
+        self.is_dead() && self.death_ticks >= DEATH_TILT_TICKS
+    }
+
+    /// Screen row of the horizon, shifted by the pitch and tilted upwards while dying so the
+    /// camera looks at the floor.
+    pub fn horizon(&self, screen_height: f64) -> f64 {
+        let tilt = self.death_ticks as f64 / DEATH_TILT_TICKS as f64 * DEATH_TILT;
+        screen_height / 2.0 + (self.pitch - tilt) * screen_height
+    }
+
+    pub fn on_ground(&self) -> bool {
+        self.z <= 0.0
+    }
+
+    /// Height of the camera above the floor of the player's storey, in wall heights.
+    pub fn eye_height(&self) -> f64 {
+        let standing = EYE_HEIGHT + (CROUCH_EYE_HEIGHT - EYE_HEIGHT) * self.crouch;
+        let bob = -self.bob_phase.sin().abs() * HEAD_BOB_AMPLITUDE * self.bob_amount;
+        standing + self.z + bob + self.lift
+    }
+
+    /// Height of the camera above the ground floor, in wall heights. Each storey stands one wall
+    /// height above the one below.
+    pub fn camera_height(&self) -> f64 {
+        self.storey as f64 + self.eye_height()
+    }
+
+    /// Moves the player onto another storey, riding a lift there from where they are now.
+    pub fn ride(&mut self, storey: usize) {
+        self.lift += self.storey as f64 - storey as f64;
+        self.storey = storey;
+    }
+
+    /// Advances jumping, crouching and head bob by one tick. `speed` is the distance the player
+    /// moved this tick.
+    pub fn update_camera(&mut self, jump: bool, crouch: bool, speed: f64) {
+        let target = if crouch { 1.0 } else { 0.0 };
+        self.crouch += (target - self.crouch) * CROUCH_SPEED;
+
+        if jump && self.on_ground() && !crouch {
+            self.vertical_speed = JUMP_SPEED;
         }
+        if self.vertical_speed != 0.0 || !self.on_ground() {
+            self.z += self.vertical_speed;
+            self.vertical_speed -= GRAVITY;
+            if self.z <= 0.0 {
+                self.z = 0.0;
+                self.vertical_speed = 0.0;
+            }
+        }
+
+        self.lift -= self.lift.signum() * self.lift.abs().min(LIFT_SPEED);
+
+        // Only steps on the ground bob the view
+        let walking = if self.on_ground() { speed } else { 0.0 };
+        self.bob_phase += walking * HEAD_BOB_FREQUENCY;
+        let target = (walking / HEAD_BOB_FULL_SPEED).min(1.0);
+        self.bob_amount += (target - self.bob_amount) * 0.2;
+    }
+
+    /// Looks up by `amount` (down if negative), as far as MAX_PITCH allows.
+    pub fn look(&mut self, amount: f64) {
+        self.pitch = (self.pitch + amount).clamp(-MAX_PITCH, MAX_PITCH);
+    }
+
+    /// Moves forward, returning the portal passed through on the way, if any.
+    pub fn move_forward(&mut self, distance: f64, map: &Map) -> Option<Portal> {
+        self.walk(distance, map)
+    }
+
+    /// Moves backward, returning the portal passed through on the way, if any.
+    pub fn move_backward(&mut self, distance: f64, map: &Map) -> Option<Portal> {
+        self.walk(-distance, map)
     }
 
-    pub fn move_forward(&mut self, distance: f64) {
+    fn walk(&mut self, distance: f64, map: &Map) -> Option<Portal> {
         let new_x = self.x + distance * self.direction.cos();
         let new_y = self.y + distance * self.direction.sin();
 
-        if !self.is_colliding(new_x, new_y) {
+        // Stepping into a portal comes out of the other end, facing the way it turns you
+        if let Some(portal) = map.portal_crossing(self.x, self.y, new_x, new_y) {
+            let (new_x, new_y) = portal.transform_point(new_x, new_y);
+            if self.is_colliding(new_x, new_y, map) {
+                return None;
+            }
             self.x = new_x;
             self.y = new_y;
+            self.direction += portal.angle();
+            return Some(portal);
         }
-    }
 
-    pub fn move_backward(&mut self, distance: f64) {
-        let new_x = self.x - distance * self.direction.cos();
-        let new_y = self.y - distance * self.direction.sin();
-
-        if !self.is_colliding(new_x, new_y) {
+        if !self.is_colliding(new_x, new_y, map) {
             self.x = new_x;
             self.y = new_y;
         }
+        None
     }
 
     pub fn turn_left(&mut self, angle: f64) {
@@ -57,14 +299,338 @@ impl Player {
         self.direction += angle;
     }
 
-    pub fn is_colliding(&self, new_x: f64, new_y: f64) -> bool {
-        let map_x = new_x as usize;
-        let map_y = new_y as usize;
+    /// The wall cell the player is facing, if one is within reach of the Use key.
+    pub fn facing_wall(&self, map: &Map) -> Option<(i32, i32)> {
+        let steps = 8;
+        (1..=steps).find_map(|step| {
+            let distance = USE_RANGE * step as f64 / steps as f64;
+            let x = self.x + distance * self.direction.cos();
+            let y = self.y + distance * self.direction.sin();
+            map.is_wall(x, y).then_some((x.floor() as i32, y.floor() as i32))
+        })
+    }
+
+    pub fn is_colliding(&self, new_x: f64, new_y: f64, map: &Map) -> bool {
+        match &map.sectors {
+            Some(sectors) => !sectors.can_step(self.x, self.y, new_x, new_y),
+            None => map.is_wall(new_x, new_y),
+        }
+    }
+}
+
+/// The level's wall grid. Cells hold a texture id, or 0 for empty space. A map can have further
+/// storeys built on top of it, each a map of its own.
+#[derive(Clone)]
+pub struct Map {
+    pub width: usize,
+    pub height: usize,
+    pub cells: Vec<u8>,
+    /// How tall each cell's wall is, as a multiple of an ordinary wall's height.
+    pub heights: Vec<f64>,
+    /// For each cell, the line its wall runs along if it is a thin wall rather than a full block.
+    pub thin_walls: Vec<Option<ThinWall>>,
+    /// Indices of wall cells that are secret pushwalls and haven't been found yet.
+    pub secrets: Vec<usize>,
+    /// The secret wall currently sliding, if any. Only one moves at a time.
+    pub pushwall: Option<Pushwall>,
+    /// Pairs of wall faces joined to each other, each listed once.
+    pub portals: Vec<Portal>,
+    /// Empty cells with no floor, opening onto the storey below. Only upper storeys have them.
+    pub holes: Vec<bool>,
+    /// Storeys stacked above this one, lowest first, each one wall height above the last.
+    pub storeys: Vec<Map>,
+    /// Lifts between this map's storeys.
+    pub lifts: Vec<Lift>,
+    /// The sectors the level is really made of, for levels drawn by the sector renderer. The grid
+    /// then only roughly follows them, and what is solid comes from the sectors instead.
+    pub sectors: Option<SectorMap>,
+}
+
+impl Map {
+    /// The built-in starting level.
+    pub fn new() -> Self {
+        let mut map = Map {
+            width: MAP_WIDTH,
+            height: MAP_HEIGHT,
+            cells: MAP.to_vec(),
+            heights: vec![1.0; MAP.len()],
+            thin_walls: vec![None; MAP.len()],
+            secrets: MAP_SECRETS.iter().map(|(x, y)| y * MAP_WIDTH + x).collect(),
+            pushwall: None,
+            portals: MAP_PORTALS.to_vec(),
+            holes: vec![false; MAP.len()],
+            storeys: Vec::new(),
+            lifts: vec![MAP_LIFT],
+            sectors: None,
+        };
+        for (x, y, height) in MAP_HEIGHTS {
+            map.heights[y * MAP_WIDTH + x] = height;
+        }
+        for (x, y, thin_wall) in MAP_THIN_WALLS {
+            map.thin_walls[y * MAP_WIDTH + x] = Some(thin_wall);
+        }
+
+        let mut gallery = Map::filled(MAP_WIDTH, MAP_HEIGHT, 1);
+        for y in 1..MAP_HEIGHT - 1 {
+            for x in 1..MAP_WIDTH - 1 {
+                gallery.set(x, y, 0);
+                gallery.holes[y * MAP_WIDTH + x] = y != MAP_GALLERY_ROW;
+            }
+        }
+        map.storeys.push(gallery);
+        map
+    }
+
+    /// A map of the given size with every cell set to `cell`.
+    pub fn filled(width: usize, height: usize, cell: u8) -> Self {
+        Map {
+            width,
+            height,
+            cells: vec![cell; width * height],
+            heights: vec![1.0; width * height],
+            thin_walls: vec![None; width * height],
+            secrets: Vec::new(),
+            pushwall: None,
+            portals: Vec::new(),
+            holes: vec![false; width * height],
+            storeys: Vec::new(),
+            lifts: Vec::new(),
+            sectors: None,
+        }
+    }
+
+    /// The storey `index` levels up, 0 being this map itself.
+    pub fn storey(&self, index: usize) -> &Map {
+        match index {
+            0 => self,
+            _ => &self.storeys[index - 1],
+        }
+    }
+
+    pub fn storey_mut(&mut self, index: usize) -> &mut Map {
+        match index {
+            0 => self,
+            _ => &mut self.storeys[index - 1],
+        }
+    }
+
+    /// The storey a lift at (x, y) takes a player on `storey` to, if there is one there.
+    pub fn lift(&self, x: i32, y: i32, storey: usize) -> Option<usize> {
+        self.lifts
+            .iter()
+            .filter(|lift| lift.x == x && lift.y == y)
+            .find_map(|lift| lift.destination(storey))
+    }
+
+    pub fn set(&mut self, x: usize, y: usize, cell: u8) {
+        self.cells[y * self.width + x] = cell;
+    }
+
+    /// The cell at (x, y), or None if it lies outside the map.
+    pub fn get(&self, x: i32, y: i32) -> Option<u8> {
+        if x < 0 || y < 0 || x as usize >= self.width || y as usize >= self.height {
+            return None;
+        }
+        Some(self.cells[y as usize * self.width + x as usize])
+    }
+
+    /// How tall the wall at (x, y) is. Cells outside the map count as ordinary walls.
+    pub fn height_at(&self, x: i32, y: i32) -> f64 {
+        if self.get(x, y).is_none() {
+            return 1.0;
+        }
+        self.heights[y as usize * self.width + x as usize]
+    }
 
-        if map_x >= MAP_WIDTH || map_y >= MAP_HEIGHT{
+    /// Returns true if the point lies inside a solid map cell, a moving pushwall or outside the map.
+    pub fn is_wall(&self, x: f64, y: f64) -> bool {
+        if x < 0.0 || y < 0.0 {
+            return true;
+        }
+        if let Some(sectors) = &self.sectors {
+            return sectors.sector_at(x, y).is_none();
+        }
+        if self.pushwall.is_some_and(|pushwall| pushwall.contains(x, y)) {
             return true;
         }
+        let (cell_x, cell_y) = (x as i32, y as i32);
+        match self.get(cell_x, cell_y) {
+            None => true,
+            // There's nothing to stand on over a hole
+            Some(0) => self.holes[cell_y as usize * self.width + cell_x as usize],
+            Some(_) => match self.thin_wall_at(cell_x, cell_y) {
+                Some(thin_wall) => thin_wall.distance_to(x.fract(), y.fract()) < THIN_WALL_THICKNESS,
+                None => true,
+            },
+        }
+    }
+
+    /// True if cell (x, y) has floor showing: it is empty or holds a thin wall, and isn't a hole.
+    pub fn floor_at(&self, x: i32, y: i32) -> bool {
+        match self.get(x, y) {
+            None => false,
+            Some(cell) => {
+                let index = y as usize * self.width + x as usize;
+                (cell == 0 || self.thin_walls[index].is_some()) && !self.holes[index]
+            }
+        }
+    }
+
+    /// The thin wall in cell (x, y), if it holds one rather than a full block.
+    pub fn thin_wall_at(&self, x: i32, y: i32) -> Option<ThinWall> {
+        self.get(x, y)?;
+        self.thin_walls[y as usize * self.width + x as usize]
+    }
+
+    /// The portal entered through the face of cell (x, y) that looks out along `normal`, if that
+    /// face is one end of a portal.
+    pub fn portal(&self, x: i32, y: i32, normal: (i32, i32)) -> Option<Portal> {
+        let face = PortalFace { x, y, normal };
+        self.portals.iter().find_map(|portal| {
+            if portal.from == face {
+                Some(*portal)
+            } else if portal.to == face {
+                Some(portal.reversed())
+            } else {
+                None
+            }
+        })
+    }
+
+    /// The portal crossed by going from one point straight to another close by, if any.
+    pub fn portal_crossing(&self, from_x: f64, from_y: f64, to_x: f64, to_y: f64) -> Option<Portal> {
+        let (cell_x, cell_y) = (to_x.floor() as i32, to_y.floor() as i32);
+        let normal = (from_x.floor() as i32 - cell_x, from_y.floor() as i32 - cell_y);
+        // Only straight through a face; cutting across a corner doesn't count
+        if normal.0.abs() + normal.1.abs() != 1 {
+            return None;
+        }
+        self.portal(cell_x, cell_y, normal)
+    }
+
+    /// Starts the secret wall at (x, y) sliding one step of (dx, dy) at a time, returning true if
+    /// it was a secret and had room to move. It stops short of any cell `occupied` says something
+    /// is standing in, rather than burying it. The path is only checked here: anything that wanders
+    /// into it while the wall is sliding ends up inside the block once it comes to rest.
+    pub fn push(&mut self, x: i32, y: i32, dx: i32, dy: i32, occupied: impl Fn(i32, i32) -> bool) -> bool {
+        if self.pushwall.is_some() {
+            return false;
+        }
+        let Some(cell) = self.get(x, y) else {
+            return false;
+        };
+        let index = y as usize * self.width + x as usize;
+        let Some(secret) = self.secrets.iter().position(|secret| *secret == index) else {
+            return false;
+        };
+
+        // Slide until something solid or someone is in the way, up to the full distance
+        let distance = (1..=PUSH_DISTANCE as i32)
+            .map(|step| (x + dx * step, y + dy * step))
+            .take_while(|&(x, y)| self.get(x, y) == Some(0) && !occupied(x, y))
+            .count() as u32;
+        if distance == 0 {
+            return false;
+        }
+
+        self.secrets.swap_remove(secret);
+        self.cells[index] = 0;
+        let height = self.heights[index];
+        self.pushwall = Some(Pushwall { x, y, dx, dy, texture_id: cell, height, distance, ticks: 0 });
+        true
+    }
+
+    /// Moves the sliding pushwall on by a tick, setting it back into the grid once it stops. Each
+    /// storey above has its own.
+    pub fn update(&mut self) {
+        for storey in &mut self.storeys {
+            storey.update();
+        }
+        let Some(pushwall) = &mut self.pushwall else {
+            return;
+        };
+        if pushwall.tick() {
+            let (x, y) = pushwall.destination();
+            let (texture_id, height) = (pushwall.texture_id, pushwall.height);
+            self.set(x as usize, y as usize, texture_id);
+            self.heights[y as usize * self.width + x as usize] = height;
+            self.pushwall = None;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::weapon::WeaponKind;
+
+    #[test]
+    fn armor_soaks_up_half_of_each_hit_while_it_lasts() {
+        let mut player = Player::new();
+        player.armor = 10.0;
+        assert_eq!(player.damage(30.0), 20.0);
+        assert_eq!((player.health, player.armor), (80.0, 0.0));
+
+        player.invulnerable_ticks = 0;
+        assert_eq!(player.damage(30.0), 30.0);
+        assert_eq!(player.health, 50.0);
+    }
+
+    #[test]
+    fn hits_are_ignored_while_invulnerable() {
+        let mut player = Player::new();
+        player.damage(10.0);
+        for _ in 0..INVULNERABLE_TICKS - 1 {
+            player.tick();
+            assert_eq!(player.damage(10.0), 0.0);
+        }
+        player.tick();
+        assert_eq!(player.damage(10.0), 10.0);
+        assert_eq!(player.health, 80.0);
+    }
+
+    #[test]
+    fn dying_tilts_the_camera_before_respawn_is_allowed() {
+        let mut player = Player::new();
+        assert_eq!(player.damage(250.0), MAX_HEALTH);
+        assert!(player.is_dead());
+        assert_eq!(player.health, 0.0);
+
+        for _ in 0..DEATH_TILT_TICKS {
+            assert!(!player.can_respawn());
+            player.tick();
+        }
+        assert!(player.can_respawn());
+        assert!(player.horizon(100.0) < Player::new().horizon(100.0));
+    }
+
+    #[test]
+    fn collecting_raises_an_event_only_when_the_item_is_taken() {
+        let mut player = Player::new();
+        assert!(!player.give(Item::Health(25.0)));
+        assert!(player.give(Item::Armor(25.0)));
+        assert!(player.give(Item::Treasure(100)));
+        assert!(!player.give(Item::Weapon(WeaponKind::Sword)));
+        let events = player.inventory.drain_events();
+        assert!(events == [InventoryEvent::Collected(Item::Armor(25.0)), InventoryEvent::Collected(Item::Treasure(100))]);
+    }
+
+    #[test]
+    fn pushwalls_stop_short_of_occupied_cells() {
+        let (x, y) = (MAP_SECRETS[0].0 as i32, MAP_SECRETS[0].1 as i32);
+
+        let mut map = Map::new();
+        assert!(map.push(x, y, -1, 0, |_, _| false));
+        assert_eq!(map.pushwall.map(|pushwall| pushwall.destination()), Some((x - 2, y)));
+
+        let mut map = Map::new();
+        assert!(map.push(x, y, -1, 0, |cell_x, _| cell_x == x - 2));
+        assert_eq!(map.pushwall.map(|pushwall| pushwall.destination()), Some((x - 1, y)));
 
-        MAP[map_y * MAP_WIDTH + map_x] != 0
+        // Something right behind it leaves no room at all, and the secret stays to be found
+        let mut map = Map::new();
+        assert!(!map.push(x, y, -1, 0, |cell_x, _| cell_x == x - 1));
+        assert!(map.pushwall.is_none());
+        assert_eq!(map.secrets.len(), 1);
     }
 }
@@ -0,0 +1,266 @@
+use crate::game::{Player, MAX_HEALTH};
+use crate::inventory::{InventoryEvent, Item, KeyColor};
+use crate::renderer::{Bitmap, Rect, Renderer};
+use crate::weapon::WeaponKind;
+
+// Frames of the face sheet after the five health levels
+const FACE_HEALTH_LEVELS: usize = 5;
+const FACE_OUCH: usize = 5;
+const FACE_GRIN: usize = 6;
+const FACE_DEAD: usize = 7;
+
+/// Most digits a number can show: enough for any u32.
+const MAX_DIGITS: i32 = 10;
+
+/// Ticks the face grins for after picking up a weapon.
+const GRIN_TICKS: u32 = 60;
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Stat {
+    Health,
+    Armor,
+    Ammo,
+    Score,
+}
+
+enum Element {
+    Image { sheet: usize, x: i32, y: i32 },
+    Number { stat: Stat, sheet: usize, x: i32, y: i32, digits: usize },
+    Face { sheet: usize, x: i32, y: i32 },
+    Key { color: KeyColor, sheet: usize, frame: i32, x: i32, y: i32 },
+}
+
+/// A strip of equally sized frames laid out left to right in one image.
+pub struct Sheet {
+    pub image_id: String,
+    pub frame_width: i32,
+    pub frame_height: i32,
+}
+
+/// The parsed contents of a HUD config file. See `static/hud.cfg` for the format.
+pub struct HudLayout {
+    pub width: i32,
+    pub height: i32,
+    pub sheets: Vec<Sheet>,
+    elements: Vec<Element>,
+}
+
+impl HudLayout {
+    pub fn parse(source: &str) -> Result<Self, String> {
+        let mut layout = HudLayout {
+            width: 320,
+            height: 200,
+            sheets: Vec::new(),
+            elements: Vec::new(),
+        };
+
+        for (line_number, line) in source.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or("").trim();
+            if line.is_empty() {
+                continue;
+            }
+
+            layout
+                .parse_line(line)
+                .map_err(|error| format!("hud config line {}: {}", line_number + 1, error))?;
+        }
+
+        Ok(layout)
+    }
+
+    fn parse_line(&mut self, line: &str) -> Result<(), String> {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        let int = |index: usize| -> Result<i32, String> {
+            let word = words.get(index).ok_or("missing argument")?;
+            word.parse().map_err(|_| format!("expected a number, got `{}`", word))
+        };
+        // Positions and frame numbers can't be negative, and sizes and digit counts must be at least 1
+        let at_least = |index: usize, min: i32| -> Result<i32, String> {
+            let value = int(index)?;
+            if value < min {
+                return Err(format!("expected at least {}, got {}", min, value));
+            }
+            Ok(value)
+        };
+        let coordinate = |index: usize| at_least(index, 0);
+        let size = |index: usize| at_least(index, 1);
+
+        match (words[0], words.len()) {
+            ("screen", 3) => {
+                self.width = size(1)?;
+                self.height = size(2)?;
+            }
+            ("sheet", 4) => self.sheets.push(Sheet {
+                image_id: words[1].to_string(),
+                frame_width: size(2)?,
+                frame_height: size(3)?,
+            }),
+            ("image", 4) => self.elements.push(Element::Image {
+                sheet: self.sheet(words[1])?,
+                x: coordinate(2)?,
+                y: coordinate(3)?,
+            }),
+            ("number", 6) => {
+                let stat = match words[1] {
+                    "health" => Stat::Health,
+                    "armor" => Stat::Armor,
+                    "ammo" => Stat::Ammo,
+                    "score" => Stat::Score,
+                    other => return Err(format!("unknown stat `{}`", other)),
+                };
+                self.elements.push(Element::Number {
+                    stat,
+                    sheet: self.sheet(words[2])?,
+                    x: coordinate(3)?,
+                    y: coordinate(4)?,
+                    digits: size(5)?.min(MAX_DIGITS) as usize,
+                });
+            }
+            ("face", 4) => self.elements.push(Element::Face {
+                sheet: self.sheet(words[1])?,
+                x: coordinate(2)?,
+                y: coordinate(3)?,
+            }),
+            ("key", 6) => {
+                let color = match words[1] {
+                    "red" => KeyColor::Red,
+                    "blue" => KeyColor::Blue,
+                    other => return Err(format!("unknown key `{}`", other)),
+                };
+                self.elements.push(Element::Key {
+                    color,
+                    sheet: self.sheet(words[2])?,
+                    frame: coordinate(3)?,
+                    x: coordinate(4)?,
+                    y: coordinate(5)?,
+                });
+            }
+            (command, _) => return Err(format!("unrecognised `{}` with {} arguments", command, words.len() - 1)),
+        }
+
+        Ok(())
+    }
+
+    fn sheet(&self, image_id: &str) -> Result<usize, String> {
+        self.sheets
+            .iter()
+            .position(|sheet| sheet.image_id == image_id)
+            .ok_or_else(|| format!("sheet `{}` used before it was declared", image_id))
+    }
+}
+
+/// The status bar drawn into the framebuffer over the bottom of the scene.
+pub struct Hud {
+    layout: HudLayout,
+    bitmaps: Vec<Bitmap>,
+    grin_ticks: u32,
+}
+
+impl Hud {
+    pub fn new(layout: HudLayout, renderer: &Renderer) -> Self {
+        let bitmaps = layout.sheets.iter().map(|sheet| renderer.load_bitmap(&sheet.image_id)).collect();
+
+        Hud {
+            layout,
+            bitmaps,
+            grin_ticks: 0,
+        }
+    }
+
+    pub fn handle_event(&mut self, event: InventoryEvent) {
+        if let InventoryEvent::Collected(Item::Weapon(_)) = event {
+            self.grin_ticks = GRIN_TICKS;
+        }
+    }
+
+    pub fn tick(&mut self) {
+        self.grin_ticks = self.grin_ticks.saturating_sub(1);
+    }
+
+    fn face_frame(&self, player: &Player) -> i32 {
+        let frame = if player.is_dead() {
+            FACE_DEAD
+        } else if self.grin_ticks > 0 {
+            FACE_GRIN
+        } else if player.invulnerable_ticks > 0 {
+            FACE_OUCH
+        } else {
+            let missing = (MAX_HEALTH - player.health) / MAX_HEALTH;
+            ((missing * FACE_HEALTH_LEVELS as f64) as usize).min(FACE_HEALTH_LEVELS - 1)
+        };
+        frame as i32
+    }
+
+    fn stat(player: &Player, weapon: WeaponKind, stat: Stat) -> Option<u32> {
+        match stat {
+            Stat::Health => Some(player.health.ceil() as u32),
+            Stat::Armor => Some(player.armor.ceil() as u32),
+            Stat::Ammo => weapon.ammo().map(|ammo| player.inventory.ammo(ammo)),
+            Stat::Score => Some(player.inventory.score),
+        }
+    }
+
+    pub fn draw(&self, renderer: &mut Renderer, player: &Player, weapon: WeaponKind) {
+        for element in &self.layout.elements {
+            match *element {
+                Element::Image { sheet, x, y } => self.draw_frame(renderer, sheet, 0, x, y),
+                Element::Number { stat, sheet, x, y, digits } => {
+                    let Some(value) = Hud::stat(player, weapon, stat) else {
+                        continue;
+                    };
+                    // Right aligned, clamped to the largest value that fits
+                    let text = value.min(10u32.saturating_pow(digits as u32) - 1).to_string();
+                    let frame_width = self.layout.sheets[sheet].frame_width;
+                    let start = x + (digits - text.len()) as i32 * frame_width;
+                    for (i, digit) in text.bytes().enumerate() {
+                        self.draw_frame(renderer, sheet, (digit - b'0') as i32, start + i as i32 * frame_width, y);
+                    }
+                }
+                Element::Face { sheet, x, y } => self.draw_frame(renderer, sheet, self.face_frame(player), x, y),
+                Element::Key { color, sheet, frame, x, y } => {
+                    if player.inventory.has_key(color) {
+                        self.draw_frame(renderer, sheet, frame, x, y);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Draws one frame of a sheet at a position in virtual HUD coordinates.
+    fn draw_frame(&self, renderer: &mut Renderer, sheet: usize, frame: i32, x: i32, y: i32) {
+        let scale_x = renderer.screen_width as f64 / self.layout.width as f64;
+        let scale_y = renderer.screen_height as f64 / self.layout.height as f64;
+        let Sheet { frame_width, frame_height, .. } = self.layout.sheets[sheet];
+
+        // Round both edges so neighbouring elements don't leave gaps between them
+        let left = (x as f64 * scale_x).round() as i32;
+        let top = (y as f64 * scale_y).round() as i32;
+        let right = ((x + frame_width) as f64 * scale_x).round() as i32;
+        let bottom = ((y + frame_height) as f64 * scale_y).round() as i32;
+
+        renderer.blit_bitmap(
+            &self.bitmaps[sheet],
+            Rect::new(frame * frame_width, 0, frame_width, frame_height),
+            Rect::new(left, top, right - left, bottom - top),
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bad_numbers_are_rejected() {
+        let layout = |line: &str| HudLayout::parse(&format!("sheet digits 8 8\n{}", line));
+        assert!(layout("number health digits 0 0 3").is_ok());
+        assert!(layout("number health digits 0 0 0").is_err());
+        assert!(layout("number health digits 0 0 -2").is_err());
+        assert!(layout("number health digits -1 0 3").is_err());
+        assert!(layout("image digits 0 -5").is_err());
+        assert!(layout("key red digits -1 0 0").is_err());
+        assert!(HudLayout::parse("sheet digits 0 8").is_err());
+        assert!(HudLayout::parse("screen 320 0").is_err());
+        assert!(HudLayout::parse(include_str!("../static/hud.cfg")).is_ok());
+    }
+}
@@ -0,0 +1,80 @@
+use crate::font::{Align, TextStyle};
+use crate::level::LevelStats;
+use crate::renderer::Renderer;
+use crate::simulation::TICKS_PER_SECOND;
+/// Ticks between each stats line appearing.
+const LINE_TICKS: u32 = 20;
+/// Ticks before the screen can be dismissed, so a held key doesn't skip straight past it.
+const MIN_TICKS: u32 = 60;
+
+/// The stats screen shown between finishing a level and starting the next.
+pub struct Intermission {
+    pub level_number: usize,
+    pub stats: LevelStats,
+    pub ticks: u32,
+    /// Whether the continue keys have been let go since the level ended.
+    pub released: bool,
+    /// True if that was the last level of the episode.
+    pub last: bool,
+}
+
+impl Intermission {
+    pub fn new(level_number: usize, stats: LevelStats, last: bool) -> Self {
+        Intermission {
+            level_number,
+            stats,
+            ticks: 0,
+            released: false,
+            last,
+        }
+    }
+
+    /// Advances the screen by a tick, returning true once the player asks to move on.
+    pub fn tick(&mut self, continue_held: bool) -> bool {
+        self.ticks += 1;
+        if !continue_held {
+            self.released = true;
+            return false;
+        }
+        self.released && self.ticks >= MIN_TICKS
+    }
+
+    pub fn draw(&self, renderer: &mut Renderer, level_name: &str, continue_key: &str) {
+        renderer.dim(0.8);
+
+        let center_x = renderer.screen_width as i32 / 2;
+        let scale = (renderer.screen_height as i32 / 150).max(1);
+        let line_height = renderer.font.line_height * scale * 3 / 2;
+        let mut y = renderer.screen_height as i32 / 6;
+
+        let title = if self.last { "Episode Complete".to_string() } else { format!("Level {} Complete", self.level_number + 1) };
+        let title_style = TextStyle { color: (230, 40, 30), align: Align::Center, scale: scale * 2, ..TextStyle::default() };
+        renderer.draw_text(center_x, y, &title, &title_style);
+        y += line_height * 2;
+
+        let style = TextStyle { color: (200, 200, 200), align: Align::Center, scale, ..TextStyle::default() };
+        renderer.draw_text(center_x, y, level_name, &style);
+        y += line_height * 2;
+
+        let stats = self.stats;
+        let seconds = stats.ticks / TICKS_PER_SECOND;
+        let lines = [
+            format!("Time  {}:{:02}", seconds / 60, seconds % 60),
+            format!("Kills  {} / {}", stats.kills, stats.total_kills),
+            format!("Items  {} / {}", stats.items, stats.total_items),
+            format!("Secrets  {} / {}", stats.secrets, stats.total_secrets),
+        ];
+        // Reveal the lines one after another
+        let shown = (self.ticks / LINE_TICKS) as usize;
+        let stat_style = TextStyle { color: (250, 220, 90), ..style };
+        for line in lines.iter().take(shown) {
+            renderer.draw_text(center_x, y, line, &stat_style);
+            y += line_height;
+        }
+
+        if self.ticks >= MIN_TICKS {
+            let prompt = format!("Press {} to continue", continue_key);
+            renderer.draw_text(center_x, renderer.screen_height as i32 * 4 / 5, &prompt, &style);
+        }
+    }
+}
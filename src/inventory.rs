@@ -0,0 +1,222 @@
+use crate::weapon::WeaponKind;
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum AmmoKind {
+    Rockets,
+    Mana,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum KeyColor {
+    Red,
+    Blue,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Item {
+    Health(f64),
+    Armor(f64),
+    Ammo(AmmoKind, u32),
+    Key(KeyColor),
+    Weapon(WeaponKind),
+    Treasure(u32),
+}
+
+impl Item {
+    pub fn name(self) -> &'static str {
+        match self {
+            Item::Health(_) => "medkit",
+            Item::Armor(_) => "armor",
+            Item::Ammo(AmmoKind::Rockets, _) => "rockets",
+            Item::Ammo(AmmoKind::Mana, _) => "mana",
+            Item::Key(KeyColor::Red) => "red key",
+            Item::Key(KeyColor::Blue) => "blue key",
+            Item::Weapon(WeaponKind::Sword) => "sword",
+            Item::Weapon(WeaponKind::RocketLauncher) => "rocket launcher",
+            Item::Weapon(WeaponKind::FireStaff) => "fire staff",
+            Item::Treasure(_) => "treasure",
+        }
+    }
+}
+
+/// Maximum amount of each stackable item the inventory can hold.
+#[derive(Clone, Copy)]
+pub struct StackLimits {
+    pub rockets: u32,
+    pub mana: u32,
+}
+
+impl StackLimits {
+    pub fn ammo(&self, kind: AmmoKind) -> u32 {
+        match kind {
+            AmmoKind::Rockets => self.rockets,
+            AmmoKind::Mana => self.mana,
+        }
+    }
+}
+
+impl Default for StackLimits {
+    fn default() -> Self {
+        StackLimits { rockets: 50, mana: 200 }
+    }
+}
+
+/// Things the HUD may want to react to, drained once per frame.
+#[derive(Clone, Copy, PartialEq)]
+pub enum InventoryEvent {
+    Collected(Item),
+    OutOfAmmo(AmmoKind),
+}
+
+pub struct Inventory {
+    pub limits: StackLimits,
+    pub rockets: u32,
+    pub mana: u32,
+    pub keys: Vec<KeyColor>,
+    pub weapons: Vec<WeaponKind>,
+    pub score: u32,
+    pub events: Vec<InventoryEvent>,
+}
+
+impl Inventory {
+    pub fn new(limits: StackLimits) -> Self {
+        Inventory {
+            limits,
+            rockets: 0,
+            mana: 0,
+            keys: Vec::new(),
+            weapons: vec![WeaponKind::Sword],
+            score: 0,
+            events: Vec::new(),
+        }
+    }
+
+    pub fn ammo(&self, kind: AmmoKind) -> u32 {
+        match kind {
+            AmmoKind::Rockets => self.rockets,
+            AmmoKind::Mana => self.mana,
+        }
+    }
+
+    fn ammo_mut(&mut self, kind: AmmoKind) -> &mut u32 {
+        match kind {
+            AmmoKind::Rockets => &mut self.rockets,
+            AmmoKind::Mana => &mut self.mana,
+        }
+    }
+
+    pub fn has_key(&self, color: KeyColor) -> bool {
+        self.keys.contains(&color)
+    }
+
+    pub fn has_weapon(&self, kind: WeaponKind) -> bool {
+        self.weapons.contains(&kind)
+    }
+
+    /// Adds an inventory item, returning false if there was no room for it.
+    /// Health and armor are not stored here; see `Player::give`.
+    pub fn add(&mut self, item: Item) -> bool {
+        match item {
+            Item::Health(_) | Item::Armor(_) => false,
+            Item::Ammo(kind, amount) => {
+                let limit = self.limits.ammo(kind);
+                let ammo = self.ammo_mut(kind);
+                if *ammo >= limit {
+                    return false;
+                }
+                *ammo = (*ammo + amount).min(limit);
+                true
+            }
+            Item::Key(color) => {
+                if self.has_key(color) {
+                    return false;
+                }
+                self.keys.push(color);
+                true
+            }
+            Item::Weapon(kind) => {
+                let had_weapon = self.has_weapon(kind);
+                if !had_weapon {
+                    self.weapons.push(kind);
+                }
+                // A weapon comes loaded, so it is still worth picking up for the ammo
+                let took_ammo = match kind.ammo() {
+                    Some(ammo) => self.add(Item::Ammo(ammo, kind.starting_ammo())),
+                    None => false,
+                };
+                !had_weapon || took_ammo
+            }
+            Item::Treasure(points) => {
+                self.score += points;
+                true
+            }
+        }
+    }
+
+    /// Spends ammo for one shot, returning false and raising an event if there isn't enough.
+    pub fn use_ammo(&mut self, kind: AmmoKind, amount: u32) -> bool {
+        let ammo = self.ammo_mut(kind);
+        if *ammo < amount {
+            self.events.push(InventoryEvent::OutOfAmmo(kind));
+            return false;
+        }
+        *ammo -= amount;
+        true
+    }
+
+    pub fn drain_events(&mut self) -> Vec<InventoryEvent> {
+        std::mem::take(&mut self.events)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn inventory() -> Inventory {
+        Inventory::new(StackLimits { rockets: 10, mana: 20 })
+    }
+
+    #[test]
+    fn ammo_stacks_up_to_its_limit() {
+        let mut inventory = inventory();
+        assert!(inventory.add(Item::Ammo(AmmoKind::Rockets, 6)));
+        assert!(inventory.add(Item::Ammo(AmmoKind::Rockets, 6)));
+        assert_eq!(inventory.rockets, 10);
+        assert_eq!(inventory.mana, 0);
+    }
+
+    #[test]
+    fn full_stacks_and_duplicates_are_refused() {
+        let mut inventory = inventory();
+        inventory.rockets = 10;
+        assert!(!inventory.add(Item::Ammo(AmmoKind::Rockets, 1)));
+
+        assert!(inventory.add(Item::Key(KeyColor::Red)));
+        assert!(!inventory.add(Item::Key(KeyColor::Red)));
+        assert_eq!(inventory.keys.len(), 1);
+
+        // A new weapon is always taken, and one already held only while its ammo has room
+        assert!(inventory.add(Item::Weapon(WeaponKind::RocketLauncher)));
+        assert!(!inventory.add(Item::Weapon(WeaponKind::RocketLauncher)));
+        assert!(!inventory.add(Item::Weapon(WeaponKind::Sword)));
+        assert!(inventory.add(Item::Weapon(WeaponKind::FireStaff)));
+        assert!(!inventory.add(Item::Weapon(WeaponKind::FireStaff)));
+        inventory.mana = 5;
+        assert!(inventory.add(Item::Weapon(WeaponKind::FireStaff)));
+        assert_eq!(inventory.mana, 20);
+    }
+
+    #[test]
+    fn running_out_of_ammo_raises_an_event() {
+        let mut inventory = inventory();
+        inventory.mana = 1;
+        assert!(inventory.use_ammo(AmmoKind::Mana, 1));
+        assert!(inventory.drain_events().is_empty());
+
+        assert!(!inventory.use_ammo(AmmoKind::Mana, 1));
+        assert_eq!(inventory.mana, 0);
+        assert!(inventory.drain_events() == [InventoryEvent::OutOfAmmo(AmmoKind::Mana)]);
+        assert!(inventory.events.is_empty());
+    }
+}
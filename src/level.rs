@@ -0,0 +1,180 @@
+use crate::dungeon::{self, DungeonConfig, DungeonMode};
+use crate::entities::Entity;
+use crate::game::Map;
+use crate::inventory::{AmmoKind, Item, KeyColor};
+use crate::maze::{self, MazeAlgorithm, MazeConfig};
+use crate::pickups::Pickup;
+use crate::rng::{Rng, Stream};
+use crate::sector;
+use crate::sprites::Sprite;
+use crate::weapon::{WeaponKind, SWORD_TEXTURE};
+
+/// Where a game's levels come from.
+#[derive(Clone, Copy, PartialEq)]
+pub enum LevelSource {
+    /// The hand-built starting map.
+    Classic,
+    Dungeon(DungeonMode),
+    /// A puzzle maze. Braided mazes have some dead ends knocked through into loops.
+    Maze { algorithm: MazeAlgorithm, braided: bool },
+    /// The hand-built level made of sectors, with sloping walls and floors at different heights.
+    Courtyard,
+}
+
+/// The levels of the campaign, played in order.
+pub const CAMPAIGN: [LevelSource; 5] = [
+    LevelSource::Classic,
+    LevelSource::Courtyard,
+    LevelSource::Dungeon(DungeonMode::Rooms),
+    LevelSource::Dungeon(DungeonMode::Caves),
+    LevelSource::Maze { algorithm: MazeAlgorithm::Wilson, braided: true },
+];
+
+/// The run of levels a game is made of.
+#[derive(Clone, Copy, PartialEq)]
+pub enum Episode {
+    Campaign,
+    /// Generated levels of one kind, one after another, for as long as the player keeps going.
+    Endless(LevelSource),
+}
+
+impl Episode {
+    pub fn name(self) -> &'static str {
+        match self {
+            Episode::Campaign => "Campaign",
+            Episode::Endless(source) => source.name(),
+        }
+    }
+
+    /// The level at `number` (counting from 0), or None once the episode is over.
+    pub fn level(self, number: usize) -> Option<LevelSource> {
+        match self {
+            Episode::Campaign => CAMPAIGN.get(number).copied(),
+            Episode::Endless(source) => Some(source),
+        }
+    }
+}
+
+/// The seed level `number` of a game is built from, so each level differs but the whole game
+/// still follows from one seed.
+pub fn level_seed(game_seed: u64, number: usize) -> u64 {
+    game_seed.wrapping_add((number as u64).wrapping_mul(0x9E3779B97F4A7C15))
+}
+
+/// Tallies shown on the intermission screen when a level is finished.
+#[derive(Clone, Copy, Default)]
+pub struct LevelStats {
+    pub ticks: u32,
+    pub kills: u32,
+    pub total_kills: u32,
+    pub items: u32,
+    pub total_items: u32,
+    pub secrets: u32,
+    pub total_secrets: u32,
+}
+
+/// Size of the grid the courtyard's sectors are laid over.
+const COURTYARD_WIDTH: usize = 11;
+const COURTYARD_HEIGHT: usize = 9;
+/// Cells behind the courtyard's exit wall, so the Use key finds the switch.
+const COURTYARD_EXITS: [(usize, usize); 2] = [(2, 8), (3, 8)];
+
+/// Fraction of dead ends opened up in a braided maze.
+const MAZE_BRAID: f64 = 0.6;
+
+impl LevelSource {
+    pub fn name(self) -> &'static str {
+        match self {
+            LevelSource::Classic => "Classic",
+            LevelSource::Dungeon(DungeonMode::Rooms) => "Dungeon",
+            LevelSource::Dungeon(DungeonMode::Caves) => "Caves",
+            LevelSource::Maze { braided: true, .. } => "Braided Maze",
+            LevelSource::Maze { algorithm: MazeAlgorithm::Backtracker, .. } => "Maze: Backtracker",
+            LevelSource::Maze { algorithm: MazeAlgorithm::Prim, .. } => "Maze: Prim",
+            LevelSource::Maze { algorithm: MazeAlgorithm::Wilson, .. } => "Maze: Wilson",
+            LevelSource::Courtyard => "Courtyard",
+        }
+    }
+
+    /// Builds the level. Generated levels come out the same every time for the same seed.
+    pub fn build(self, seed: u64) -> Level {
+        match self {
+            LevelSource::Classic => Level::classic(),
+            LevelSource::Dungeon(mode) => {
+                let config = DungeonConfig { mode, ..DungeonConfig::default() };
+                dungeon::generate(&config, &mut Rng::new(seed, Stream::Level))
+            }
+            LevelSource::Maze { algorithm, braided } => {
+                let braid = if braided { MAZE_BRAID } else { 0.0 };
+                let config = MazeConfig { algorithm, braid, ..MazeConfig::default() };
+                maze::build_level(&config, &mut Rng::new(seed, Stream::Level))
+            }
+            LevelSource::Courtyard => Level::courtyard(),
+        }
+    }
+}
+
+/// A map together with where the player starts and what is placed in it.
+pub struct Level {
+    pub map: Map,
+    pub spawn_x: f64,
+    pub spawn_y: f64,
+    pub spawn_direction: f64,
+    pub entities: Vec<Entity>,
+    pub pickups: Vec<Pickup>,
+    pub sprites: Vec<Sprite>,
+}
+
+impl Level {
+    pub fn classic() -> Self {
+        Level {
+            map: Map::new(),
+            spawn_x: 3.5,
+            spawn_y: 3.5,
+            spawn_direction: 0.0,
+            entities: vec![
+                Entity::barrel(5.5, 5.5),
+                Entity::barrel(6.0, 4.5),
+                // Up on the gallery, reached by the lift
+                Entity { storey: 1, ..Entity::barrel(3.5, 1.5) },
+            ],
+            pickups: vec![
+                Pickup::new(1.5, 3.5, Item::Weapon(WeaponKind::RocketLauncher)),
+                Pickup::new(1.5, 6.5, Item::Weapon(WeaponKind::FireStaff)),
+                Pickup::new(2.5, 6.5, Item::Ammo(AmmoKind::Mana, 20)),
+                Pickup::new(6.5, 1.5, Item::Ammo(AmmoKind::Rockets, 5)),
+                Pickup::new(6.5, 6.5, Item::Health(25.0)),
+                Pickup::new(5.5, 6.5, Item::Armor(50.0)),
+                Pickup::new(4.5, 1.5, Item::Key(KeyColor::Red)),
+                Pickup::new(2.5, 4.5, Item::Key(KeyColor::Blue)),
+                // In the nook behind the secret wall
+                Pickup::new(2.5, 2.5, Item::Treasure(100)),
+                Pickup { storey: 1, ..Pickup::new(1.5, 1.5, Item::Treasure(500)) },
+            ],
+            sprites: vec![
+                Sprite { x: 3.5, y: 5.5, storey: 0, texture_id: SWORD_TEXTURE, distance: 0.0 },
+            ],
+        }
+    }
+
+    pub fn courtyard() -> Self {
+        let sectors = sector::courtyard();
+        let mut map = sectors.to_grid(COURTYARD_WIDTH, COURTYARD_HEIGHT, &COURTYARD_EXITS);
+        map.sectors = Some(sectors);
+        Level {
+            map,
+            spawn_x: 3.0,
+            spawn_y: 3.0,
+            spawn_direction: 0.0,
+            entities: vec![
+                Entity::barrel(3.0, 6.5),
+            ],
+            pickups: vec![
+                Pickup::new(8.0, 3.0, Item::Health(25.0)),
+                Pickup::new(7.5, 2.0, Item::Ammo(AmmoKind::Rockets, 5)),
+                Pickup::new(1.8, 4.5, Item::Treasure(100)),
+            ],
+            sprites: Vec::new(),
+        }
+    }
+}
@@ -7,6 +7,27 @@ mod utils;
 mod game;
 mod raycasting;
 mod sprites;
+mod entities;
+mod weapon;
+mod inventory;
+mod pickups;
+mod hud;
+mod font;
+mod settings;
+mod menu;
+mod save;
+mod rng;
+mod replay;
+mod simulation;
+mod level;
+mod dungeon;
+mod maze;
+mod intermission;
+mod pushwall;
+mod thin_wall;
+mod portal;
+mod lift;
+mod sector;
 
 
 #[wasm_bindgen]
@@ -0,0 +1,25 @@
+/// Wall heights a lift carries the player per tick.
+pub const LIFT_SPEED: f64 = 0.05;
+
+/// A cell that carries the player between two storeys: stepping onto it on one takes them to the
+/// same cell on the other.
+#[derive(Clone, Copy, PartialEq)]
+pub struct Lift {
+    pub x: i32,
+    pub y: i32,
+    /// The lower of the two storeys it joins. The other is the one directly above.
+    pub storey: usize,
+}
+
+impl Lift {
+    /// The storey the lift takes a player who steps onto it on `storey`, if it stops there.
+    pub fn destination(&self, storey: usize) -> Option<usize> {
+        if storey == self.storey {
+            Some(self.storey + 1)
+        } else if storey == self.storey + 1 {
+            Some(self.storey)
+        } else {
+            None
+        }
+    }
+}
@@ -0,0 +1,350 @@
+use crate::dungeon::{self, Contents};
+use crate::game::Map;
+use crate::level::Level;
+use crate::rng::Rng;
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum MazeAlgorithm {
+    /// Depth-first search: long winding corridors with few branches.
+    Backtracker,
+    /// Randomized Prim's: many short dead ends branching off everywhere.
+    Prim,
+    /// Loop-erased random walks: an unbiased pick among all possible perfect mazes.
+    Wilson,
+}
+
+pub struct MazeConfig {
+    pub algorithm: MazeAlgorithm,
+    /// Size of the maze in cells. Each cell becomes a `corridor_width` square of open floor.
+    pub columns: usize,
+    pub rows: usize,
+    pub corridor_width: usize,
+    /// Fraction of dead ends knocked through to make loops. 0 leaves a perfect maze with exactly
+    /// one route between any two points; 1 removes every dead end.
+    pub braid: f64,
+    /// Each stretch of wall between two cells gets one of these, picked at random.
+    pub wall_textures: Vec<u8>,
+    pub contents: Contents,
+}
+
+impl Default for MazeConfig {
+    fn default() -> Self {
+        MazeConfig {
+            algorithm: MazeAlgorithm::Backtracker,
+            columns: 12,
+            rows: 12,
+            corridor_width: 1,
+            braid: 0.0,
+            wall_textures: vec![1, 2, 3],
+            contents: Contents {
+                barrels: 0,
+                pickups: 8,
+                decorations: 0,
+                // Maze walls are a single cell thick, leaving nowhere to hide a closet
+                secrets: 0,
+            },
+        }
+    }
+}
+
+/// A grid of cells and which of the walls between them are open.
+pub struct Maze {
+    pub columns: usize,
+    pub rows: usize,
+    /// Whether each cell has a passage to the cell on its right.
+    pub open_east: Vec<bool>,
+    /// Whether each cell has a passage to the cell below it.
+    pub open_south: Vec<bool>,
+}
+
+impl Maze {
+    fn closed(columns: usize, rows: usize) -> Self {
+        Maze {
+            columns,
+            rows,
+            open_east: vec![false; columns * rows],
+            open_south: vec![false; columns * rows],
+        }
+    }
+
+    /// The cells beside `cell`, whether or not there is a passage to them.
+    fn neighbours(&self, cell: usize) -> Vec<usize> {
+        let (x, y) = (cell % self.columns, cell / self.columns);
+        let mut neighbours = Vec::with_capacity(4);
+        if x > 0 {
+            neighbours.push(cell - 1);
+        }
+        if x + 1 < self.columns {
+            neighbours.push(cell + 1);
+        }
+        if y > 0 {
+            neighbours.push(cell - self.columns);
+        }
+        if y + 1 < self.rows {
+            neighbours.push(cell + self.columns);
+        }
+        neighbours
+    }
+
+    pub fn is_open(&self, a: usize, b: usize) -> bool {
+        let (low, high) = (a.min(b), a.max(b));
+        if high == low + 1 {
+            self.open_east[low]
+        } else {
+            self.open_south[low]
+        }
+    }
+
+    /// Knocks down the wall between two neighbouring cells.
+    pub fn connect(&mut self, a: usize, b: usize) {
+        let (low, high) = (a.min(b), a.max(b));
+        if high == low + 1 {
+            self.open_east[low] = true;
+        } else {
+            self.open_south[low] = true;
+        }
+    }
+
+    fn passages(&self, cell: usize) -> usize {
+        self.neighbours(cell).into_iter().filter(|next| self.is_open(cell, *next)).count()
+    }
+
+    /// Opens up a fraction of the dead ends, preferring to join them to other dead ends so each
+    /// opening removes two at once.
+    pub fn braid(&mut self, fraction: f64, rng: &mut Rng) {
+        let mut cells: Vec<usize> = (0..self.columns * self.rows).collect();
+        rng.shuffle(&mut cells);
+
+        for cell in cells {
+            // Earlier openings may already have fixed this one
+            if self.passages(cell) != 1 || rng.next_f64() >= fraction {
+                continue;
+            }
+            let closed: Vec<usize> = self.neighbours(cell).into_iter().filter(|next| !self.is_open(cell, *next)).collect();
+            let dead_ends: Vec<usize> = closed.iter().copied().filter(|next| self.passages(*next) == 1).collect();
+            let choices = if dead_ends.is_empty() { closed } else { dead_ends };
+            if !choices.is_empty() {
+                self.connect(cell, choices[rng.below(choices.len())]);
+            }
+        }
+    }
+}
+
+/// Generates a perfect maze, with exactly one route between any two cells.
+pub fn generate(algorithm: MazeAlgorithm, columns: usize, rows: usize, rng: &mut Rng) -> Maze {
+    let mut maze = Maze::closed(columns, rows);
+    match algorithm {
+        MazeAlgorithm::Backtracker => backtracker(&mut maze, rng),
+        MazeAlgorithm::Prim => prim(&mut maze, rng),
+        MazeAlgorithm::Wilson => wilson(&mut maze, rng),
+    }
+    maze
+}
+
+fn backtracker(maze: &mut Maze, rng: &mut Rng) {
+    let mut visited = vec![false; maze.columns * maze.rows];
+    let start = rng.below(visited.len());
+    let mut stack = vec![start];
+    visited[start] = true;
+
+    while let Some(&cell) = stack.last() {
+        let unvisited: Vec<usize> = maze.neighbours(cell).into_iter().filter(|next| !visited[*next]).collect();
+        if unvisited.is_empty() {
+            stack.pop();
+            continue;
+        }
+        let next = unvisited[rng.below(unvisited.len())];
+        maze.connect(cell, next);
+        visited[next] = true;
+        stack.push(next);
+    }
+}
+
+fn prim(maze: &mut Maze, rng: &mut Rng) {
+    let mut in_maze = vec![false; maze.columns * maze.rows];
+    let mut frontier = vec![rng.below(in_maze.len())];
+
+    while !frontier.is_empty() {
+        let cell = frontier.swap_remove(rng.below(frontier.len()));
+        if in_maze[cell] {
+            continue;
+        }
+
+        // Join the new cell to a random part of the maze next to it (the very first cell has none)
+        let joined: Vec<usize> = maze.neighbours(cell).into_iter().filter(|next| in_maze[*next]).collect();
+        if !joined.is_empty() {
+            maze.connect(cell, joined[rng.below(joined.len())]);
+        }
+        in_maze[cell] = true;
+        frontier.extend(maze.neighbours(cell).into_iter().filter(|next| !in_maze[*next]));
+    }
+}
+
+fn wilson(maze: &mut Maze, rng: &mut Rng) {
+    let count = maze.columns * maze.rows;
+    let mut in_maze = vec![false; count];
+    in_maze[rng.below(count)] = true;
+    // The way out of each cell on the current walk. Revisiting a cell overwrites it, which erases the loop.
+    let mut exit = vec![0; count];
+
+    for start in 0..count {
+        if in_maze[start] {
+            continue;
+        }
+
+        let mut cell = start;
+        while !in_maze[cell] {
+            let neighbours = maze.neighbours(cell);
+            exit[cell] = neighbours[rng.below(neighbours.len())];
+            cell = exit[cell];
+        }
+
+        let mut cell = start;
+        while !in_maze[cell] {
+            maze.connect(cell, exit[cell]);
+            in_maze[cell] = true;
+            cell = exit[cell];
+        }
+    }
+}
+
+/// Builds a playable level from a maze: spawn in the top-left corner, exit switch at the far end.
+pub fn build_level(config: &MazeConfig, rng: &mut Rng) -> Level {
+    let mut maze = generate(config.algorithm, config.columns, config.rows, rng);
+    if config.braid > 0.0 {
+        maze.braid(config.braid, rng);
+    }
+
+    let map = to_map(&maze, config.corridor_width, &config.wall_textures, rng);
+    // The middle of the first cell's block, which starts one cell in from the corner
+    let middle = 1 + config.corridor_width.max(1) / 2;
+    let spawn = middle * map.width + middle;
+    dungeon::populate(map, spawn, &config.contents, rng)
+}
+
+/// Writes the maze into a wall grid. Cells become `corridor_width` squares of floor with one cell
+/// of wall between them; every stretch of wall between two cells gets its own texture.
+pub fn to_map(maze: &Maze, corridor_width: usize, wall_textures: &[u8], rng: &mut Rng) -> Map {
+    let corridor_width = corridor_width.max(1);
+    // With no textures given, fall back to the first one rather than leaving holes in the walls
+    let wall_textures = if wall_textures.is_empty() { &[1] } else { wall_textures };
+    let pitch = corridor_width + 1;
+    let mut map = Map::filled(maze.columns * pitch + 1, maze.rows * pitch + 1, wall_textures[0]);
+    for cell in 0..maze.columns * maze.rows {
+        let (column, row) = (cell % maze.columns, cell / maze.columns);
+        let (x, y) = (1 + column * pitch, 1 + row * pitch);
+        fill(&mut map, x, y, corridor_width, corridor_width, 0);
+
+        // The wall segments to the right of and below this cell, plus the outer walls along the top
+        // and left. The outer edge is never opened, so the map stays enclosed.
+        let open_east = column + 1 < maze.columns && maze.open_east[cell];
+        let open_south = row + 1 < maze.rows && maze.open_south[cell];
+        let mut segments = vec![
+            (open_east, x + corridor_width, y, 1, corridor_width),
+            (open_south, x, y + corridor_width, corridor_width, 1),
+        ];
+        if column == 0 {
+            segments.push((false, 0, y, 1, corridor_width));
+        }
+        if row == 0 {
+            segments.push((false, x, 0, corridor_width, 1));
+        }
+
+        for (open, x, y, width, height) in segments {
+            let cell = if open { 0 } else { wall_textures[rng.below(wall_textures.len())] };
+            fill(&mut map, x, y, width, height, cell);
+        }
+    }
+
+    map
+}
+
+fn fill(map: &mut Map, x: usize, y: usize, width: usize, height: usize, cell: u8) {
+    for y in y..y + height {
+        for x in x..x + width {
+            map.set(x, y, cell);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rng::Stream;
+
+    const ALGORITHMS: [MazeAlgorithm; 3] = [MazeAlgorithm::Backtracker, MazeAlgorithm::Prim, MazeAlgorithm::Wilson];
+
+    /// Every cell reachable from the first, and how many passages there are in all.
+    fn explore(maze: &Maze) -> (Vec<bool>, usize) {
+        let count = maze.columns * maze.rows;
+        let passages = (0..count).map(|cell| maze.passages(cell)).sum::<usize>() / 2;
+        let mut reached = vec![false; count];
+        let mut stack = vec![0];
+        reached[0] = true;
+        while let Some(cell) = stack.pop() {
+            for next in maze.neighbours(cell) {
+                if maze.is_open(cell, next) && !reached[next] {
+                    reached[next] = true;
+                    stack.push(next);
+                }
+            }
+        }
+        (reached, passages)
+    }
+
+    #[test]
+    fn mazes_are_perfect() {
+        for algorithm in ALGORITHMS {
+            for seed in 0..10 {
+                let (columns, rows) = (3 + seed as usize, 12 - seed as usize);
+                let maze = generate(algorithm, columns, rows, &mut Rng::new(seed, Stream::Level));
+                // Connected with one fewer passage than cells is a spanning tree: no loops
+                let (reached, passages) = explore(&maze);
+                assert!(reached.iter().all(|reached| *reached));
+                assert_eq!(passages, columns * rows - 1);
+            }
+        }
+    }
+
+    #[test]
+    fn maze_maps_are_enclosed_and_connected() {
+        for corridor_width in 1..=3 {
+            let mut rng = Rng::new(corridor_width as u64, Stream::Level);
+            let maze = generate(MazeAlgorithm::Backtracker, 5, 4, &mut rng);
+            let map = to_map(&maze, corridor_width, &[1, 2], &mut rng);
+            assert_eq!((map.width, map.height), (5 * (corridor_width + 1) + 1, 4 * (corridor_width + 1) + 1));
+
+            for x in 0..map.width as i32 {
+                for y in 0..map.height as i32 {
+                    let edge = x == 0 || y == 0 || x == map.width as i32 - 1 || y == map.height as i32 - 1;
+                    assert!(!edge || map.get(x, y) != Some(0));
+                }
+            }
+
+            let middle = 1 + corridor_width / 2;
+            let reached = dungeon::distances(&map, middle * map.width + middle);
+            for (index, cell) in map.cells.iter().enumerate() {
+                assert_eq!(*cell == 0, reached[index].is_some());
+            }
+        }
+    }
+
+    #[test]
+    fn empty_wall_textures_fall_back_to_the_first() {
+        let mut rng = Rng::new(0, Stream::Level);
+        let maze = generate(MazeAlgorithm::Prim, 3, 3, &mut rng);
+        let map = to_map(&maze, 2, &[], &mut rng);
+        assert!(map.cells.iter().all(|cell| *cell == 0 || *cell == 1));
+    }
+
+    #[test]
+    fn full_braiding_leaves_no_dead_ends() {
+        for algorithm in ALGORITHMS {
+            let mut rng = Rng::new(3, Stream::Level);
+            let mut maze = generate(algorithm, 10, 10, &mut rng);
+            maze.braid(1.0, &mut rng);
+            assert!((0..100).all(|cell| maze.passages(cell) > 1));
+            assert!(explore(&maze).0.iter().all(|reached| *reached));
+        }
+    }
+}
@@ -0,0 +1,424 @@
+use crate::dungeon::DungeonMode;
+use crate::font::{Align, TextStyle};
+use crate::level::{Episode, LevelSource};
+use crate::maze::MazeAlgorithm;
+use crate::renderer::{Renderer, TextureFilter};
+use crate::settings::{
+    key_name, Action, Settings, KEY_DOWN, KEY_ENTER, KEY_ESCAPE, KEY_LEFT, KEY_RIGHT, KEY_UP, MAX_FOV_DEGREES,
+    MAX_RESOLUTION_SCALE, MAX_SENSITIVITY, MIN_FOV_DEGREES, MIN_RESOLUTION_SCALE, MIN_SENSITIVITY,
+};
+
+const SENSITIVITY_STEP: f64 = 0.25;
+const FOV_STEP: f64 = 5.0;
+const RESOLUTION_SCALE_STEP: f64 = 0.25;
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Screen {
+    Title,
+    Pause,
+    NewGame,
+    Options,
+    Bindings,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum MenuInput {
+    Up,
+    Down,
+    Left,
+    Right,
+    Select,
+    Back,
+}
+
+impl MenuInput {
+    pub fn from_key(key_code: usize) -> Option<Self> {
+        match key_code {
+            KEY_UP => Some(MenuInput::Up),
+            KEY_DOWN => Some(MenuInput::Down),
+            KEY_LEFT => Some(MenuInput::Left),
+            KEY_RIGHT => Some(MenuInput::Right),
+            KEY_ENTER => Some(MenuInput::Select),
+            KEY_ESCAPE => Some(MenuInput::Back),
+            _ => None,
+        }
+    }
+}
+
+/// What the engine needs to do in response to a menu selection.
+#[derive(Clone, Copy, PartialEq)]
+pub enum MenuCommand {
+    NewGame(Episode),
+    Continue,
+    QuitToTitle,
+    SaveGame,
+    LoadGame,
+    SettingsChanged,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Item {
+    NewGame,
+    Start(Episode),
+    Continue,
+    SaveGame,
+    LoadGame,
+    Options,
+    QuitToTitle,
+    Sensitivity,
+    Fov,
+    ResolutionScale,
+    TextureFilter,
+    KeyBindings,
+    Bind(Action),
+    Back,
+}
+
+impl Screen {
+    fn title(self) -> &'static str {
+        match self {
+            Screen::Title => "RAYCASTER",
+            Screen::Pause => "Paused",
+            Screen::NewGame => "New Game",
+            Screen::Options => "Options",
+            Screen::Bindings => "Key Bindings",
+        }
+    }
+
+    fn items(self) -> Vec<Item> {
+        match self {
+            Screen::Title => vec![Item::NewGame, Item::LoadGame, Item::Options],
+            Screen::Pause => vec![
+                Item::Continue,
+                Item::SaveGame,
+                Item::LoadGame,
+                Item::NewGame,
+                Item::Options,
+                Item::QuitToTitle,
+            ],
+            Screen::NewGame => vec![
+                Item::Start(Episode::Campaign),
+                Item::Start(Episode::Endless(LevelSource::Dungeon(DungeonMode::Rooms))),
+                Item::Start(Episode::Endless(LevelSource::Dungeon(DungeonMode::Caves))),
+                Item::Start(Episode::Endless(LevelSource::Maze { algorithm: MazeAlgorithm::Backtracker, braided: false })),
+                Item::Start(Episode::Endless(LevelSource::Maze { algorithm: MazeAlgorithm::Prim, braided: false })),
+                Item::Start(Episode::Endless(LevelSource::Maze { algorithm: MazeAlgorithm::Wilson, braided: false })),
+                Item::Start(Episode::Endless(LevelSource::Maze { algorithm: MazeAlgorithm::Backtracker, braided: true })),
+                Item::Back,
+            ],
+            Screen::Options => vec![
+                Item::Sensitivity,
+                Item::Fov,
+                Item::ResolutionScale,
+                Item::TextureFilter,
+                Item::KeyBindings,
+                Item::Back,
+            ],
+            Screen::Bindings => Action::ALL.iter().map(|action| Item::Bind(*action)).chain([Item::Back]).collect(),
+        }
+    }
+}
+
+/// The menu state machine. While any screen is open the simulation is paused.
+pub struct Menu {
+    /// Open screens, with the one being shown last.
+    stack: Vec<Screen>,
+    selected: usize,
+    /// The action waiting for a key press to be bound to it.
+    rebinding: Option<Action>,
+}
+
+impl Menu {
+    pub fn new(screen: Screen) -> Self {
+        Menu {
+            stack: vec![screen],
+            selected: 0,
+            rebinding: None,
+        }
+    }
+
+    pub fn is_open(&self) -> bool {
+        !self.stack.is_empty()
+    }
+
+    pub fn is_rebinding(&self) -> bool {
+        self.rebinding.is_some()
+    }
+
+    /// Replaces whatever is showing with a fresh screen.
+    pub fn open(&mut self, screen: Screen) {
+        self.stack = vec![screen];
+        self.selected = 0;
+        self.rebinding = None;
+    }
+
+    pub fn close(&mut self) {
+        self.stack.clear();
+        self.rebinding = None;
+    }
+
+    fn push(&mut self, screen: Screen) {
+        self.stack.push(screen);
+        self.selected = 0;
+    }
+
+    fn pop(&mut self) {
+        let popped = self.stack.pop();
+        // Return to the item that opened the screen we just left
+        let parent_items = self.stack.last().map(|screen| screen.items()).unwrap_or_default();
+        self.selected = match popped {
+            Some(Screen::NewGame) => parent_items.iter().position(|item| *item == Item::NewGame),
+            Some(Screen::Options) => parent_items.iter().position(|item| *item == Item::Options),
+            Some(Screen::Bindings) => parent_items.iter().position(|item| *item == Item::KeyBindings),
+            _ => None,
+        }
+        .unwrap_or(0);
+    }
+
+    /// Binds the action waiting for a key to `key_code`. Escape cancels without changing anything.
+    pub fn rebind(&mut self, key_code: usize, settings: &mut Settings) -> Option<MenuCommand> {
+        let action = self.rebinding.take()?;
+        if key_code == KEY_ESCAPE {
+            return None;
+        }
+        settings.bindings.bind(action, key_code);
+        Some(MenuCommand::SettingsChanged)
+    }
+
+    pub fn handle_input(&mut self, input: MenuInput, settings: &mut Settings) -> Option<MenuCommand> {
+        let screen = *self.stack.last()?;
+        let items = screen.items();
+        let item = items[self.selected.min(items.len() - 1)];
+
+        match input {
+            MenuInput::Up => {
+                self.selected = (self.selected + items.len() - 1) % items.len();
+                None
+            }
+            MenuInput::Down => {
+                self.selected = (self.selected + 1) % items.len();
+                None
+            }
+            MenuInput::Left | MenuInput::Right => {
+                let direction = if input == MenuInput::Left { -1.0 } else { 1.0 };
+                Menu::adjust(item, direction, settings)
+            }
+            MenuInput::Back => match screen {
+                Screen::Title => None,
+                Screen::Pause => {
+                    self.close();
+                    Some(MenuCommand::Continue)
+                }
+                Screen::NewGame | Screen::Options | Screen::Bindings => {
+                    self.pop();
+                    None
+                }
+            },
+            MenuInput::Select => match item {
+                Item::NewGame => {
+                    self.push(Screen::NewGame);
+                    None
+                }
+                Item::Start(source) => {
+                    self.close();
+                    Some(MenuCommand::NewGame(source))
+                }
+                Item::Continue => {
+                    self.close();
+                    Some(MenuCommand::Continue)
+                }
+                Item::SaveGame => {
+                    self.close();
+                    Some(MenuCommand::SaveGame)
+                }
+                // The engine closes the menu once the save has loaded
+                Item::LoadGame => Some(MenuCommand::LoadGame),
+                Item::Options => {
+                    self.push(Screen::Options);
+                    None
+                }
+                Item::QuitToTitle => {
+                    self.open(Screen::Title);
+                    Some(MenuCommand::QuitToTitle)
+                }
+                Item::KeyBindings => {
+                    self.push(Screen::Bindings);
+                    None
+                }
+                Item::Bind(action) => {
+                    self.rebinding = Some(action);
+                    None
+                }
+                Item::Back => {
+                    self.pop();
+                    None
+                }
+                Item::Sensitivity | Item::Fov | Item::ResolutionScale | Item::TextureFilter => Menu::adjust(item, 1.0, settings),
+            },
+        }
+    }
+
+    /// Steps a setting up or down, wrapping round at the ends of its range.
+    fn adjust(item: Item, direction: f64, settings: &mut Settings) -> Option<MenuCommand> {
+        let step = |value: f64, step: f64, min: f64, max: f64| {
+            let next = value + step * direction;
+            if next > max + 1e-9 {
+                min
+            } else if next < min - 1e-9 {
+                max
+            } else {
+                next
+            }
+        };
+
+        match item {
+            Item::Sensitivity => {
+                settings.sensitivity = step(settings.sensitivity, SENSITIVITY_STEP, MIN_SENSITIVITY, MAX_SENSITIVITY);
+            }
+            Item::Fov => {
+                settings.fov_degrees = step(settings.fov_degrees, FOV_STEP, MIN_FOV_DEGREES, MAX_FOV_DEGREES);
+            }
+            Item::ResolutionScale => {
+                settings.resolution_scale = step(
+                    settings.resolution_scale,
+                    RESOLUTION_SCALE_STEP,
+                    MIN_RESOLUTION_SCALE,
+                    MAX_RESOLUTION_SCALE,
+                );
+            }
+            Item::TextureFilter => {
+                let filters = TextureFilter::ALL;
+                let index = filters.iter().position(|filter| *filter == settings.texture_filter).unwrap_or(0);
+                let next = (index as isize + direction as isize).rem_euclid(filters.len() as isize);
+                settings.texture_filter = filters[next as usize];
+            }
+            _ => return None,
+        }
+
+        Some(MenuCommand::SettingsChanged)
+    }
+
+    fn label(&self, item: Item, settings: &Settings) -> String {
+        match item {
+            Item::NewGame => "New Game".to_string(),
+            Item::Start(episode) => episode.name().to_string(),
+            Item::Continue => "Continue".to_string(),
+            Item::SaveGame => "Save Game".to_string(),
+            Item::LoadGame => "Load Game".to_string(),
+            Item::Options => "Options".to_string(),
+            Item::QuitToTitle => "Quit to Title".to_string(),
+            Item::Sensitivity => format!("Sensitivity < {:.2} >", settings.sensitivity),
+            Item::Fov => format!("Field of View < {} >", settings.fov_degrees as i32),
+            Item::ResolutionScale => format!("Resolution < {}% >", (settings.resolution_scale * 100.0) as i32),
+            Item::TextureFilter => format!("Textures < {} >", settings.texture_filter.name()),
+            Item::KeyBindings => "Key Bindings".to_string(),
+            Item::Bind(action) if self.rebinding == Some(action) => format!("{}: press a key", action.name()),
+            Item::Bind(action) => format!("{}: {}", action.name(), key_name(settings.bindings.key(action))),
+            Item::Back => "Back".to_string(),
+        }
+    }
+
+    /// Dims the scene and draws the current screen over it.
+    pub fn draw(&self, renderer: &mut Renderer, settings: &Settings) {
+        let Some(screen) = self.stack.last() else {
+            return;
+        };
+
+        renderer.dim(0.4);
+
+        let center_x = renderer.screen_width as i32 / 2;
+        let scale = (renderer.screen_height as i32 / 150).max(1);
+        let line_height = renderer.font.line_height * scale * 3 / 2;
+        let mut y = renderer.screen_height as i32 / 5;
+
+        let title_style = TextStyle { color: (230, 40, 30), align: Align::Center, scale: scale * 2, ..TextStyle::default() };
+        renderer.draw_text(center_x, y, screen.title(), &title_style);
+        y += line_height * 2;
+
+        // Close the lines up on long lists so they still fit above the bottom of the screen
+        let items = screen.items();
+        let room = renderer.screen_height as i32 - y - line_height / 2;
+        let line_height = line_height.min(room / items.len() as i32).max(renderer.font.line_height * scale);
+
+        for (index, item) in items.into_iter().enumerate() {
+            let selected = index == self.selected;
+            let style = TextStyle {
+                color: if selected { (250, 220, 90) } else { (200, 200, 200) },
+                align: Align::Center,
+                scale,
+                ..TextStyle::default()
+            };
+            let label = self.label(item, settings);
+            let text = if selected { format!("> {} <", label) } else { label };
+            renderer.draw_text(center_x, y, &text, &style);
+            y += line_height;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn press(menu: &mut Menu, settings: &mut Settings, inputs: &[MenuInput]) -> Option<MenuCommand> {
+        inputs.iter().map(|input| menu.handle_input(*input, settings)).last().flatten()
+    }
+
+    #[test]
+    fn selection_wraps_around_both_ends() {
+        let (mut menu, mut settings) = (Menu::new(Screen::Title), Settings::default());
+        press(&mut menu, &mut settings, &[MenuInput::Up]);
+        assert_eq!(menu.selected, Screen::Title.items().len() - 1);
+        press(&mut menu, &mut settings, &[MenuInput::Down]);
+        assert_eq!(menu.selected, 0);
+    }
+
+    #[test]
+    fn backing_out_returns_to_the_item_that_opened_the_screen() {
+        let (mut menu, mut settings) = (Menu::new(Screen::Pause), Settings::default());
+        let position = |screen: Screen, wanted: Item| screen.items().iter().position(|item| *item == wanted).unwrap();
+        let (options, bindings) = (position(Screen::Pause, Item::Options), position(Screen::Options, Item::KeyBindings));
+
+        menu.selected = options;
+        press(&mut menu, &mut settings, &[MenuInput::Select]);
+        assert!(menu.stack == [Screen::Pause, Screen::Options]);
+        assert_eq!(menu.selected, 0);
+
+        menu.selected = bindings;
+        press(&mut menu, &mut settings, &[MenuInput::Select, MenuInput::Back]);
+        assert!(menu.stack == [Screen::Pause, Screen::Options]);
+        assert_eq!(menu.selected, bindings);
+        press(&mut menu, &mut settings, &[MenuInput::Back]);
+        assert!(menu.stack == [Screen::Pause]);
+        assert_eq!(menu.selected, options);
+
+        // Backing out of the pause menu itself resumes the game
+        assert!(press(&mut menu, &mut settings, &[MenuInput::Back]) == Some(MenuCommand::Continue));
+        assert!(!menu.is_open());
+    }
+
+    #[test]
+    fn rebinding_takes_the_next_key_unless_it_is_escape() {
+        let (mut menu, mut settings) = (Menu::new(Screen::Bindings), Settings::default());
+        press(&mut menu, &mut settings, &[MenuInput::Down, MenuInput::Select]);
+        assert!(menu.is_rebinding());
+        assert!(menu.rebind(KEY_ESCAPE, &mut settings).is_none());
+        assert!(!menu.is_rebinding());
+        assert_eq!(settings.bindings.key(Action::Backward), b'S' as usize);
+
+        press(&mut menu, &mut settings, &[MenuInput::Select]);
+        assert!(menu.rebind(b'K' as usize, &mut settings) == Some(MenuCommand::SettingsChanged));
+        assert_eq!(settings.bindings.key(Action::Backward), b'K' as usize);
+        assert!(menu.rebind(b'J' as usize, &mut settings).is_none());
+    }
+
+    #[test]
+    fn settings_wrap_round_at_the_ends_of_their_range() {
+        let (mut menu, mut settings) = (Menu::new(Screen::Options), Settings::default());
+        settings.sensitivity = MAX_SENSITIVITY;
+        assert!(press(&mut menu, &mut settings, &[MenuInput::Right]) == Some(MenuCommand::SettingsChanged));
+        assert_eq!(settings.sensitivity, MIN_SENSITIVITY);
+        press(&mut menu, &mut settings, &[MenuInput::Left]);
+        assert_eq!(settings.sensitivity, MAX_SENSITIVITY);
+    }
+}
@@ -0,0 +1,44 @@
+use crate::inventory::{AmmoKind, Item, KeyColor};
+use crate::weapon::{WeaponKind, LAUNCHER_TEXTURE, STAFF_TEXTURE, SWORD_TEXTURE};
+
+// Texture ids as loaded by `Engine::new` (1-based, like the map)
+pub const MEDKIT_TEXTURE: usize = 11;
+pub const ARMOR_TEXTURE: usize = 12;
+pub const ROCKET_AMMO_TEXTURE: usize = 13;
+pub const MANA_TEXTURE: usize = 14;
+pub const RED_KEY_TEXTURE: usize = 15;
+pub const BLUE_KEY_TEXTURE: usize = 16;
+pub const TREASURE_TEXTURE: usize = 17;
+
+/// Distance from the player's edge at which an item is picked up.
+pub const PICKUP_RADIUS: f64 = 0.3;
+
+/// An item lying in the world, collected when the player walks over it.
+pub struct Pickup {
+    pub x: f64,
+    pub y: f64,
+    pub storey: usize,
+    pub item: Item,
+}
+
+impl Pickup {
+    /// An item lying on the ground floor.
+    pub fn new(x: f64, y: f64, item: Item) -> Self {
+        Pickup { x, y, storey: 0, item }
+    }
+
+    pub fn texture_id(&self) -> usize {
+        match self.item {
+            Item::Health(_) => MEDKIT_TEXTURE,
+            Item::Armor(_) => ARMOR_TEXTURE,
+            Item::Ammo(AmmoKind::Rockets, _) => ROCKET_AMMO_TEXTURE,
+            Item::Ammo(AmmoKind::Mana, _) => MANA_TEXTURE,
+            Item::Key(KeyColor::Red) => RED_KEY_TEXTURE,
+            Item::Key(KeyColor::Blue) => BLUE_KEY_TEXTURE,
+            Item::Weapon(WeaponKind::Sword) => SWORD_TEXTURE,
+            Item::Weapon(WeaponKind::RocketLauncher) => LAUNCHER_TEXTURE,
+            Item::Weapon(WeaponKind::FireStaff) => STAFF_TEXTURE,
+            Item::Treasure(_) => TREASURE_TEXTURE,
+        }
+    }
+}
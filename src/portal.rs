@@ -0,0 +1,103 @@
+/// One face of a wall cell, as the doorway of a portal.
+#[derive(Clone, Copy, PartialEq)]
+pub struct PortalFace {
+    /// The wall cell the face belongs to.
+    pub x: i32,
+    pub y: i32,
+    /// Unit step from the cell out through the face, into the open cell it looks onto.
+    pub normal: (i32, i32),
+}
+
+impl PortalFace {
+    /// The middle of the face.
+    pub fn centre(&self) -> (f64, f64) {
+        (
+            self.x as f64 + 0.5 + self.normal.0 as f64 * 0.5,
+            self.y as f64 + 0.5 + self.normal.1 as f64 * 0.5,
+        )
+    }
+}
+
+/// A pair of wall faces joined so that going into one comes straight out of the other, turned to
+/// face out from it. The two can be anywhere in the map and facing any way, so the space doesn't
+/// have to add up.
+#[derive(Clone, Copy, PartialEq)]
+pub struct Portal {
+    pub from: PortalFace,
+    pub to: PortalFace,
+}
+
+impl Portal {
+    /// The same portal, entered from the other end.
+    pub fn reversed(&self) -> Portal {
+        Portal { from: self.to, to: self.from }
+    }
+
+    /// The cosine and sine of the turn from heading into `from` to heading out of `to`.
+    fn rotation(&self) -> (f64, f64) {
+        let (in_x, in_y) = (-self.from.normal.0, -self.from.normal.1);
+        let (out_x, out_y) = self.to.normal;
+        ((in_x * out_x + in_y * out_y) as f64, (in_x * out_y - in_y * out_x) as f64)
+    }
+
+    /// How far anything going through turns, in radians.
+    pub fn angle(&self) -> f64 {
+        let (cos, sin) = self.rotation();
+        sin.atan2(cos)
+    }
+
+    /// A direction on the `from` side, as it carries on from the `to` side.
+    pub fn transform_direction(&self, x: f64, y: f64) -> (f64, f64) {
+        let (cos, sin) = self.rotation();
+        (x * cos - y * sin, x * sin + y * cos)
+    }
+
+    /// A point by the `from` face, moved to the same place relative to the `to` face.
+    pub fn transform_point(&self, x: f64, y: f64) -> (f64, f64) {
+        let (from_x, from_y) = self.from.centre();
+        let (to_x, to_y) = self.to.centre();
+        let (x, y) = self.transform_direction(x - from_x, y - from_y);
+        (to_x + x, to_y + y)
+    }
+
+    /// True if the point is out in front of the `to` face rather than behind it.
+    pub fn in_front_of_exit(&self, x: f64, y: f64) -> bool {
+        let (to_x, to_y) = self.to.centre();
+        (x - to_x) * self.to.normal.0 as f64 + (y - to_y) * self.to.normal.1 as f64 > 0.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn close((x, y): (f64, f64), (expected_x, expected_y): (f64, f64)) -> bool {
+        (x - expected_x).abs() < 1e-9 && (y - expected_y).abs() < 1e-9
+    }
+
+    /// Into a south-facing face heading north, out of an east-facing one heading east.
+    const PORTAL: Portal = Portal {
+        from: PortalFace { x: 3, y: 0, normal: (0, 1) },
+        to: PortalFace { x: 0, y: 6, normal: (1, 0) },
+    };
+
+    #[test]
+    fn directions_turn_from_into_the_entrance_to_out_of_the_exit() {
+        assert!(close(PORTAL.transform_direction(0.0, -1.0), (1.0, 0.0)));
+        assert!(close(PORTAL.transform_direction(1.0, 0.0), (0.0, 1.0)));
+        assert!((PORTAL.angle() - std::f64::consts::FRAC_PI_2).abs() < 1e-9);
+        assert!(close(PORTAL.reversed().transform_direction(1.0, 0.0), (0.0, -1.0)));
+    }
+
+    #[test]
+    fn points_keep_their_place_relative_to_the_faces() {
+        assert!(close(PORTAL.transform_point(3.5, 1.0), (1.0, 6.5)));
+        // Just short of the entrance comes out just behind the exit, and back again
+        let (x, y) = PORTAL.transform_point(3.5, 1.2);
+        assert!(close((x, y), (0.8, 6.5)));
+        assert!(!PORTAL.in_front_of_exit(x, y));
+        assert!(close(PORTAL.reversed().transform_point(x, y), (3.5, 1.2)));
+        // Just past it comes out in front
+        assert!(PORTAL.in_front_of_exit(1.2, 6.5));
+    }
+}
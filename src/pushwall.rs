@@ -0,0 +1,91 @@
+/// How far a secret wall slides once pushed, in cells.
+pub const PUSH_DISTANCE: u32 = 2;
+/// Ticks the wall takes to slide one cell.
+pub const TICKS_PER_CELL: u32 = 64;
+
+/// A secret wall block sliding away from the player who pushed it. While it moves it is not in the
+/// map's cells at all; the map asks it directly for collision and the raycaster for hits.
+#[derive(Clone, Copy)]
+pub struct Pushwall {
+    /// The cell the block started from.
+    pub x: i32,
+    pub y: i32,
+    /// Unit step the block moves along, one of the four axis directions.
+    pub dx: i32,
+    pub dy: i32,
+    pub texture_id: u8,
+    pub height: f64,
+    /// Cells it will travel before coming to rest, which is less than `PUSH_DISTANCE` if something
+    /// was in the way.
+    pub distance: u32,
+    pub ticks: u32,
+}
+
+/// Where a ray meets a face of the moving block.
+pub struct PushwallHit {
+    pub distance: f64,
+    /// Horizontal texture coordinate across the face, from 0 to 1.
+    pub texture_x: f64,
+}
+
+impl Pushwall {
+    /// How far the block has moved so far, in cells.
+    pub fn offset(&self) -> f64 {
+        self.ticks as f64 / TICKS_PER_CELL as f64
+    }
+
+    /// The block's top-left corner.
+    pub fn position(&self) -> (f64, f64) {
+        let offset = self.offset();
+        (self.x as f64 + self.dx as f64 * offset, self.y as f64 + self.dy as f64 * offset)
+    }
+
+    /// The cell the block comes to rest in.
+    pub fn destination(&self) -> (i32, i32) {
+        let distance = self.distance as i32;
+        (self.x + self.dx * distance, self.y + self.dy * distance)
+    }
+
+    /// Moves the block on by a tick, returning true once it has arrived.
+    pub fn tick(&mut self) -> bool {
+        self.ticks += 1;
+        self.ticks >= self.distance * TICKS_PER_CELL
+    }
+
+    pub fn contains(&self, x: f64, y: f64) -> bool {
+        let (left, top) = self.position();
+        x >= left && x < left + 1.0 && y >= top && y < top + 1.0
+    }
+
+    /// Intersects a ray from (origin_x, origin_y) with the block, treating it as a unit square.
+    pub fn intersect(&self, origin_x: f64, origin_y: f64, cos_angle: f64, sin_angle: f64) -> Option<PushwallHit> {
+        let (left, top) = self.position();
+
+        // Distances along the ray to the two x faces and the two y faces
+        let (near_x, far_x) = slab(left, origin_x, cos_angle);
+        let (near_y, far_y) = slab(top, origin_y, sin_angle);
+        let enter = near_x.max(near_y);
+        let leave = far_x.min(far_y);
+        if enter > leave || enter <= 0.0 {
+            return None;
+        }
+
+        // Entering through an x face means the texture runs along y, as with grid hits
+        let texture_x = if near_x > near_y {
+            origin_y + enter * sin_angle - top
+        } else {
+            origin_x + enter * cos_angle - left
+        };
+        Some(PushwallHit { distance: enter, texture_x: texture_x.clamp(0.0, 1.0) })
+    }
+}
+
+/// The distances along one axis at which a ray enters and leaves the span [start, start + 1].
+fn slab(start: f64, origin: f64, direction: f64) -> (f64, f64) {
+    if direction == 0.0 {
+        return if origin >= start && origin <= start + 1.0 { (f64::NEG_INFINITY, f64::INFINITY) } else { (f64::INFINITY, f64::NEG_INFINITY) };
+    }
+    let a = (start - origin) / direction;
+    let b = (start + 1.0 - origin) / direction;
+    (a.min(b), a.max(b))
+}
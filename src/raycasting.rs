@@ -1,28 +1,210 @@
-use crate::game::{MAP, MAP_HEIGHT, MAP_WIDTH, Player};
+use crate::game::{Map, Player};
+use crate::portal::Portal;
 use crate::renderer::Renderer;
-use crate::sprites::Sprite;
+use crate::sprites::{sort_back_to_front, Sprite};
+
+/// Walls with this texture are mirrors, reflecting whatever is in front of them.
+pub const MIRROR_TEXTURE: u8 = 21;
+/// Most times a ray bounces between mirrors. The next mirror it meets is drawn as a plain wall.
+const MAX_BOUNCES: u32 = 3;
+/// Most portals a ray goes through, so two facing each other don't send it round forever. The next
+/// one it meets is drawn as the wall it is set in.
+const MAX_PORTALS: u32 = 8;
+/// Colour each bounce off a mirror multiplies the reflection by, so reflections look a little
+/// dimmer and cooler than the real thing.
+const MIRROR_TINT: (f32, f32, f32) = (0.8, 0.85, 0.9);
 
 pub struct Ray {
+    /// Distance along the ray's whole path, including any bounces off mirrors or trips through
+    /// portals on the way.
     pub distance: f64,
-    pub texture_coord: f64,
-    pub vertical_hit: bool,
-    pub hit: bool,
-    pub texture_id: u8
+    pub texture_id: u8,
+    /// Horizontal texture coordinate across the wall face that was hit, from 0 to 1.
+    pub texture_x: f64,
+    /// Height of the wall that was hit, as a multiple of an ordinary wall.
+    pub height: f64,
+    /// The last mirror or portal the ray went through. The hit is only visible within its outline.
+    pub clip: Option<Clip>,
+    /// Set when the hit is a mirror or portal the ray went through. The hits after it are what is
+    /// seen in it.
+    pub opening: Option<Opening>,
+    /// Set when the hit is a stretch of floor rather than a wall, to the distances at which the
+    /// ray crosses onto and off it. `distance` is then halfway between, which sorts it among the
+    /// walls either side of it.
+    pub floor: Option<(f64, f64)>,
 }
 
-impl Ray {
-    pub fn new(distance: f64, texture_coord: f64, vertical_hit: bool, hit: bool, texture_id: u8) -> Self {
-        Ray {
-            distance,
-            texture_coord,
-            vertical_hit,
-            hit,
-            texture_id
+/// The line a mirror's surface lies along.
+#[derive(Clone, Copy, PartialEq)]
+pub struct MirrorPlane {
+    pub start: (f64, f64),
+    pub end: (f64, f64),
+}
+
+impl MirrorPlane {
+    fn axis(&self) -> (f64, f64) {
+        let (x, y) = (self.end.0 - self.start.0, self.end.1 - self.start.1);
+        let length = (x * x + y * y).sqrt();
+        (x / length, y / length)
+    }
+
+    /// Flips a direction over the plane, keeping the part along it and reversing the part across it.
+    pub fn reflect_direction(&self, x: f64, y: f64) -> (f64, f64) {
+        let (axis_x, axis_y) = self.axis();
+        let along = x * axis_x + y * axis_y;
+        (2.0 * along * axis_x - x, 2.0 * along * axis_y - y)
+    }
+
+    /// Where a point appears to be when seen in the mirror.
+    pub fn reflect_point(&self, x: f64, y: f64) -> (f64, f64) {
+        let (x, y) = self.reflect_direction(x - self.start.0, y - self.start.1);
+        (self.start.0 + x, self.start.1 + y)
+    }
+
+    /// Which side of the plane a point is on: positive on one side, negative on the other.
+    pub fn side(&self, x: f64, y: f64) -> f64 {
+        let (axis_x, axis_y) = self.axis();
+        axis_x * (y - self.start.1) - axis_y * (x - self.start.0)
+    }
+}
+
+/// A wall surface that rays carry on through to somewhere else.
+#[derive(Clone, Copy, PartialEq)]
+pub enum Opening {
+    Mirror(MirrorPlane),
+    /// A portal entered through its `from` face.
+    Portal(Portal),
+}
+
+impl Opening {
+    /// Where a ray that meets the opening at (x, y) heading along (cos_angle, sin_angle) carries
+    /// on from, and which way.
+    fn carry(&self, x: f64, y: f64, cos_angle: f64, sin_angle: f64) -> ((f64, f64), (f64, f64)) {
+        match self {
+            Opening::Mirror(plane) => ((x, y), plane.reflect_direction(cos_angle, sin_angle)),
+            Opening::Portal(portal) => (portal.transform_point(x, y), portal.transform_direction(cos_angle, sin_angle)),
+        }
+    }
+
+    /// Where something at (x, y) appears to be when seen in the opening from (viewer_x, viewer_y),
+    /// or None if it isn't on the side that can be seen.
+    fn image(&self, x: f64, y: f64, viewer_x: f64, viewer_y: f64) -> Option<(f64, f64)> {
+        match self {
+            Opening::Mirror(plane) => (plane.side(x, y) * plane.side(viewer_x, viewer_y) > 0.0).then(|| plane.reflect_point(x, y)),
+            Opening::Portal(portal) => portal.in_front_of_exit(x, y).then(|| portal.reversed().transform_point(x, y)),
         }
     }
 }
 
-pub fn cast_ray(player: &Player, angle: f64, cos_angle: f64, sin_angle: f64) -> Ray {
+/// Darkens a pixel's colour by MIRROR_TINT. Each mirror a reflection is seen through tints it again.
+fn tint(color: &mut [u8]) {
+    for (channel, tint) in color.iter_mut().zip([MIRROR_TINT.0, MIRROR_TINT.1, MIRROR_TINT.2]) {
+        *channel = (*channel as f32 * tint) as u8;
+    }
+}
+
+/// The outline of a mirror or portal a ray went through, which is all that can be seen of what
+/// lies beyond it.
+#[derive(Clone, Copy)]
+pub struct Clip {
+    /// Distance along the ray's path to the opening.
+    pub distance: f64,
+    pub height: f64,
+}
+
+/// One straight stretch of a ray, from the player or from the last mirror or portal it went
+/// through.
+#[derive(Clone, Copy)]
+struct Leg {
+    x: f64,
+    y: f64,
+    cos_angle: f64,
+    sin_angle: f64,
+    travelled: f64,
+    bounces: u32,
+    portals: u32,
+    clip: Option<Clip>,
+}
+
+/// How tall a wall has to be to stop a ray, wherever it is hit. It must be at least as tall as
+/// every wall the ray could still reach beyond it, so none of them shows over the top, and must
+/// reach above the camera.
+pub struct StopHeights {
+    width: usize,
+    height: usize,
+    /// Walls lower than this never stop a ray, since the camera can see over them.
+    least: f64,
+    /// For each way a ray can head, the tallest wall in each cell or anywhere further on in that
+    /// direction: towards +x +y, +x -y, -x +y and -x -y.
+    tallest: [Vec<f64>; 4],
+}
+
+impl StopHeights {
+    pub fn new(map: &Map, least: f64) -> Self {
+        let (width, height) = (map.width, map.height);
+        // A sliding pushwall isn't in the grid and could be anywhere, so it counts everywhere
+        let pushwall = map.pushwall.map_or(0.0, |pushwall| pushwall.height);
+        let tallest = [(1, 1), (1, -1), (-1, 1), (-1, -1)].map(|(step_x, step_y)| {
+            let mut tallest = vec![pushwall; width * height];
+            // Work back from the far corner, so the cells further on are filled in first
+            let xs: Vec<usize> = if step_x > 0 { (0..width).rev().collect() } else { (0..width).collect() };
+            let ys: Vec<usize> = if step_y > 0 { (0..height).rev().collect() } else { (0..height).collect() };
+            for &y in &ys {
+                for &x in &xs {
+                    let index = y * width + x;
+                    let mut value = tallest[index];
+                    if map.cells[index] != 0 {
+                        value = value.max(map.heights[index]);
+                    }
+                    if let Some(next) = x.checked_add_signed(step_x).filter(|next| *next < width) {
+                        value = value.max(tallest[y * width + next]);
+                    }
+                    if let Some(next) = y.checked_add_signed(step_y).filter(|next| *next < height) {
+                        value = value.max(tallest[next * width + x]);
+                    }
+                    tallest[index] = value;
+                }
+            }
+            tallest
+        });
+        StopHeights { width, height, least, tallest }
+    }
+
+    /// The height a wall at (x, y) needs to stop a ray heading along (step_x, step_y).
+    pub fn at(&self, x: i32, y: i32, step_x: i32, step_y: i32) -> f64 {
+        let quadrant = &self.tallest[(step_x < 0) as usize * 2 + (step_y < 0) as usize];
+        let tallest = |x: i32, y: i32| {
+            let inside = x >= 0 && y >= 0 && (x as usize) < self.width && (y as usize) < self.height;
+            if inside { quadrant[y as usize * self.width + x as usize] } else { 0.0 }
+        };
+        // Everything the ray can still reach lies one step on across or down from here
+        tallest(x + step_x, y).max(tallest(x, y + step_y)).max(self.least)
+    }
+}
+
+/// Casts a ray through the map and returns every wall it hits, nearest first. Walls lower than
+/// `stops` asks for don't stop the ray, so taller ones behind them can still be drawn over the top,
+/// and neither do walls whose texture is marked in `see_through`. Mirrors send the ray off in a
+/// new direction, up to MAX_BOUNCES times, and portals carry it on from their other end. With
+/// `floors` set, the stretches of floor the ray passes over are returned too, for drawing an upper
+/// storey's floor over what is below it.
+pub fn cast_ray(map: &Map, player: &Player, cos_angle: f64, sin_angle: f64, stops: &StopHeights, see_through: &[bool], floors: bool) -> Vec<Ray> {
+    let blocks = |texture_id: u8, height: f64, (x, y): (i32, i32), (step_x, step_y): (i32, i32)| {
+        height >= stops.at(x, y, step_x, step_y) && !see_through.get(texture_id as usize - 1).copied().unwrap_or(false)
+    };
+
+    let mut hits = Vec::new();
+    let mut leg = Some(Leg { x: player.x, y: player.y, cos_angle, sin_angle, travelled: 0.0, bounces: 0, portals: 0, clip: None });
+    while let Some(current) = leg {
+        leg = cast_leg(map, current, &blocks, floors, &mut hits);
+    }
+    hits
+}
+
+/// Casts one leg of a ray, adding what it hits to `hits`. Returns the next leg if the ray went
+/// through a mirror or portal.
+fn cast_leg(map: &Map, leg: Leg, blocks: &dyn Fn(u8, f64, (i32, i32), (i32, i32)) -> bool, floors: bool, hits: &mut Vec<Ray>) -> Option<Leg> {
+    let (cos_angle, sin_angle) = (leg.cos_angle, leg.sin_angle);
     let step_x = if cos_angle > 0.0 { 1 } else { -1 };
     let step_y = if sin_angle > 0.0 { 1 } else { -1 };
 
@@ -30,82 +212,293 @@ pub fn cast_ray(player: &Player, angle: f64, cos_angle: f64, sin_angle: f64) ->
     let delta_dist_y = (1.0 / sin_angle).abs();
 
     let mut side_dist_x = if cos_angle > 0.0 {
-        (player.x.floor() + 1.0 - player.x) * delta_dist_x
+        (leg.x.floor() + 1.0 - leg.x) * delta_dist_x
     } else {
-        (player.x - player.x.floor()) * delta_dist_x
+        (leg.x - leg.x.floor()) * delta_dist_x
     };
 
     let mut side_dist_y = if sin_angle > 0.0 {
-        (player.y.floor() + 1.0 - player.y) * delta_dist_y
+        (leg.y.floor() + 1.0 - leg.y) * delta_dist_y
     } else {
-        (player.y - player.y.floor()) * delta_dist_y
+        (leg.y - leg.y.floor()) * delta_dist_y
     };
 
-    let mut map_x = player.x as i32;
-    let mut map_y = player.y as i32;
+    let mut map_x = leg.x as i32;
+    let mut map_y = leg.y as i32;
 
-    let mut hit = false;
-    let mut vertical_hit = false;
-    let mut texture_id = 0;
+    let first = hits.len();
+    let mut next = None;
+    // Whether the ray was stopped or sent elsewhere, so nothing further along this line can be seen
+    let mut ended = false;
+
+    // The floor of the cell the leg starts in, up to where the ray leaves it
+    let has_floor = |x: i32, y: i32| floors && map.floor_at(x, y);
+    if has_floor(map_x, map_y) {
+        hits.extend(floor(&leg, 0.0, side_dist_x.min(side_dist_y)));
+    }
 
     // Cast rays and minimize calculations
-    while !hit {
-        if side_dist_x < side_dist_y {
+    loop {
+        let near = side_dist_x.min(side_dist_y);
+        let vertical_hit = if side_dist_x < side_dist_y {
             side_dist_x += delta_dist_x;
             map_x += step_x;
-            vertical_hit = true;
+            true
         } else {
             side_dist_y += delta_dist_y;
             map_y += step_y;
-            vertical_hit = false;
+            false
+        };
+
+        let far = side_dist_x.min(side_dist_y);
+
+        // The ray left the map without hitting anything else
+        let Some(texture_id) = map.get(map_x, map_y) else {
+            break;
+        };
+        let has_floor = has_floor(map_x, map_y);
+        if texture_id == 0 {
+            if has_floor {
+                hits.extend(floor(&leg, near, far));
+            }
+            continue;
         }
 
-        if map_x >= 0 && map_y >= 0 && (map_x as usize) < MAP_WIDTH && (map_y as usize) < MAP_HEIGHT {
-            let map_index = (map_y as usize) * MAP_WIDTH + (map_x as usize);
-            texture_id = MAP[map_index];
+        // The floor beyond a thin wall, drawn after it unless the wall ends the leg
+        let mut beyond = None;
+        let (distance, texture_x, plane, normal) = if let Some(thin_wall) = map.thin_wall_at(map_x, map_y) {
+            // Thin walls only cover a line through the cell, which the ray may pass beside
+            match thin_wall.intersect(map_x, map_y, leg.x, leg.y, cos_angle, sin_angle) {
+                Some(hit) => {
+                    let (x, y) = (map_x as f64, map_y as f64);
+                    let plane = MirrorPlane {
+                        start: (x + thin_wall.start.0, y + thin_wall.start.1),
+                        end: (x + thin_wall.end.0, y + thin_wall.end.1),
+                    };
+                    // Split the cell's floor at the wall, so each part sorts to its own side of it
+                    if has_floor {
+                        hits.extend(floor(&leg, near, hit.distance));
+                        beyond = floor(&leg, hit.distance, far);
+                    }
+                    (hit.distance, hit.texture_x, plane, None)
+                }
+                None => {
+                    if has_floor {
+                        hits.extend(floor(&leg, near, far));
+                    }
+                    continue;
+                }
+            }
+        } else {
+            let face_x = map_x as f64 + (1.0 - step_x as f64) / 2.0;
+            let face_y = map_y as f64 + (1.0 - step_y as f64) / 2.0;
+            let distance = if vertical_hit {
+                (face_x - leg.x) / cos_angle
+            } else {
+                (face_y - leg.y) / sin_angle
+            };
+            let (texture_x, plane, normal) = if vertical_hit {
+                let plane = MirrorPlane { start: (face_x, map_y as f64), end: (face_x, map_y as f64 + 1.0) };
+                ((leg.y + distance * sin_angle).fract(), plane, (-step_x, 0))
+            } else {
+                let plane = MirrorPlane { start: (map_x as f64, face_y), end: (map_x as f64 + 1.0, face_y) };
+                ((leg.x + distance * cos_angle).fract(), plane, (0, -step_y))
+            };
+            (distance, texture_x, plane, Some(normal))
+        };
+        let height = map.height_at(map_x, map_y);
+        let mut ray = Ray {
+            distance: leg.travelled + distance,
+            texture_id,
+            texture_x,
+            height,
+            clip: leg.clip,
+            opening: None,
+            floor: None,
+        };
+
+        // Past their limits, mirrors and portals are drawn as ordinary walls
+        let opening = if texture_id == MIRROR_TEXTURE && leg.bounces < MAX_BOUNCES {
+            Some(Opening::Mirror(plane))
+        } else if leg.portals < MAX_PORTALS {
+            normal.and_then(|normal| map.portal(map_x, map_y, normal)).map(Opening::Portal)
+        } else {
+            None
+        };
+        if let Some(opening) = opening {
+            ray.opening = Some(opening);
+            let hit = (leg.x + distance * cos_angle, leg.y + distance * sin_angle);
+            let ((x, y), (cos_angle, sin_angle)) = opening.carry(hit.0, hit.1, cos_angle, sin_angle);
+            let mirror = matches!(opening, Opening::Mirror(_));
+            next = Some(Leg {
+                x,
+                y,
+                cos_angle,
+                sin_angle,
+                travelled: ray.distance,
+                bounces: leg.bounces + mirror as u32,
+                portals: leg.portals + !mirror as u32,
+                clip: Some(Clip { distance: ray.distance, height }),
+            });
+            hits.push(ray);
+            ended = true;
+            break;
+        }
 
-            if texture_id > 0 {
-                hit = true;
-                break;
+        hits.push(ray);
+        // Nothing behind a solid wall this tall can show
+        if blocks(texture_id, height, (map_x, map_y), (step_x, step_y)) {
+            ended = true;
+            break;
+        }
+        hits.extend(beyond);
+    }
+
+    // A sliding pushwall isn't in the grid, so slot it in among this leg's hits by distance
+    if let Some(pushwall) = map.pushwall {
+        if let Some(hit) = pushwall.intersect(leg.x, leg.y, cos_angle, sin_angle) {
+            let distance = leg.travelled + hit.distance;
+            let index = first + hits[first..].partition_point(|ray| ray.distance < distance);
+            if index < hits.len() || !ended {
+                hits.insert(index, Ray {
+                    distance,
+                    texture_id: pushwall.texture_id,
+                    texture_x: hit.texture_x,
+                    height: pushwall.height,
+                    clip: leg.clip,
+                    opening: None,
+                    floor: None,
+                });
+                let cell = ((leg.x + hit.distance * cos_angle) as i32, (leg.y + hit.distance * sin_angle) as i32);
+                if blocks(pushwall.texture_id, pushwall.height, cell, (step_x, step_y)) {
+                    // It hides everything behind it, including any mirror or portal the ray would
+                    // have gone through
+                    hits.truncate(index + 1);
+                    next = None;
+                }
             }
         }
     }
+    next
+}
 
-    let distance = if vertical_hit {
-        (map_x as f64 - player.x + (1.0 - step_x as f64) / 2.0) / cos_angle
-    } else {
-        (map_y as f64 - player.y + (1.0 - step_y as f64) / 2.0) / sin_angle
-    };
+/// The stretch of floor a leg crosses between `near` and `far` along it, if it has any length.
+fn floor(leg: &Leg, near: f64, far: f64) -> Option<Ray> {
+    (far > near).then(|| Ray {
+        distance: leg.travelled + (near + far) / 2.0,
+        texture_id: 0,
+        texture_x: 0.0,
+        height: 0.0,
+        clip: leg.clip,
+        opening: None,
+        floor: Some((leg.travelled + near, leg.travelled + far)),
+    })
+}
 
-    let texture_coord = if vertical_hit {
-        (player.y + distance * sin_angle).fract()
-    } else {
-        (player.x + distance * cos_angle).fract()
-    };
 
-    Ray {
-        distance,
-        texture_coord,
-        vertical_hit,
-        hit,
-        texture_id,
+/// The nearest see-through wall in front of a pixel, so sprites drawn behind it can be seen
+/// through it rather than pasted over it.
+#[derive(Clone, Copy)]
+struct Overlay {
+    distance: f64,
+    color: (f32, f32, f32),
+    alpha: f32,
+}
+
+/// A pixel showing what is seen in a mirror or through a portal.
+#[derive(Clone, Copy)]
+struct OpeningPixel {
+    /// Index into the frame's list of openings, for pixels in one seen directly. Openings seen
+    /// inside other openings have none, since sprites are only carried through one.
+    opening: Option<usize>,
+    /// Depth of the opening's surface. Real sprites behind it are hidden.
+    distance: f64,
+}
+
+/// Everything the sprite pass needs to know about the walls already drawn. Kept on the renderer
+/// between frames so it is only allocated again when the resolution changes.
+#[derive(Default)]
+pub struct SceneBuffers {
+    depth: Vec<f64>,
+    overlays: Vec<Option<Overlay>>,
+    openings: Vec<Option<OpeningPixel>>,
+}
+
+impl SceneBuffers {
+    /// Buffers for `size` pixels with nothing drawn yet.
+    pub fn new(size: usize) -> Self {
+        SceneBuffers {
+            depth: vec![f64::MAX; size],
+            overlays: vec![None; size],
+            openings: vec![None; size],
+        }
+    }
+
+    /// Forgets everything drawn last frame.
+    pub fn clear(&mut self) {
+        self.depth.fill(f64::MAX);
+        self.overlays.fill(None);
+        self.openings.fill(None);
+    }
+
+    /// Records that something solid was drawn over a pixel at `depth`, hiding whatever was there.
+    pub fn set_opaque(&mut self, pixel: usize, depth: f64) {
+        self.depth[pixel] = depth;
+        self.overlays[pixel] = None;
+        self.openings[pixel] = None;
     }
 }
 
+/// Where the view is from, for projecting sprites onto the screen.
+#[derive(Clone, Copy)]
+pub struct Camera {
+    pub x: f64,
+    pub y: f64,
+    pub dir_x: f64,
+    pub dir_y: f64,
+    pub plane_x: f64,
+    pub plane_y: f64,
+    pub horizon: i32,
+    /// Height of the camera above the floor sprites stand on.
+    pub eye: f64,
+}
+
+impl Camera {
+    pub fn new(player: &Player, horizon: i32, eye: f64) -> Self {
+        let dir_x = player.direction.cos();
+        let dir_y = player.direction.sin();
+        let fov_factor = (player.fov / 2.0).tan();
+        Camera {
+            x: player.x,
+            y: player.y,
+            dir_x,
+            dir_y,
+            plane_x: -dir_y * fov_factor,
+            plane_y: dir_x * fov_factor,
+            horizon,
+            eye,
+        }
+    }
+}
 
-pub fn render_scene(player: &Player, renderer: &mut Renderer, sprites: &mut Vec<Sprite>) {
+pub fn render_scene(map: &Map, player: &Player, renderer: &mut Renderer, sprites: &mut [Sprite]) {
     renderer.clear_framebuffer();
 
     let num_rays = renderer.screen_width / 6;
-    let screen_width = renderer.screen_width as f64;
     let screen_height = renderer.screen_height as f64;
-    let half_screen_height = screen_height / 2.0;
+    let horizon = player.horizon(screen_height) as i32;
+    // Measured from the ground floor, so each storey can be placed at its own height below it
+    let eye = player.camera_height();
 
-    let mut depth_buffer = vec![std::f64::MAX; renderer.screen_width];
+    // Per pixel rather than per column, since a sprite can stick up above a low wall in front of it.
+    // Taken off the renderer for the frame so both can be borrowed at once.
+    let mut buffers = std::mem::take(&mut renderer.scene_buffers);
+    buffers.clear();
+    let mut openings: Vec<Opening> = Vec::new();
 
 
     for y in 0..renderer.screen_height {
-        let is_ceiling = y < (renderer.screen_height / 2);
+        let is_ceiling = (y as i32) < horizon;
         let color = if is_ceiling {
             (50, 50, 50)
         } else {
@@ -121,148 +514,402 @@ pub fn render_scene(player: &Player, renderer: &mut Renderer, sprites: &mut Vec<
         }
     }
 
+    // The rows a wall of `height` at `distance` on a storey with its floor at `base` covers, from
+    // its top down to the floor
+    let span = |distance: f64, height: f64, base: f64| {
+        let line_height = (screen_height / distance) as i32;
+        // Walls stand on the floor, which lies eye height below the horizon, and grow upwards
+        let bottom = ((eye - base) * line_height as f64) as i32 + horizon;
+        (bottom - (height * line_height as f64) as i32, bottom, line_height)
+    };
+
+    // A wall only hides everything behind it on its storey if it reaches from below the camera to
+    // above it, and no taller wall further on could show over it
+    let stop_heights: Vec<StopHeights> = (0..=map.storeys.len())
+        .map(|index| {
+            let base = index as f64;
+            let least = if eye < base { f64::INFINITY } else { eye - base };
+            StopHeights::new(map.storey(index), least)
+        })
+        .collect();
     for x in 0..num_rays {
         let angle = player.direction - player.fov / 2.0 + (x as f64 / num_rays as f64) * player.fov;
         let cos_angle = angle.cos();
         let sin_angle = angle.sin();
-
-        let ray = cast_ray(player, angle, cos_angle, sin_angle);
-
-        let line_height = (screen_height / ray.distance) as i32;
-        let draw_start = (-line_height / 2 + half_screen_height as i32).max(0);
-        let draw_end = (line_height / 2 + half_screen_height as i32).min(screen_height as i32 - 1);
-
-        let tex_x = if ray.vertical_hit {
-            (player.y + ray.distance * sin_angle).fract() * renderer.texture_width as f64
-        } else {
-            (player.x + ray.distance * cos_angle).fract() * renderer.texture_width as f64
-        } as usize;
-
-        let corrected_distance = ray.distance * (player.direction - angle).cos();
         let column = x * 6;
-        for i in 0..6 {
-            if column + i < depth_buffer.len() {
-                depth_buffer[column + i] = corrected_distance;
-            }
+        let columns = column..(column + 6).min(renderer.screen_width);
+        let correction = (player.direction - angle).cos();
+
+        // Every storey's hits together, farthest first, along with the height of their storey's floor
+        let mut hits: Vec<(f64, Ray)> = Vec::new();
+        for (index, stop_height) in stop_heights.iter().enumerate() {
+            let rays = cast_ray(map.storey(index), player, cos_angle, sin_angle, stop_height, &renderer.see_through, index > 0);
+            hits.extend(rays.into_iter().rev().map(|ray| (index as f64, ray)));
         }
+        hits.sort_by(|a, b| b.1.distance.partial_cmp(&a.1.distance).unwrap());
+
+        // Draw back to front, so nearer walls cover whatever part of the farther ones they hide
+        for (base, ray) in &hits {
+            let base = *base;
+            let (top, bottom, line_height) = span(ray.distance, ray.height, base);
+            let mut draw_start = top.max(0);
+            let mut draw_end = bottom.min(screen_height as i32 - 1);
+            // What is seen in a mirror or portal only shows within its outline
+            let clip = ray.clip.map(|clip| span(clip.distance, clip.height, base));
+            if let Some((clip_top, clip_bottom, _)) = clip {
+                draw_start = draw_start.max(clip_top);
+                draw_end = draw_end.min(clip_bottom);
+            }
+            let corrected_distance = ray.distance * correction;
+
+            if let Some((near, far)) = ray.floor {
+                // Upper floors look like the ceiling from underneath, and a shade darker than the
+                // ground floor from above so holes down to it stand out
+                let above = eye - base;
+                let row = |distance: f64| (horizon as f64 + above * screen_height / distance) as i32;
+                let (color, start, end) = if above > 0.0 {
+                    ((85, 85, 85), row(far), row(near))
+                } else {
+                    ((50, 50, 50), row(near), row(far))
+                };
+                let (mut start, mut end) = (start.max(0), end.min(screen_height as i32 - 1));
+                if let Some((clip_top, clip_bottom, _)) = clip {
+                    start = start.max(clip_top);
+                    end = end.min(clip_bottom);
+                }
+                for y in start..end {
+                    let depth = (above * screen_height / (y - horizon) as f64).abs() * correction;
+                    let row = y as usize * renderer.screen_width;
+                    for pixel in columns.clone().map(|i| row + i) {
+                        let index = pixel * 4;
+                        renderer.framebuffer[index] = color.0;
+                        renderer.framebuffer[index + 1] = color.1;
+                        renderer.framebuffer[index + 2] = color.2;
+                        buffers.set_opaque(pixel, depth);
+                    }
+                }
+                continue;
+            }
 
-        for y in draw_start..draw_end {
-            let d = y * 256 - (screen_height as i32 * 128) + line_height * 128;
-            let tex_y = ((d * renderer.texture_height as i32) / line_height) / 256;
+            if let Some(opening) = ray.opening {
+                // Everything drawn here so far is what is seen in it; mark it for the sprite pass,
+                // and tint it if it is a reflection
+                let index = ray.clip.is_none().then(|| {
+                    openings.iter().position(|seen| *seen == opening).unwrap_or_else(|| {
+                        openings.push(opening);
+                        openings.len() - 1
+                    })
+                });
+                let mirror = matches!(opening, Opening::Mirror(_));
+                for y in draw_start..draw_end {
+                    let row = y as usize * renderer.screen_width;
+                    for pixel in columns.clone().map(|i| row + i) {
+                        if mirror {
+                            tint(&mut renderer.framebuffer[pixel * 4..pixel * 4 + 3]);
+                        }
+                        // An opening already marked here is one seen inside this one
+                        let opening = index.filter(|_| buffers.openings[pixel].is_none());
+                        buffers.openings[pixel] = Some(OpeningPixel { opening, distance: corrected_distance });
+                    }
+                }
+                continue;
+            }
 
-            let (r, g, b) = renderer.get_texture_color_rgb(ray.texture_id as usize - 1, tex_x, tex_y as usize);
-            renderer.draw_rect(x as f64 * 6.0, y as f64, 6.0, 1.0, Some((r, g, b)));
-        }
-    }
-    let dir_x = player.direction.cos();
-    let dir_y = player.direction.sin();
-    let fov_factor = (player.fov / 2.0).tan();
-    let plane_x = -dir_y * fov_factor;
-    let plane_y = dir_x * fov_factor;
+            let filter = renderer.texture_filter;
+            let Some(texture) = renderer.textures.get(ray.texture_id as usize - 1) else {
+                continue;
+            };
+            // Far away walls use a smaller copy of the texture so they don't shimmer
+            let texture = texture.filtered(filter, line_height as f64);
+            // The texture repeats once per unit of height, lined up with the floor
+            let texture_offset = ray.height.ceil() - ray.height;
 
+            for y in draw_start..draw_end {
+                let v = (y - top) as f64 / line_height as f64 + texture_offset;
 
-    for sprite in sprites.iter_mut() {
-        let dx = sprite.x - player.x;
-        let dy = sprite.y - player.y;
-        sprite.distance = (dx * dx + dy * dy).sqrt();
+                let (r, g, b, a) = texture.sample(ray.texture_x, v.fract(), filter);
+                if a == 0 {
+                    continue;
+                }
+
+                let row = y as usize * renderer.screen_width;
+                for pixel in columns.clone().map(|i| row + i) {
+                    let index = pixel * 4;
+                    if a == 255 {
+                        renderer.framebuffer[index] = r;
+                        renderer.framebuffer[index + 1] = g;
+                        renderer.framebuffer[index + 2] = b;
+                        buffers.set_opaque(pixel, corrected_distance);
+                    } else {
+                        // Partly transparent, like glass: tint what is behind and remember it for sprites
+                        let alpha = a as f32 / 255.0;
+                        for (channel, value) in [r, g, b].into_iter().enumerate() {
+                            let behind = renderer.framebuffer[index + channel] as f32;
+                            renderer.framebuffer[index + channel] = (value as f32 * alpha + behind * (1.0 - alpha)) as u8;
+                        }
+                        let color = (r as f32, g as f32, b as f32);
+                        buffers.overlays[pixel] = Some(Overlay { distance: corrected_distance, color, alpha });
+                    }
+                }
+            }
+        }
     }
-    for sprite in sprites.iter_mut() {
-        let dx = sprite.x - player.x;
-        let dy = sprite.y - player.y;
-        sprite.distance = (dx * dx + dy * dy).sqrt();
+    let camera = Camera::new(player, horizon, eye);
+    sort_back_to_front(sprites, player.x, player.y);
+
+    // Each storey stands one wall height above the last, so sprites upstairs are that much nearer the eye
+    let camera_for = |sprite: &Sprite| Camera { eye: eye - sprite.storey as f64, ..camera };
+
+    // Sprites seen in mirrors and portals first: they all lie behind the opening's surface, so any
+    // real sprite in front of it must be drawn over them
+    for (index, opening) in openings.iter().enumerate() {
+        let mut images: Vec<Sprite> = sprites
+            .iter()
+            .filter_map(|sprite| {
+                let (x, y) = opening.image(sprite.x, sprite.y, player.x, player.y)?;
+                Some(Sprite { x, y, ..*sprite })
+            })
+            .collect();
+        sort_back_to_front(&mut images, player.x, player.y);
+        for image in images {
+            draw_sprite(renderer, &camera_for(&image), &buffers, image.x, image.y, image.texture_id, Some((index, opening)));
+        }
     }
-    sprites.sort_by(|a, b| b.distance.partial_cmp(&a.distance).unwrap());
 
     for sprite in sprites.iter() {
-        let sprite_x = sprite.x - player.x;
-        let sprite_y = sprite.y - player.y;
+        draw_sprite(renderer, &camera_for(sprite), &buffers, sprite.x, sprite.y, sprite.texture_id, None);
+    }
+    renderer.scene_buffers = buffers;
+}
 
-        let inv_det = 1.0 / (plane_x * dir_y - dir_x * plane_y);
+/// Draws a sprite standing at (x, y). With `opening` set it is the image of a sprite seen in that
+/// mirror or portal, and only shows on pixels of it.
+pub fn draw_sprite(renderer: &mut Renderer, camera: &Camera, buffers: &SceneBuffers, x: f64, y: f64, texture_id: usize, opening: Option<(usize, &Opening)>) {
+    let screen_width = renderer.screen_width as f64;
+    let screen_height = renderer.screen_height as f64;
+    let (dir_x, dir_y, plane_x, plane_y) = (camera.dir_x, camera.dir_y, camera.plane_x, camera.plane_y);
 
-        let transform_x = inv_det * (dir_y * sprite_x - dir_x * sprite_y);
-        let transform_y = inv_det * (-plane_y * sprite_x + plane_x * sprite_y);
+    let sprite_x = x - camera.x;
+    let sprite_y = y - camera.y;
 
-        if transform_y <= 0.0 {
-            continue;
-        }
+    let inv_det = 1.0 / (plane_x * dir_y - dir_x * plane_y);
 
-        let sprite_screen_x = ((screen_width / 2.0) * (1.0 + transform_x / transform_y)) as i32;
+    let transform_x = inv_det * (dir_y * sprite_x - dir_x * sprite_y);
+    let transform_y = inv_det * (-plane_y * sprite_x + plane_x * sprite_y);
 
-        let sprite_height = (screen_height / transform_y).abs() as i32;
-        let sprite_width = sprite_height;
+    if transform_y <= 0.0 {
+        return;
+    }
 
-        let draw_start_y = (-sprite_height / 2 + half_screen_height as i32).max(0);
-        let draw_end_y = (sprite_height / 2 + half_screen_height as i32).min(screen_height as i32 - 1);
-        let draw_start_x = (-sprite_width / 2 + sprite_screen_x).max(0);
-        let draw_end_x = (sprite_width / 2 + sprite_screen_x).min(renderer.screen_width as i32 - 1);
+    let sprite_screen_x = ((screen_width / 2.0) * (1.0 + transform_x / transform_y)) as i32;
 
-        let texture_index = sprite.texture_id as usize - 1;
-        if texture_index >= renderer.textures.len() {
+    let Some(texture) = renderer.textures.get(texture_id - 1) else {
+        return;
+    };
+    let sprite_height = (screen_height / transform_y).abs() as i32;
+    // As wide as the texture's shape, so sprites that aren't square aren't stretched
+    let sprite_width = (sprite_height as f64 * texture.width as f64 / texture.height as f64) as i32;
+
+    // Sprites stand on the floor too
+    let sprite_top = (camera.eye * sprite_height as f64) as i32 + camera.horizon - sprite_height;
+    let draw_start_y = sprite_top.max(0);
+    let draw_end_y = (sprite_top + sprite_height).min(screen_height as i32 - 1);
+    let draw_start_x = (-sprite_width / 2 + sprite_screen_x).max(0);
+    let draw_end_x = (sprite_width / 2 + sprite_screen_x).min(renderer.screen_width as i32 - 1);
+
+    for stripe in draw_start_x..draw_end_x {
+        if stripe < 0 || stripe >= renderer.screen_width as i32 {
             continue;
         }
-        let texture = &renderer.textures[texture_index];
 
-        for stripe in draw_start_x..draw_end_x {
-            if stripe < 0 || stripe >= renderer.screen_width as i32 {
+        let tex_x = ((stripe - (-sprite_width / 2 + sprite_screen_x)) * texture.width as i32) / sprite_width;
+        for y in draw_start_y..draw_end_y {
+            if y < 0 || y >= renderer.screen_height as i32 {
+                continue;
+            }
+            let pixel = y as usize * renderer.screen_width + stripe as usize;
+            if transform_y >= buffers.depth[pixel] {
+                continue;
+            }
+            // Images only show in their own opening, and real sprites never show behind one
+            let visible = match (opening, buffers.openings[pixel]) {
+                (Some((index, _)), Some(opening_pixel)) => opening_pixel.opening == Some(index),
+                (Some(_), None) => false,
+                (None, Some(opening_pixel)) => transform_y < opening_pixel.distance,
+                (None, None) => true,
+            };
+            if !visible {
                 continue;
             }
 
-            let tex_x = ((stripe - (-sprite_width / 2 + sprite_screen_x)) * renderer.texture_width as i32) / sprite_width;
-            if stripe as usize >= depth_buffer.len() {
+            let tex_y = ((y - sprite_top) * texture.height as i32) / sprite_height;
+
+            let tex_index = ((tex_y * texture.width as i32 + tex_x) * 4) as usize;
+            let texture = &texture.pixels;
+
+            if tex_index + 3 >= texture.len() {
                 continue;
             }
 
-            if transform_y >= depth_buffer[stripe as usize] {
-                continue; 
+            let mut tex_r = texture[tex_index] as f32;
+            let mut tex_g = texture[tex_index + 1] as f32;
+            let mut tex_b = texture[tex_index + 2] as f32;
+            let tex_a = texture[tex_index + 3] as f32 / 255.0; // Normalize alpha to [0,1]
+
+            if let Some((_, Opening::Mirror(_))) = opening {
+                tex_r *= MIRROR_TINT.0;
+                tex_g *= MIRROR_TINT.1;
+                tex_b *= MIRROR_TINT.2;
             }
-            for y in draw_start_y..draw_end_y {
-                if y < 0 || y >= renderer.screen_height as i32 {
-                    continue;
+
+            // Seen through glass, the sprite takes on the glass's tint. The framebuffer already
+            // has the glass over the wall behind, so blending the tinted sprite into it by the
+            // sprite's own alpha gives the right result.
+            if let Some(overlay) = buffers.overlays[pixel] {
+                if overlay.distance < transform_y {
+                    tex_r = overlay.color.0 * overlay.alpha + tex_r * (1.0 - overlay.alpha);
+                    tex_g = overlay.color.1 * overlay.alpha + tex_g * (1.0 - overlay.alpha);
+                    tex_b = overlay.color.2 * overlay.alpha + tex_b * (1.0 - overlay.alpha);
                 }
+            }
 
-                let d = y * 256 - (screen_height as i32) * 128 + sprite_height * 128;
-                let tex_y = ((d * renderer.texture_height as i32) / sprite_height) / 256;
+            // Skip fully transparent pixels
+            if tex_a <= 0.0 {
+                continue;
+            }
 
-                let texture = &renderer.textures[texture_index];
-                let tex_index = ((tex_y * renderer.texture_width as i32 + tex_x) * 4) as usize;
+            let screen_index = pixel * 4;
+
+            let bg_r = renderer.framebuffer[screen_index] as f32;
+            let bg_g = renderer.framebuffer[screen_index + 1] as f32;
+            let bg_b = renderer.framebuffer[screen_index + 2] as f32;
+            let bg_a = renderer.framebuffer[screen_index + 3] as f32 / 255.0;
+
+            let out_a = tex_a + bg_a * (1.0 - tex_a);
+            if out_a > 0.0 {
+                let out_r = (tex_r * tex_a + bg_r * bg_a * (1.0 - tex_a)) / out_a;
+                let out_g = (tex_g * tex_a + bg_g * bg_a * (1.0 - tex_a)) / out_a;
+                let out_b = (tex_b * tex_a + bg_b * bg_a * (1.0 - tex_a)) / out_a;
+
+                renderer.framebuffer[screen_index] = out_r as u8;
+                renderer.framebuffer[screen_index + 1] = out_g as u8;
+                renderer.framebuffer[screen_index + 2] = out_b as u8;
+                renderer.framebuffer[screen_index + 3] = (out_a * 255.0) as u8;
+            } else {
+                renderer.framebuffer[screen_index + 3] = 0;
+            }
+        }
+    }
+}
 
-                if tex_index + 3 >= texture.len() {
-                    continue;
-                }
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::portal::PortalFace;
+
+    /// A walled room with a low wall east of (1, 2) and a double height one behind it, and a low
+    /// wall south with nothing taller beyond.
+    fn room() -> Map {
+        let mut map = Map::filled(10, 10, 0);
+        for i in 0..10 {
+            map.set(i, 0, 1);
+            map.set(i, 9, 1);
+            map.set(0, i, 1);
+            map.set(9, i, 1);
+        }
+        map.set(5, 2, 1);
+        map.set(8, 2, 1);
+        map.heights[2 * 10 + 8] = 2.0;
+        map.set(1, 5, 1);
+        map
+    }
 
-                let tex_r = texture[tex_index] as f32;
-                let tex_g = texture[tex_index + 1] as f32;
-                let tex_b = texture[tex_index + 2] as f32;
-                let tex_a = texture[tex_index + 3] as f32 / 255.0; // Normalize alpha to [0,1]
+    fn hits(map: &Map, cos_angle: f64, sin_angle: f64) -> Vec<(i32, i32)> {
+        let mut player = Player::new();
+        (player.x, player.y) = (1.5, 2.5);
+        let stops = StopHeights::new(map, 0.5);
+        cast_ray(map, &player, cos_angle, sin_angle, &stops, &[], false)
+            .iter()
+            .map(|ray| ((player.x + ray.distance * cos_angle) as i32, (player.y + ray.distance * sin_angle) as i32))
+            .collect()
+    }
 
-                // Skip fully transparent pixels
-                if tex_a <= 0.0 {
-                    continue;
-                }
+    #[test]
+    fn rays_carry_on_only_while_something_taller_lies_ahead() {
+        let map = room();
+        // The tall wall shows over the low one in front of it, and stops the ray itself
+        assert_eq!(hits(&map, 1.0, 0.0), vec![(5, 2), (8, 2)]);
+        // Nothing beyond the southern wall is taller, so it hides the rest
+        assert_eq!(hits(&map, 0.0, 1.0), vec![(1, 5)]);
+    }
 
-                let screen_index = (y as usize * renderer.screen_width + stripe as usize) * 4;
-
-                let bg_r = renderer.framebuffer[screen_index] as f32;
-                let bg_g = renderer.framebuffer[screen_index + 1] as f32;
-                let bg_b = renderer.framebuffer[screen_index + 2] as f32;
-                let bg_a = renderer.framebuffer[screen_index + 3] as f32 / 255.0;
-
-                let out_a = tex_a + bg_a * (1.0 - tex_a);
-                if out_a > 0.0 {
-                    let out_r = (tex_r * tex_a + bg_r * bg_a * (1.0 - tex_a)) / out_a;
-                    let out_g = (tex_g * tex_a + bg_g * bg_a * (1.0 - tex_a)) / out_a;
-                    let out_b = (tex_b * tex_a + bg_b * bg_a * (1.0 - tex_a)) / out_a;
-    
-                    renderer.framebuffer[screen_index] = out_r as u8;
-                    renderer.framebuffer[screen_index + 1] = out_g as u8;
-                    renderer.framebuffer[screen_index + 2] = out_b as u8;
-                    renderer.framebuffer[screen_index + 3] = (out_a * 255.0) as u8;
-                } else {
-                    renderer.framebuffer[screen_index + 3] = 0;
-                }
-            }
+    #[test]
+    fn mirrors_reflect_across_their_plane() {
+        let wall = MirrorPlane { start: (2.0, 0.0), end: (2.0, 1.0) };
+        assert_eq!(wall.reflect_direction(1.0, 1.0), (-1.0, 1.0));
+        assert_eq!(wall.reflect_point(3.0, 5.0), (1.0, 5.0));
+        assert!(wall.side(3.0, 0.5) * wall.side(1.0, 0.5) < 0.0);
+
+        let diagonal = MirrorPlane { start: (0.0, 0.0), end: (1.0, 1.0) };
+        let (x, y) = diagonal.reflect_direction(1.0, 0.0);
+        assert!(x.abs() < 1e-9 && (y - 1.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn facing_mirrors_stop_bouncing_after_max_bounces() {
+        // A corridor with a mirror at each end
+        let mut map = Map::filled(5, 3, 1);
+        for x in 1..4 {
+            map.set(x, 1, 0);
+        }
+        map.set(0, 1, MIRROR_TEXTURE);
+        map.set(4, 1, MIRROR_TEXTURE);
+        let mut player = Player::new();
+        (player.x, player.y) = (2.5, 1.5);
+
+        let rays = cast_ray(&map, &player, 1.0, 0.0, &StopHeights::new(&map, 0.5), &[], false);
+        assert_eq!(rays.len(), MAX_BOUNCES as usize + 1);
+        assert!(rays[..MAX_BOUNCES as usize].iter().all(|ray| matches!(ray.opening, Some(Opening::Mirror(_)))));
+        let last = &rays[MAX_BOUNCES as usize];
+        assert!(last.opening.is_none() && last.texture_id == MIRROR_TEXTURE);
+        assert!((last.distance - 10.5).abs() < 1e-9);
+    }
+
+    #[test]
+    fn portals_leading_back_on_themselves_stop_after_max_portals() {
+        // A corridor whose east end opens out of its own west end
+        let mut map = Map::filled(5, 3, 1);
+        for x in 1..4 {
+            map.set(x, 1, 0);
         }
+        map.portals.push(Portal {
+            from: PortalFace { x: 4, y: 1, normal: (-1, 0) },
+            to: PortalFace { x: 0, y: 1, normal: (1, 0) },
+        });
+        let mut player = Player::new();
+        (player.x, player.y) = (2.5, 1.5);
+
+        let rays = cast_ray(&map, &player, 1.0, 0.0, &StopHeights::new(&map, 0.5), &[], false);
+        assert_eq!(rays.len(), MAX_PORTALS as usize + 1);
+        assert!(rays[..MAX_PORTALS as usize].iter().all(|ray| matches!(ray.opening, Some(Opening::Portal(_)))));
+        assert!(rays[MAX_PORTALS as usize].opening.is_none());
+        assert!((rays[MAX_PORTALS as usize].distance - (1.5 + 3.0 * MAX_PORTALS as f64)).abs() < 1e-9);
     }
 
-    renderer.flush();
+    #[test]
+    fn each_mirror_tints_its_reflection_again() {
+        let mut pixel = [255, 255, 255, 255];
+        for _ in 0..2 {
+            tint(&mut pixel[..3]);
+        }
+        assert_eq!(pixel, [163, 183, 206, 255]);
+    }
+
+    #[test]
+    fn walls_below_the_camera_never_stop_rays() {
+        let map = room();
+        let stops = StopHeights::new(&map, 1.5);
+        assert_eq!(stops.at(5, 2, 1, -1), 2.0);
+        assert_eq!(stops.at(1, 5, -1, 1), 1.5);
+    }
 }
@@ -1,17 +1,230 @@
 use wasm_bindgen::JsCast;
-use web_sys::{CanvasRenderingContext2d, HtmlCanvasElement, HtmlImageElement};
-use crate::game::Player;
-use crate::sprites::Sprite;
+use web_sys::{CanvasRenderingContext2d, HtmlCanvasElement};
+use crate::console_log;
+use crate::dungeon::EXIT_TEXTURE;
+use crate::font::{BitmapFont, TextStyle};
+use crate::raycasting::SceneBuffers;
+
+/// Largest the minimap grows to on screen, in pixels.
+const MINIMAP_MAX_SIZE: f64 = 160.0;
+
+/// An RGBA image kept at its natural size, used for HUD graphics.
+pub struct Bitmap {
+    pub width: usize,
+    pub height: usize,
+    pub pixels: Vec<u8>,
+}
+
+#[derive(Clone, Copy)]
+pub struct Rect {
+    pub x: i32,
+    pub y: i32,
+    pub width: i32,
+    pub height: i32,
+}
+
+impl Rect {
+    pub fn new(x: i32, y: i32, width: i32, height: i32) -> Self {
+        Rect { x, y, width, height }
+    }
+}
+
+/// How wall textures are sampled.
+#[derive(Clone, Copy, PartialEq)]
+pub enum TextureFilter {
+    /// The nearest texel of the full-size texture: sharpest, but distant walls shimmer.
+    Nearest,
+    /// The nearest texel of whichever mip level best fits the wall's size on screen.
+    Mipmapped,
+    /// A blend of the four nearest texels of the best fitting mip level.
+    Bilinear,
+}
+
+impl TextureFilter {
+    pub const ALL: [TextureFilter; 3] = [TextureFilter::Nearest, TextureFilter::Mipmapped, TextureFilter::Bilinear];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            TextureFilter::Nearest => "Nearest",
+            TextureFilter::Mipmapped => "Mipmapped",
+            TextureFilter::Bilinear => "Bilinear",
+        }
+    }
+}
+
+/// A wall or sprite texture. Textures can be any size and needn't be square; walls repeat theirs
+/// once per map unit whatever its size.
+pub struct Texture {
+    pub width: usize,
+    pub height: usize,
+    pub pixels: Vec<u8>,
+    /// Ever smaller copies for drawing the texture far away, each half the size of the one before,
+    /// down to a single texel.
+    pub mips: Vec<Texture>,
+}
+
+/// Drawn in place of an image that has no pixels, so it stands out rather than vanishing.
+const MISSING_TEXEL: [u8; 4] = [255, 0, 255, 255];
+
+impl Texture {
+    /// A texture from RGBA pixels. One with no pixels, or too few for its size, becomes a single
+    /// magenta texel instead, so a texture is never empty.
+    pub fn new(width: usize, height: usize, pixels: Vec<u8>) -> Self {
+        let (width, height, pixels) = if width == 0 || height == 0 || pixels.len() < width * height * 4 {
+            (1, 1, MISSING_TEXEL.to_vec())
+        } else {
+            (width, height, pixels)
+        };
+        let mut texture = Texture { width, height, pixels, mips: Vec::new() };
+        let mut mips: Vec<Texture> = Vec::new();
+        while let Some(smaller) = mips.last().unwrap_or(&texture).halved() {
+            mips.push(smaller);
+        }
+        texture.mips = mips;
+        texture
+    }
+
+    /// The texture at half the size, each texel the average of the two by two it covers, or None
+    /// once it is down to one texel. Colours are weighted by alpha so that transparent texels
+    /// don't darken their neighbours.
+    fn halved(&self) -> Option<Texture> {
+        if self.width == 1 && self.height == 1 {
+            return None;
+        }
+        let (width, height) = ((self.width / 2).max(1), (self.height / 2).max(1));
+        let mut pixels = Vec::with_capacity(width * height * 4);
+        for y in 0..height {
+            for x in 0..width {
+                let texels = [(0, 0), (1, 0), (0, 1), (1, 1)]
+                    .map(|(dx, dy)| self.pixel((x * 2 + dx).min(self.width - 1), (y * 2 + dy).min(self.height - 1)));
+                pixels.extend_from_slice(&blend(texels, [0.25; 4]));
+            }
+        }
+        Some(Texture { width, height, pixels, mips: Vec::new() })
+    }
+
+    /// The mip level that best fits the texture's full height being drawn `projected_height`
+    /// pixels tall on screen: 0 for the texture itself, or the index into `mips` plus one.
+    pub fn mip_level(&self, projected_height: f64) -> usize {
+        let texels_per_pixel = self.height as f64 / projected_height;
+        if texels_per_pixel <= 1.0 {
+            return 0;
+        }
+        (texels_per_pixel.log2() as usize).min(self.mips.len())
+    }
+
+    /// The texture at mip level `level`, as chosen by `mip_level`.
+    pub fn mip(&self, level: usize) -> &Texture {
+        match level {
+            0 => self,
+            _ => &self.mips[level - 1],
+        }
+    }
+
+    /// The texture to sample with `filter` for a surface it covers `projected_height` pixels tall:
+    /// the best fitting mip level, unless the filter doesn't use them.
+    pub fn filtered(&self, filter: TextureFilter, projected_height: f64) -> &Texture {
+        match filter {
+            TextureFilter::Nearest => self,
+            TextureFilter::Mipmapped | TextureFilter::Bilinear => self.mip(self.mip_level(projected_height)),
+        }
+    }
+
+    /// The colour at (u, v), each from 0 to 1 across and down the texture, sampled with `filter`.
+    pub fn sample(&self, u: f64, v: f64, filter: TextureFilter) -> (u8, u8, u8, u8) {
+        match filter {
+            TextureFilter::Nearest | TextureFilter::Mipmapped => self.pixel(self.column(u), self.row(v)),
+            TextureFilter::Bilinear => self.bilinear(u, v),
+        }
+    }
+
+    /// The column `u` of the way across, from 0 at the left edge to 1 at the right.
+    pub fn column(&self, u: f64) -> usize {
+        ((u * self.width as f64) as usize).min(self.width - 1)
+    }
+
+    /// The row `v` of the way down, from 0 at the top to 1 at the bottom.
+    pub fn row(&self, v: f64) -> usize {
+        ((v * self.height as f64) as usize).min(self.height - 1)
+    }
+
+    /// The colour at (x, y), wrapping around past the edges.
+    pub fn pixel(&self, x: usize, y: usize) -> (u8, u8, u8, u8) {
+        let index = (wrap(y, self.height) * self.width + wrap(x, self.width)) * 4;
+        (self.pixels[index], self.pixels[index + 1], self.pixels[index + 2], self.pixels[index + 3])
+    }
+
+    /// The colour at (u, v) blended from the four nearest texels, wrapping around past the edges.
+    pub fn bilinear(&self, u: f64, v: f64) -> (u8, u8, u8, u8) {
+        let x = u * self.width as f64 - 0.5;
+        let y = v * self.height as f64 - 0.5;
+        let (left, top) = (x.floor(), y.floor());
+        let (across, down) = (x - left, y - top);
+        // A whole texture further on so that the texels left of or above 0 wrap round
+        let left = (left as isize + self.width as isize) as usize;
+        let top = (top as isize + self.height as isize) as usize;
+        let texels = [
+            self.pixel(left, top),
+            self.pixel(left + 1, top),
+            self.pixel(left, top + 1),
+            self.pixel(left + 1, top + 1),
+        ];
+        let weights = [
+            (1.0 - across) * (1.0 - down),
+            across * (1.0 - down),
+            (1.0 - across) * down,
+            across * down,
+        ];
+        let [r, g, b, a] = blend(texels, weights);
+        (r, g, b, a)
+    }
+}
+
+/// The weighted average of four texels, with colours also weighted by alpha.
+fn blend(texels: [(u8, u8, u8, u8); 4], weights: [f64; 4]) -> [u8; 4] {
+    let alpha: f64 = texels.iter().zip(weights).map(|(texel, weight)| texel.3 as f64 * weight).sum();
+    let channel = |value: fn(&(u8, u8, u8, u8)) -> u8| {
+        let (sum, total) = texels.iter().zip(weights).fold((0.0, 0.0), |(sum, total), (texel, weight)| {
+            // Fully transparent texels only count if every texel is, so the colour is still defined
+            let weight = if alpha > 0.0 { weight * texel.3 as f64 } else { weight };
+            (sum + value(texel) as f64 * weight, total + weight)
+        });
+        if total > 0.0 {
+            (sum / total).round() as u8
+        } else {
+            0
+        }
+    };
+    [channel(|texel| texel.0), channel(|texel| texel.1), channel(|texel| texel.2), alpha.round() as u8]
+}
+
+/// `value` wrapped into 0..size, with a mask rather than a division when the size is a power of two.
+fn wrap(value: usize, size: usize) -> usize {
+    if size.is_power_of_two() {
+        value & (size - 1)
+    } else {
+        value % size
+    }
+}
 
 pub struct Renderer {
     pub context: CanvasRenderingContext2d,
+    pub canvas: HtmlCanvasElement,
+    // The framebuffer is uploaded here first, then stretched onto the visible canvas
+    buffer_canvas: HtmlCanvasElement,
+    buffer_context: CanvasRenderingContext2d,
     pub framebuffer: Vec<u8>, // Store the entire screen in a buffer
-    pub textures: Vec<Vec<u8>>,
-    pub texture_width: usize,
-    pub texture_height: usize,
+    pub textures: Vec<Texture>,
+    /// Whether each texture is a see-through material, like a grate or glass. Walls using one don't
+    /// stop rays, so whatever is behind them gets drawn too.
+    pub see_through: Vec<bool>,
+    /// How walls are sampled, from the settings.
+    pub texture_filter: TextureFilter,
     pub screen_width: usize,
     pub screen_height: usize,
-
+    pub font: BitmapFont,
+    /// Depth and what was seen through each pixel, filled in by the wall pass for the sprites.
+    pub scene_buffers: SceneBuffers,
 }
 
 impl Renderer {
@@ -30,19 +243,60 @@ impl Renderer {
 
             let screen_width = canvas.width() as usize;
             let screen_height = canvas.height() as usize;
-    
+
+            let buffer_canvas = web_sys::window()
+                .unwrap()
+                .document()
+                .unwrap()
+                .create_element("canvas")
+                .unwrap()
+                .dyn_into::<HtmlCanvasElement>()
+                .unwrap();
+            buffer_canvas.set_width(screen_width as u32);
+            buffer_canvas.set_height(screen_height as u32);
+
+            let buffer_context = buffer_canvas
+                .get_context("2d")
+                .unwrap()
+                .unwrap()
+                .dyn_into::<CanvasRenderingContext2d>()
+                .unwrap();
+
+            // Keep pixels crisp when the framebuffer is rendered below canvas resolution
+            context.set_image_smoothing_enabled(false);
     
             Renderer {
                 context,
+                canvas,
+                buffer_canvas,
+                buffer_context,
                 framebuffer: vec![0; screen_width * screen_height * 4], // RGBA buffer
                 textures: Vec::new(),
-                texture_width: 64,
-                texture_height: 64,
-                screen_height: screen_height,
-                screen_width: screen_width
+                see_through: Vec::new(),
+                texture_filter: TextureFilter::Nearest,
+                screen_height,
+                screen_width,
+                font: BitmapFont::builtin(),
+                scene_buffers: SceneBuffers::new(screen_width * screen_height),
             }
     }
 
+    /// Resizes the framebuffer to a fraction of the canvas resolution.
+    pub fn set_resolution_scale(&mut self, scale: f64) {
+        let screen_width = ((self.canvas.width() as f64 * scale).round() as usize).max(1);
+        let screen_height = ((self.canvas.height() as f64 * scale).round() as usize).max(1);
+        if screen_width == self.screen_width && screen_height == self.screen_height {
+            return;
+        }
+
+        self.screen_width = screen_width;
+        self.screen_height = screen_height;
+        self.framebuffer = vec![0; screen_width * screen_height * 4];
+        self.scene_buffers = SceneBuffers::new(screen_width * screen_height);
+        self.buffer_canvas.set_width(screen_width as u32);
+        self.buffer_canvas.set_height(screen_height as u32);
+    }
+
     pub fn clear(&self) {
         self.context.clear_rect(0.0, 0.0, 800.0, 600.0);
     }
@@ -71,7 +325,8 @@ impl Renderer {
         player_y: f64,
         player_dir: f64,
     ) {
-        let scale = 10.0;
+        // Shrink large generated maps so they don't cover the view
+        let scale = (MINIMAP_MAX_SIZE / map_width.max(map_height) as f64).min(10.0);
         let offset_x = 20.0;
         let offset_y = 20.0;
     
@@ -87,8 +342,12 @@ impl Renderer {
         for y in 0..map_height {
             for x in 0..map_width {
                 let tile_value = map[y * map_width + x];
-                let color = if tile_value > 0 && tile_value <= texture_colors.len() as u8 {
+                let color = if tile_value == EXIT_TEXTURE {
+                    "lime"
+                } else if tile_value > 0 && tile_value <= texture_colors.len() as u8 {
                     texture_colors[(tile_value - 1) as usize] // Adjust for 0-based index
+                } else if tile_value > 0 {
+                    "gray"
                 } else {
                     "lightgray" // Empty space
                 };
@@ -119,22 +378,112 @@ impl Renderer {
         self.context.stroke();
     }
     
-    pub fn load_texture(&mut self, texture_id: &str) {
+    /// Loads an `<img>` element as a texture, at whatever size the image is.
+    pub fn load_texture(&mut self, texture_id: &str, see_through: bool) {
+        let bitmap = self.load_bitmap(texture_id);
+        if bitmap.width == 0 || bitmap.height == 0 {
+            console_log!("Texture `{}` has no pixels, drawing a placeholder instead", texture_id);
+        }
+        let texture = Texture::new(bitmap.width, bitmap.height, bitmap.pixels);
+        self.see_through.push(see_through);
+        self.textures.push(texture);
+    }
+
+    /// Draws text into the framebuffer with the current font. See `BitmapFont::layout` for how
+    /// (x, y) anchors the text.
+    pub fn draw_text(&mut self, x: i32, y: i32, text: &str, style: &TextStyle) {
+        let (r, g, b) = style.color;
+        let tint = [r as u32, g as u32, b as u32];
+        let page = &self.font.page;
+
+        for (src, dest) in self.font.layout(x, y, text, style) {
+            for dy in 0..dest.height {
+                let py = dest.y + dy;
+                if py < 0 || py >= self.screen_height as i32 {
+                    continue;
+                }
+                let sy = (src.y + dy * src.height / dest.height) as usize;
+
+                for dx in 0..dest.width {
+                    let px = dest.x + dx;
+                    if px < 0 || px >= self.screen_width as i32 {
+                        continue;
+                    }
+                    let sx = (src.x + dx * src.width / dest.width) as usize;
+
+                    let src_index = (sy * page.width + sx) * 4;
+                    let alpha = page.pixels[src_index + 3] as u32;
+                    if alpha == 0 {
+                        continue;
+                    }
+
+                    let screen_index = (py as usize * self.screen_width + px as usize) * 4;
+                    for (channel, tint) in tint.iter().enumerate() {
+                        let color = page.pixels[src_index + channel] as u32 * tint / 255;
+                        let background = self.framebuffer[screen_index + channel] as u32;
+                        self.framebuffer[screen_index + channel] = ((color * alpha + background * (255 - alpha)) / 255) as u8;
+                    }
+                    self.framebuffer[screen_index + 3] = 255;
+                }
+            }
+        }
+    }
+
+    pub fn flush(&self) {
+        let image_data = web_sys::ImageData::new_with_u8_clamped_array_and_sh(
+            wasm_bindgen::Clamped(&self.framebuffer),
+            self.screen_width as u32,
+            self.screen_height as u32,
+        )
+        .unwrap();
+
+        self.buffer_context.put_image_data(&image_data, 0.0, 0.0).unwrap();
+
+        let canvas_width = self.canvas.width() as f64;
+        let canvas_height = self.canvas.height() as f64;
+
+        // Clear the canvas before drawing the new frame
+        self.context.clear_rect(0.0, 0.0, canvas_width, canvas_height);
+
+        // Draw the framebuffer to the canvas, stretched to fill it
+        self.context
+            .draw_image_with_html_canvas_element_and_dw_and_dh(&self.buffer_canvas, 0.0, 0.0, canvas_width, canvas_height)
+            .unwrap();
+    }
+
+    /// Darkens the whole framebuffer, e.g. behind a menu. `amount` is the brightness kept.
+    pub fn dim(&mut self, amount: f64) {
+        for pixel in self.framebuffer.chunks_exact_mut(4) {
+            for channel in &mut pixel[..3] {
+                *channel = (*channel as f64 * amount) as u8;
+            }
+        }
+    }
+
+    /// Loads an `<img>` element at its natural size.
+    pub fn load_bitmap(&self, image_id: &str) -> Bitmap {
         let document = web_sys::window().unwrap().document().unwrap();
         let img_element = document
-            .get_element_by_id(texture_id)
+            .get_element_by_id(image_id)
             .unwrap()
             .dyn_into::<web_sys::HtmlImageElement>()
             .unwrap();
 
+        let width = img_element.natural_width();
+        let height = img_element.natural_height();
+        // Not loaded, or failed to decode. There is nothing to read back from the canvas.
+        if width == 0 || height == 0 {
+            return Bitmap { width: 0, height: 0, pixels: Vec::new() };
+        }
+
         let canvas = document
             .create_element("canvas")
             .unwrap()
             .dyn_into::<HtmlCanvasElement>()
             .unwrap();
 
-        canvas.set_width(self.texture_width as u32);
-        canvas.set_height(self.texture_height as u32);
+        canvas.set_width(width);
+        canvas.set_height(height);
 
         let context = canvas
             .get_context("2d")
@@ -148,94 +497,99 @@ impl Renderer {
             .unwrap();
 
         let image_data = context
-            .get_image_data(0.0, 0.0, self.texture_width as f64, self.texture_height as f64)
+            .get_image_data(0.0, 0.0, width as f64, height as f64)
             .unwrap();
 
-        let texture_data = image_data.data().to_vec();
-        self.textures.push(texture_data);
-    }
-
-
-    pub fn get_texture_color(&self, texture_index: usize, tex_x: usize, tex_y: usize) -> String {
-        if texture_index >= self.textures.len() {
-            return "black".to_string();
+        Bitmap {
+            width: width as usize,
+            height: height as usize,
+            pixels: image_data.data().to_vec(),
         }
-
-        let texture = &self.textures[texture_index];
-        let tex_width = self.texture_width;
-        let tex_height = self.texture_height;
-
-        let index = (tex_y * tex_width + tex_x) * 4;
-
-        if index + 3 >= texture.len() {
-            return "black".to_string();
-        }
-
-        let r = texture[index];
-        let g = texture[index + 1];
-        let b = texture[index + 2];
-        format!("rgb({}, {}, {})", r, g, b)
-    }
-
-    pub fn draw_text(&self, x: f64, y: f64, text: &str) {
-        self.context.set_fill_style_str("white");
-        self.context.set_font("16px Arial");
-        self.context.fill_text(text, x, y).unwrap();
     }
 
-    pub fn flush(&self) {
-        let image_data = web_sys::ImageData::new_with_u8_clamped_array_and_sh(
-            wasm_bindgen::Clamped(&self.framebuffer),
-            self.screen_width as u32,
-            self.screen_height as u32,
-        )
-        .unwrap();
-
-        // Clear the canvas before drawing the new frame
-        self.context.clear_rect(0.0, 0.0, self.screen_width as f64, self.screen_height as f64);
+    /// Copies the `src` region of a bitmap into the `dest` region of the framebuffer, stretching
+    /// it with nearest-neighbour sampling and alpha blending over what is already there.
+    pub fn blit_bitmap(&mut self, bitmap: &Bitmap, src: Rect, dest: Rect) {
+        if dest.width <= 0 || dest.height <= 0 {
+            return;
+        }
 
-        // Draw the framebuffer to the canvas
-        self.context.put_image_data(&image_data, 0.0, 0.0).unwrap();
-    }
+        for dy in 0..dest.height {
+            let py = dest.y + dy;
+            if py < 0 || py >= self.screen_height as i32 {
+                continue;
+            }
+            let sy = src.y + dy * src.height / dest.height;
+            if sy < 0 || sy >= bitmap.height as i32 {
+                continue;
+            }
 
-    pub fn draw_rect(&mut self, x: f64, y: f64, width: f64, height: f64, color: Option<(u8, u8, u8)>) {
-        let (r, g, b) = color.unwrap_or((0, 0, 0));
+            for dx in 0..dest.width {
+                let px = dest.x + dx;
+                if px < 0 || px >= self.screen_width as i32 {
+                    continue;
+                }
+                let sx = src.x + dx * src.width / dest.width;
+                if sx < 0 || sx >= bitmap.width as i32 {
+                    continue;
+                }
 
-        let start_x = x as u32;
-        let start_y = y as u32;
-        let end_x = (x + width) as u32;
-        let end_y = (y + height) as u32;
+                let src_index = (sy as usize * bitmap.width + sx as usize) * 4;
+                let alpha = bitmap.pixels[src_index + 3] as u32;
+                if alpha == 0 {
+                    continue;
+                }
 
-        for px in start_x..end_x {
-            for py in start_y..end_y {
-                if px < self.screen_width as u32 && py < self.screen_height as u32 {
-                    let index = ((py * self.screen_width as u32 + px) * 4) as usize;
-                    self.framebuffer[index] = r;
-                    self.framebuffer[index + 1] = g;
-                    self.framebuffer[index + 2] = b;
-                    self.framebuffer[index + 3] = 255; // Alpha channel
+                let screen_index = (py as usize * self.screen_width + px as usize) * 4;
+                for channel in 0..3 {
+                    let color = bitmap.pixels[src_index + channel] as u32;
+                    let background = self.framebuffer[screen_index + channel] as u32;
+                    self.framebuffer[screen_index + channel] = ((color * alpha + background * (255 - alpha)) / 255) as u8;
                 }
+                self.framebuffer[screen_index + 3] = 255;
             }
         }
     }
 
-    pub fn get_texture_color_rgb(&self, texture_index: usize, tex_x: usize, tex_y: usize) -> (u8, u8, u8) {
+    /// Draws a texture into the framebuffer at (x, y), scaled up by an integer factor.
+    /// Pixels are alpha blended over the existing contents; fully transparent pixels are skipped.
+    pub fn blit_texture(&mut self, texture_index: usize, x: i32, y: i32, scale: usize) {
         if texture_index >= self.textures.len() {
-            return (0, 0, 0); // Return black if texture not found
+            return;
         }
 
-        let texture = &self.textures[texture_index];
-        let tex_width = self.texture_width;
-        let index = (tex_y * tex_width + tex_x) * 4;
+        let (width, height) = (self.textures[texture_index].width, self.textures[texture_index].height);
+        for dest_y in 0..(height * scale) as i32 {
+            let py = y + dest_y;
+            if py < 0 || py >= self.screen_height as i32 {
+                continue;
+            }
 
-        if index + 3 >= texture.len() {
-            return (0, 0, 0); // Out of bounds, return black
-        }
+            for dest_x in 0..(width * scale) as i32 {
+                let px = x + dest_x;
+                if px < 0 || px >= self.screen_width as i32 {
+                    continue;
+                }
+
+                let tex_x = dest_x as usize / scale;
+                let tex_y = dest_y as usize / scale;
+                let tex_index = (tex_y * width + tex_x) * 4;
+                let texture = &self.textures[texture_index].pixels;
+
+                let alpha = texture[tex_index + 3] as u32;
+                if alpha == 0 {
+                    continue;
+                }
 
-        let r = texture[index];
-        let g = texture[index + 1];
-        let b = texture[index + 2];
-        (r, g, b)
+                let screen_index = (py as usize * self.screen_width + px as usize) * 4;
+                for channel in 0..3 {
+                    let src = texture[tex_index + channel] as u32;
+                    let dst = self.framebuffer[screen_index + channel] as u32;
+                    self.framebuffer[screen_index + channel] = ((src * alpha + dst * (255 - alpha)) / 255) as u8;
+                }
+                self.framebuffer[screen_index + 3] = 255;
+            }
+        }
     }
 
     pub fn clear_framebuffer(&mut self) {
@@ -243,72 +597,80 @@ impl Renderer {
             self.framebuffer[i] = 0; // Set all pixels to black (RGBA = 0)
         }
     }
+}
 
-    pub fn render_sprites(&mut self, player: &Player, sprites: &Vec<Sprite>) {
-        let screen_width = self.screen_width as f64;
-        let screen_height = self.screen_height as f64;
-        let half_screen_height = screen_height / 2.0;
-
-        for sprite in sprites {
-            let sprite_dir_x = sprite.x - player.x;
-            let sprite_dir_y = sprite.y - player.y;
-
-            // Calculate the distance to the sprite
-            let sprite_distance = (sprite_dir_x.powi(2) + sprite_dir_y.powi(2)).sqrt();
-
-            // Calculate angle to the sprite relative to the player's direction
-            let sprite_angle = (sprite_dir_y.atan2(sprite_dir_x) - player.direction).to_degrees();
-
-            // Check if the sprite is in the player's field of view
-            if sprite_angle.abs() > player.fov.to_degrees() / 2.0 {
-                continue;
-            }
-
-            // Calculate the height of the sprite on the screen
-            let sprite_height = (screen_height / sprite_distance) as i32;
-            let draw_start_y = (-sprite_height / 2 + half_screen_height as i32).max(0);
-            let draw_end_y = (sprite_height / 2 + half_screen_height as i32).min(screen_height as i32 - 1);
-
-            // Calculate the horizontal position of the sprite
-            let sprite_screen_x = (screen_width / 2.0) * (1.0 + sprite_dir_x / sprite_distance);
-            let sprite_width = sprite_height;
-            let draw_start_x = (sprite_screen_x - sprite_width as f64 / 2.0) as i32;
-            let draw_end_x = (sprite_screen_x + sprite_width as f64 / 2.0) as i32;
-
-            // Draw the sprite using its texture
-            let texture_index = sprite.texture_id - 1;
-            if texture_index >= self.textures.len() {
-                continue;
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn empty_textures_become_a_placeholder() {
+        for texture in [Texture::new(0, 0, Vec::new()), Texture::new(4, 4, vec![0; 8])] {
+            assert_eq!((texture.width, texture.height), (1, 1));
+            assert!(texture.mips.is_empty());
+            for filter in TextureFilter::ALL {
+                assert_eq!(texture.sample(1.0, 1.0, filter), (255, 0, 255, 255));
             }
+            assert_eq!(texture.column(0.5), 0);
+            assert_eq!(texture.row(0.5), 0);
+        }
+    }
 
-            let texture = &self.textures[texture_index];
-
-            for x in draw_start_x..draw_end_x {
-                if x < 0 || x >= self.screen_width as i32 {
-                    continue;
-                }
-
-                let tex_x = ((x - draw_start_x) * self.texture_width as i32) / sprite_width;
-
-                for y in draw_start_y..draw_end_y {
-                    let d = y * 256 - (screen_height as i32 * 128) + sprite_height * 128;
-                    let tex_y = (d * self.texture_height as i32) / sprite_height / 256;
-
-                    let index = ((tex_y * self.texture_width as i32 + tex_x ) * 4) as usize;
-                    let r = texture[index];
-                    let g = texture[index + 1];
-                    let b = texture[index + 2];
+    fn solid(width: usize, height: usize) -> Texture {
+        Texture::new(width, height, vec![255; width * height * 4])
+    }
 
-                    let screen_index = (y as usize * self.screen_width + x as usize) * 4;
-                    self.framebuffer[screen_index] = r;
-                    self.framebuffer[screen_index + 1] = g;
-                    self.framebuffer[screen_index + 2] = b;
-                    self.framebuffer[screen_index + 3] = 255;
-                }
-            }
+    #[test]
+    fn mip_chain_halves_down_to_one_texel() {
+        for (width, height, sizes) in [
+            (8, 8, vec![(4, 4), (2, 2), (1, 1)]),
+            (8, 2, vec![(4, 1), (2, 1), (1, 1)]),
+            (5, 3, vec![(2, 1), (1, 1)]),
+            (1, 1, vec![]),
+        ] {
+            let texture = solid(width, height);
+            let found: Vec<(usize, usize)> = texture.mips.iter().map(|mip| (mip.width, mip.height)).collect();
+            assert_eq!(found, sizes);
+            assert!(texture.mips.iter().all(|mip| mip.pixels.len() == mip.width * mip.height * 4));
         }
     }
 
+    #[test]
+    fn mip_level_matches_projected_height() {
+        let texture = solid(64, 64);
+        assert_eq!(texture.mips.len(), 6);
+        // Magnified or drawn at its own size, the full texture is used
+        assert_eq!(texture.mip_level(640.0), 0);
+        assert_eq!(texture.mip_level(64.0), 0);
+        // Each halving of the height on screen steps down one level
+        assert_eq!(texture.mip_level(32.0), 1);
+        assert_eq!(texture.mip_level(20.0), 1);
+        assert_eq!(texture.mip_level(16.0), 2);
+        // Never past the last, single texel level
+        assert_eq!(texture.mip_level(1.0), 6);
+        assert_eq!(texture.mip_level(0.01), 6);
+        assert_eq!(texture.mip(6).width, 1);
+    }
 
-    
-}
\ No newline at end of file
+    #[test]
+    fn bilinear_wraps_at_the_edges() {
+        // Four differently coloured texels, two by two
+        let texels = [[200, 0, 0, 255], [0, 200, 0, 255], [0, 0, 200, 255], [100, 100, 100, 255]];
+        let texture = Texture::new(2, 2, texels.concat());
+
+        // Texel centres give back exactly that texel
+        assert_eq!(texture.bilinear(0.25, 0.25), (200, 0, 0, 255));
+        assert_eq!(texture.bilinear(0.75, 0.75), (100, 100, 100, 255));
+
+        // The corners lie halfway between texels on opposite sides, so all four blend evenly, and
+        // 0 and 1 wrap round to the same place
+        let corner = texture.bilinear(0.0, 0.0);
+        assert_eq!(corner, (75, 75, 75, 255));
+        assert_eq!(texture.bilinear(1.0, 1.0), corner);
+        assert_eq!(texture.bilinear(0.0, 1.0), corner);
+
+        // Along the left edge, halfway between the two columns of the same row
+        assert_eq!(texture.bilinear(0.0, 0.25), texture.bilinear(1.0, 0.25));
+        assert_eq!(texture.bilinear(0.0, 0.25), (100, 100, 0, 255));
+    }
+}
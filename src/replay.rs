@@ -0,0 +1,191 @@
+use crate::level::Episode;
+use crate::save::{Persist, SaveReader, SaveWriter};
+use crate::settings::Action;
+use crate::simulation::TICKS_PER_SECOND;
+
+const REPLAY_MAGIC: &[u8; 4] = b"RCRP";
+pub const REPLAY_VERSION: u16 = 10;
+
+/// Longest replay that will be loaded, in ticks: four hours at the simulation rate. Anything longer
+/// is taken to be a corrupt file rather than allocated.
+const MAX_REPLAY_TICKS: usize = 4 * 60 * 60 * TICKS_PER_SECOND as usize;
+
+/// Number of weapon slot keys recorded after the actions.
+pub const WEAPON_SLOTS: usize = 3;
+
+/// Everything the simulation reads from the player in one tick, packed one bit per control.
+#[derive(Clone, Copy, PartialEq, Default)]
+pub struct TickInput {
+    bits: u16,
+}
+
+impl TickInput {
+    pub fn action(self, action: Action) -> bool {
+        self.bits & (1 << action as u16) != 0
+    }
+
+    pub fn set_action(&mut self, action: Action, down: bool) {
+        self.set(action as usize, down);
+    }
+
+    /// Whether the key selecting weapon `slot` (0 for the first) is held.
+    pub fn weapon_slot(self, slot: usize) -> bool {
+        self.bits & (1 << (Action::ALL.len() + slot)) != 0
+    }
+
+    pub fn set_weapon_slot(&mut self, slot: usize, down: bool) {
+        self.set(Action::ALL.len() + slot, down);
+    }
+
+    fn set(&mut self, bit: usize, down: bool) {
+        if down {
+            self.bits |= 1 << bit;
+        } else {
+            self.bits &= !(1 << bit);
+        }
+    }
+}
+
+/// The seed a run started from and the input for each of its ticks, enough to play the run back exactly.
+pub struct Replay {
+    pub seed: u64,
+    /// Turning sensitivity the run was played with, since it scales how far each turning tick goes.
+    pub sensitivity: f64,
+    pub episode: Episode,
+    pub inputs: Vec<TickInput>,
+}
+
+impl Replay {
+    pub fn new(seed: u64, sensitivity: f64, episode: Episode) -> Self {
+        Replay { seed, sensitivity, episode, inputs: Vec::new() }
+    }
+
+    pub fn record(&mut self, input: TickInput) {
+        self.inputs.push(input);
+    }
+
+    /// Input rarely changes from one tick to the next, so it is stored as runs of identical ticks.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut runs: Vec<(TickInput, u32)> = Vec::new();
+        for input in &self.inputs {
+            match runs.last_mut() {
+                Some((last, count)) if last == input => *count += 1,
+                _ => runs.push((*input, 1)),
+            }
+        }
+
+        let mut writer = SaveWriter::with_header(REPLAY_MAGIC, REPLAY_VERSION);
+        writer.u64(self.seed);
+        writer.f64(self.sensitivity);
+        self.episode.save(&mut writer);
+        writer.u32(runs.len() as u32);
+        for (input, count) in runs {
+            writer.u16(input.bits);
+            writer.u32(count);
+        }
+        writer.finish()
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, String> {
+        let mut reader = SaveReader::with_header(bytes, REPLAY_MAGIC, REPLAY_VERSION, "replay")?;
+        let mut replay = Replay::new(reader.u64()?, reader.f64()?, Episode::load(&mut reader)?);
+        for _ in 0..reader.u32()? {
+            let input = TickInput { bits: reader.u16()? };
+            let count = reader.u32()?;
+            if replay.inputs.len() + count as usize > MAX_REPLAY_TICKS {
+                return Err("replay is too long".to_string());
+            }
+            replay.inputs.extend(std::iter::repeat_n(input, count as usize));
+        }
+        reader.finish()?;
+        Ok(replay)
+    }
+}
+
+/// Feeds a replay's input back to the simulation one tick at a time.
+pub struct Playback {
+    replay: Replay,
+    tick: usize,
+}
+
+impl Playback {
+    pub fn new(replay: Replay) -> Self {
+        Playback { replay, tick: 0 }
+    }
+
+    pub fn sensitivity(&self) -> f64 {
+        self.replay.sensitivity
+    }
+
+    /// The input for the next tick, or None once the replay has finished.
+    pub fn next_input(&mut self) -> Option<TickInput> {
+        let input = self.replay.inputs.get(self.tick).copied();
+        self.tick += 1;
+        input
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::simulation::Simulation;
+
+    /// A scripted run that walks, turns, looks about, switches weapons and fires.
+    fn scripted_input(tick: usize) -> TickInput {
+        let mut input = TickInput::default();
+        input.set_action(Action::Forward, tick % 200 < 120);
+        input.set_action(Action::TurnLeft, tick % 90 < 15);
+        input.set_action(Action::TurnRight, (tick / 300) % 2 == 1 && tick % 50 < 10);
+        input.set_action(Action::LookUp, tick % 400 < 20);
+        input.set_action(Action::Fire, tick % 70 < 5);
+        input.set_action(Action::Use, tick.is_multiple_of(130));
+        input.set_action(Action::Jump, tick.is_multiple_of(250));
+        input.set_weapon_slot(1, tick == 500);
+        input
+    }
+
+    fn state(game: &Simulation) -> Vec<u8> {
+        let mut writer = SaveWriter::new();
+        game.save(&mut writer);
+        writer.finish()
+    }
+
+    #[test]
+    fn playback_matches_recording() {
+        let seed = 0x5EED;
+        let mut recorded = Simulation::new(Episode::Campaign, seed);
+        let mut replay = Replay::new(seed, 1.25, Episode::Campaign);
+        for tick in 0..1200 {
+            let input = scripted_input(tick);
+            replay.record(input);
+            recorded.tick(input, replay.sensitivity);
+        }
+
+        let loaded = Replay::from_bytes(&replay.to_bytes()).unwrap();
+        assert!(loaded.inputs == replay.inputs);
+        let mut played = Simulation::new(loaded.episode, loaded.seed);
+        let mut playback = Playback::new(loaded);
+        let sensitivity = playback.sensitivity();
+        while let Some(input) = playback.next_input() {
+            played.tick(input, sensitivity);
+        }
+
+        let start = Simulation::new(Episode::Campaign, seed);
+        assert!(state(&recorded) != state(&start));
+        assert!(state(&played) == state(&recorded));
+    }
+
+    #[test]
+    fn overlong_replay_is_rejected() {
+        let mut writer = SaveWriter::with_header(REPLAY_MAGIC, REPLAY_VERSION);
+        writer.u64(1);
+        writer.f64(1.0);
+        Episode::Campaign.save(&mut writer);
+        writer.u32(2);
+        for _ in 0..2 {
+            writer.u16(0);
+            writer.u32(u32::MAX);
+        }
+        assert!(Replay::from_bytes(&writer.finish()).is_err());
+    }
+}
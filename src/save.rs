@@ -0,0 +1,1085 @@
+use crate::entities::{Entity, Explosion, Projectile, ProjectileKind, World};
+use crate::dungeon::DungeonMode;
+use crate::game::{Map, Player};
+use crate::inventory::{AmmoKind, Inventory, Item, KeyColor, StackLimits};
+use crate::intermission::Intermission;
+use crate::level::{Episode, LevelSource, LevelStats};
+use crate::maze::MazeAlgorithm;
+use crate::pickups::Pickup;
+use crate::lift::Lift;
+use crate::portal::{Portal, PortalFace};
+use crate::pushwall::{Pushwall, PUSH_DISTANCE};
+use crate::sector::{Sector, SectorMap, SectorWall};
+use crate::thin_wall::ThinWall;
+use crate::rng::Rng;
+use crate::simulation::Simulation;
+use crate::sprites::Sprite;
+use crate::weapon::{Weapon, WeaponKind, WeaponState};
+
+const SAVE_MAGIC: &[u8; 4] = b"RCSV";
+/// Bump whenever the layout written by any `Persist` impl changes.
+pub const SAVE_VERSION: u16 = 12;
+/// Most cells a saved map can have. Larger sizes are taken to be a corrupt file rather than allocated.
+const MAX_MAP_CELLS: usize = 256 * 256;
+/// How many levels of storeys a saved map can have. Only the ground map has storeys of its own.
+const MAX_STOREY_DEPTH: usize = 1;
+
+/// Builds a save file: a magic number and version followed by little-endian fields.
+pub struct SaveWriter {
+    bytes: Vec<u8>,
+}
+
+impl SaveWriter {
+    pub fn new() -> Self {
+        SaveWriter::with_header(SAVE_MAGIC, SAVE_VERSION)
+    }
+
+    /// Starts a file of another kind that shares the save encoding, such as a replay.
+    pub fn with_header(magic: &[u8; 4], version: u16) -> Self {
+        let mut writer = SaveWriter { bytes: magic.to_vec() };
+        writer.u16(version);
+        writer
+    }
+
+    pub fn u8(&mut self, value: u8) {
+        self.bytes.push(value);
+    }
+
+    pub fn u16(&mut self, value: u16) {
+        self.bytes.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn u32(&mut self, value: u32) {
+        self.bytes.extend_from_slice(&value.to_le_bytes());
+    }
+
+    /// Floats are stored bit for bit so a loaded game continues exactly as the saved one would have.
+    pub fn f64(&mut self, value: f64) {
+        self.bytes.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn u64(&mut self, value: u64) {
+        self.bytes.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn bool(&mut self, value: bool) {
+        self.u8(value as u8);
+    }
+
+    pub fn finish(self) -> Vec<u8> {
+        self.bytes
+    }
+}
+
+pub struct SaveReader<'a> {
+    bytes: &'a [u8],
+    position: usize,
+}
+
+impl<'a> SaveReader<'a> {
+    pub fn new(bytes: &'a [u8]) -> Result<Self, String> {
+        SaveReader::with_header(bytes, SAVE_MAGIC, SAVE_VERSION, "save")
+    }
+
+    /// Opens a file written by `SaveWriter::with_header`, checking it is the expected `kind` and version.
+    pub fn with_header(bytes: &'a [u8], magic: &[u8; 4], version: u16, kind: &str) -> Result<Self, String> {
+        let mut reader = SaveReader { bytes, position: 0 };
+        if reader.take(4)? != magic {
+            return Err(format!("not a {} file", kind));
+        }
+
+        let found = reader.u16()?;
+        if found != version {
+            return Err(format!("{} version {} is not supported (expected {})", kind, found, version));
+        }
+
+        Ok(reader)
+    }
+
+    fn take(&mut self, count: usize) -> Result<&'a [u8], String> {
+        let end = self.position + count;
+        let bytes = self.bytes.get(self.position..end).ok_or("save file is truncated")?;
+        self.position = end;
+        Ok(bytes)
+    }
+
+    pub fn u8(&mut self) -> Result<u8, String> {
+        Ok(self.take(1)?[0])
+    }
+
+    pub fn u16(&mut self) -> Result<u16, String> {
+        Ok(u16::from_le_bytes(self.take(2)?.try_into().unwrap()))
+    }
+
+    pub fn u32(&mut self) -> Result<u32, String> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    pub fn f64(&mut self) -> Result<f64, String> {
+        Ok(f64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+    }
+
+    pub fn u64(&mut self) -> Result<u64, String> {
+        Ok(u64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+    }
+
+    pub fn bool(&mut self) -> Result<bool, String> {
+        Ok(self.u8()? != 0)
+    }
+
+    /// Checks that the whole file was consumed.
+    pub fn finish(self) -> Result<(), String> {
+        if self.position != self.bytes.len() {
+            return Err("unexpected data at the end of the save file".to_string());
+        }
+        Ok(())
+    }
+}
+
+fn invalid(what: &str, tag: u8) -> String {
+    format!("invalid {} tag {} in save file", what, tag)
+}
+
+/// A piece of game state that can be written to and restored from a save file.
+pub trait Persist: Sized {
+    fn save(&self, writer: &mut SaveWriter);
+    fn load(reader: &mut SaveReader) -> Result<Self, String>;
+}
+
+impl<T: Persist> Persist for Vec<T> {
+    fn save(&self, writer: &mut SaveWriter) {
+        writer.u32(self.len() as u32);
+        for item in self {
+            item.save(writer);
+        }
+    }
+
+    fn load(reader: &mut SaveReader) -> Result<Self, String> {
+        let count = reader.u32()?;
+        (0..count).map(|_| T::load(reader)).collect()
+    }
+}
+
+impl<T: Persist> Persist for Option<T> {
+    fn save(&self, writer: &mut SaveWriter) {
+        writer.bool(self.is_some());
+        if let Some(value) = self {
+            value.save(writer);
+        }
+    }
+
+    fn load(reader: &mut SaveReader) -> Result<Self, String> {
+        if reader.bool()? {
+            Ok(Some(T::load(reader)?))
+        } else {
+            Ok(None)
+        }
+    }
+}
+
+impl Persist for AmmoKind {
+    fn save(&self, writer: &mut SaveWriter) {
+        writer.u8(match self {
+            AmmoKind::Rockets => 0,
+            AmmoKind::Mana => 1,
+        });
+    }
+
+    fn load(reader: &mut SaveReader) -> Result<Self, String> {
+        match reader.u8()? {
+            0 => Ok(AmmoKind::Rockets),
+            1 => Ok(AmmoKind::Mana),
+            tag => Err(invalid("ammo", tag)),
+        }
+    }
+}
+
+impl Persist for KeyColor {
+    fn save(&self, writer: &mut SaveWriter) {
+        writer.u8(match self {
+            KeyColor::Red => 0,
+            KeyColor::Blue => 1,
+        });
+    }
+
+    fn load(reader: &mut SaveReader) -> Result<Self, String> {
+        match reader.u8()? {
+            0 => Ok(KeyColor::Red),
+            1 => Ok(KeyColor::Blue),
+            tag => Err(invalid("key", tag)),
+        }
+    }
+}
+
+impl Persist for WeaponKind {
+    fn save(&self, writer: &mut SaveWriter) {
+        writer.u8(match self {
+            WeaponKind::Sword => 0,
+            WeaponKind::RocketLauncher => 1,
+            WeaponKind::FireStaff => 2,
+        });
+    }
+
+    fn load(reader: &mut SaveReader) -> Result<Self, String> {
+        match reader.u8()? {
+            0 => Ok(WeaponKind::Sword),
+            1 => Ok(WeaponKind::RocketLauncher),
+            2 => Ok(WeaponKind::FireStaff),
+            tag => Err(invalid("weapon", tag)),
+        }
+    }
+}
+
+impl Persist for Item {
+    fn save(&self, writer: &mut SaveWriter) {
+        match self {
+            Item::Health(amount) => {
+                writer.u8(0);
+                writer.f64(*amount);
+            }
+            Item::Armor(amount) => {
+                writer.u8(1);
+                writer.f64(*amount);
+            }
+            Item::Ammo(kind, amount) => {
+                writer.u8(2);
+                kind.save(writer);
+                writer.u32(*amount);
+            }
+            Item::Key(color) => {
+                writer.u8(3);
+                color.save(writer);
+            }
+            Item::Weapon(kind) => {
+                writer.u8(4);
+                kind.save(writer);
+            }
+            Item::Treasure(points) => {
+                writer.u8(5);
+                writer.u32(*points);
+            }
+        }
+    }
+
+    fn load(reader: &mut SaveReader) -> Result<Self, String> {
+        match reader.u8()? {
+            0 => Ok(Item::Health(reader.f64()?)),
+            1 => Ok(Item::Armor(reader.f64()?)),
+            2 => Ok(Item::Ammo(AmmoKind::load(reader)?, reader.u32()?)),
+            3 => Ok(Item::Key(KeyColor::load(reader)?)),
+            4 => Ok(Item::Weapon(WeaponKind::load(reader)?)),
+            5 => Ok(Item::Treasure(reader.u32()?)),
+            tag => Err(invalid("item", tag)),
+        }
+    }
+}
+
+impl Persist for Inventory {
+    fn save(&self, writer: &mut SaveWriter) {
+        writer.u32(self.limits.rockets);
+        writer.u32(self.limits.mana);
+        writer.u32(self.rockets);
+        writer.u32(self.mana);
+        self.keys.save(writer);
+        self.weapons.save(writer);
+        writer.u32(self.score);
+    }
+
+    fn load(reader: &mut SaveReader) -> Result<Self, String> {
+        let limits = StackLimits {
+            rockets: reader.u32()?,
+            mana: reader.u32()?,
+        };
+        let mut inventory = Inventory::new(limits);
+        inventory.rockets = reader.u32()?;
+        inventory.mana = reader.u32()?;
+        inventory.keys = Vec::load(reader)?;
+        inventory.weapons = Vec::load(reader)?;
+        inventory.score = reader.u32()?;
+        Ok(inventory)
+    }
+}
+
+impl Persist for Player {
+    fn save(&self, writer: &mut SaveWriter) {
+        writer.f64(self.x);
+        writer.f64(self.y);
+        writer.f64(self.direction);
+        writer.f64(self.pitch);
+        writer.f64(self.z);
+        writer.u32(self.storey as u32);
+        writer.f64(self.lift);
+        writer.f64(self.vertical_speed);
+        writer.f64(self.crouch);
+        writer.f64(self.bob_phase);
+        writer.f64(self.bob_amount);
+        writer.f64(self.fov);
+        writer.f64(self.health);
+        writer.f64(self.armor);
+        writer.u32(self.invulnerable_ticks);
+        writer.u32(self.death_ticks);
+        self.inventory.save(writer);
+    }
+
+    fn load(reader: &mut SaveReader) -> Result<Self, String> {
+        let mut player = Player::new();
+        player.x = reader.f64()?;
+        player.y = reader.f64()?;
+        player.direction = reader.f64()?;
+        player.pitch = reader.f64()?;
+        player.z = reader.f64()?;
+        player.storey = reader.u32()? as usize;
+        player.lift = reader.f64()?;
+        player.vertical_speed = reader.f64()?;
+        player.crouch = reader.f64()?;
+        player.bob_phase = reader.f64()?;
+        player.bob_amount = reader.f64()?;
+        player.fov = reader.f64()?;
+        player.health = reader.f64()?;
+        player.armor = reader.f64()?;
+        player.invulnerable_ticks = reader.u32()?;
+        player.death_ticks = reader.u32()?;
+        player.inventory = Inventory::load(reader)?;
+        Ok(player)
+    }
+}
+
+impl Persist for Map {
+    fn save(&self, writer: &mut SaveWriter) {
+        writer.u32(self.width as u32);
+        writer.u32(self.height as u32);
+        for cell in &self.cells {
+            writer.u8(*cell);
+        }
+        for height in &self.heights {
+            writer.f64(*height);
+        }
+        self.thin_walls.save(writer);
+        writer.u32(self.secrets.len() as u32);
+        for secret in &self.secrets {
+            writer.u32(*secret as u32);
+        }
+        self.pushwall.save(writer);
+        self.portals.save(writer);
+        for hole in &self.holes {
+            writer.bool(*hole);
+        }
+        self.storeys.save(writer);
+        self.lifts.save(writer);
+        self.sectors.save(writer);
+    }
+
+    fn load(reader: &mut SaveReader) -> Result<Self, String> {
+        load_map(reader, 0)
+    }
+}
+
+/// Loads a map that is `depth` storeys above the ground.
+fn load_map(reader: &mut SaveReader, depth: usize) -> Result<Map, String> {
+    let width = reader.u32()? as usize;
+    let height = reader.u32()? as usize;
+    let size = width.checked_mul(height).filter(|size| *size <= MAX_MAP_CELLS);
+    if size.is_none() {
+        return Err("map is too large in save file".to_string());
+    }
+    let cells = (0..width * height).map(|_| reader.u8()).collect::<Result<_, _>>()?;
+    let heights = (0..width * height).map(|_| reader.f64()).collect::<Result<_, _>>()?;
+    let thin_walls = Vec::<Option<ThinWall>>::load(reader)?;
+    if thin_walls.len() != width * height {
+        return Err("thin walls don't match the map size in save file".to_string());
+    }
+    let secrets = (0..reader.u32()?).map(|_| Ok(reader.u32()? as usize)).collect::<Result<_, String>>()?;
+    let inside = |x: i32, y: i32| x >= 0 && y >= 0 && (x as usize) < width && (y as usize) < height;
+    let unit = |(dx, dy): (i32, i32)| dx.abs() + dy.abs() == 1;
+    let pushwall = Option::<Pushwall>::load(reader)?;
+    if let Some(pushwall) = pushwall {
+        let (to_x, to_y) = pushwall.destination();
+        if !inside(pushwall.x, pushwall.y) || !inside(to_x, to_y) || !unit((pushwall.dx, pushwall.dy)) {
+            return Err("pushwall is off the map in save file".to_string());
+        }
+        if pushwall.distance == 0 || pushwall.distance > PUSH_DISTANCE || pushwall.texture_id == 0 {
+            return Err("invalid pushwall in save file".to_string());
+        }
+    }
+    let portals = Vec::<Portal>::load(reader)?;
+    let faces = portals.iter().flat_map(|portal| [portal.from, portal.to]);
+    if faces.into_iter().any(|face| !inside(face.x, face.y) || !unit(face.normal)) {
+        return Err("invalid portal in save file".to_string());
+    }
+    let holes = (0..width * height).map(|_| reader.bool()).collect::<Result<_, _>>()?;
+    let storeys = load_storeys(reader, depth)?;
+    let lifts = Vec::<Lift>::load(reader)?;
+    if lifts.iter().any(|lift| !inside(lift.x, lift.y)) {
+        return Err("lift is off the map in save file".to_string());
+    }
+    if lifts.iter().any(|lift| lift.storey >= storeys.len()) {
+        return Err("lift leads to a missing storey in save file".to_string());
+    }
+    let sectors = Option::<SectorMap>::load(reader)?;
+    Ok(Map { width, height, cells, heights, thin_walls, secrets, pushwall, portals, holes, storeys, lifts, sectors })
+}
+
+/// Loads the storeys stacked on a map `depth` storeys above the ground. They all sit on the
+/// ground map, so deeper nesting means a corrupt file, and is refused before it can recurse.
+fn load_storeys(reader: &mut SaveReader, depth: usize) -> Result<Vec<Map>, String> {
+    let count = reader.u32()?;
+    if count > 0 && depth >= MAX_STOREY_DEPTH {
+        return Err("storeys are nested too deeply in save file".to_string());
+    }
+    (0..count).map(|_| load_map(reader, depth + 1)).collect()
+}
+
+impl Persist for ThinWall {
+    fn save(&self, writer: &mut SaveWriter) {
+        writer.f64(self.start.0);
+        writer.f64(self.start.1);
+        writer.f64(self.end.0);
+        writer.f64(self.end.1);
+    }
+
+    fn load(reader: &mut SaveReader) -> Result<Self, String> {
+        Ok(ThinWall {
+            start: (reader.f64()?, reader.f64()?),
+            end: (reader.f64()?, reader.f64()?),
+        })
+    }
+}
+
+impl Persist for Pushwall {
+    fn save(&self, writer: &mut SaveWriter) {
+        writer.u32(self.x as u32);
+        writer.u32(self.y as u32);
+        writer.u8(self.dx as u8);
+        writer.u8(self.dy as u8);
+        writer.u8(self.texture_id);
+        writer.f64(self.height);
+        writer.u32(self.distance);
+        writer.u32(self.ticks);
+    }
+
+    fn load(reader: &mut SaveReader) -> Result<Self, String> {
+        Ok(Pushwall {
+            x: reader.u32()? as i32,
+            y: reader.u32()? as i32,
+            dx: reader.u8()? as i8 as i32,
+            dy: reader.u8()? as i8 as i32,
+            texture_id: reader.u8()?,
+            height: reader.f64()?,
+            distance: reader.u32()?,
+            ticks: reader.u32()?,
+        })
+    }
+}
+
+impl Persist for PortalFace {
+    fn save(&self, writer: &mut SaveWriter) {
+        writer.u32(self.x as u32);
+        writer.u32(self.y as u32);
+        writer.u8(self.normal.0 as u8);
+        writer.u8(self.normal.1 as u8);
+    }
+
+    fn load(reader: &mut SaveReader) -> Result<Self, String> {
+        Ok(PortalFace {
+            x: reader.u32()? as i32,
+            y: reader.u32()? as i32,
+            normal: (reader.u8()? as i8 as i32, reader.u8()? as i8 as i32),
+        })
+    }
+}
+
+impl Persist for Portal {
+    fn save(&self, writer: &mut SaveWriter) {
+        self.from.save(writer);
+        self.to.save(writer);
+    }
+
+    fn load(reader: &mut SaveReader) -> Result<Self, String> {
+        Ok(Portal { from: PortalFace::load(reader)?, to: PortalFace::load(reader)? })
+    }
+}
+
+impl Persist for Lift {
+    fn save(&self, writer: &mut SaveWriter) {
+        writer.u32(self.x as u32);
+        writer.u32(self.y as u32);
+        writer.u32(self.storey as u32);
+    }
+
+    fn load(reader: &mut SaveReader) -> Result<Self, String> {
+        Ok(Lift {
+            x: reader.u32()? as i32,
+            y: reader.u32()? as i32,
+            storey: reader.u32()? as usize,
+        })
+    }
+}
+
+impl Persist for SectorWall {
+    fn save(&self, writer: &mut SaveWriter) {
+        writer.f64(self.start.0);
+        writer.f64(self.start.1);
+        writer.f64(self.end.0);
+        writer.f64(self.end.1);
+        writer.u8(self.texture_id);
+        writer.bool(self.neighbour.is_some());
+        if let Some(neighbour) = self.neighbour {
+            writer.u32(neighbour as u32);
+        }
+    }
+
+    fn load(reader: &mut SaveReader) -> Result<Self, String> {
+        Ok(SectorWall {
+            start: (reader.f64()?, reader.f64()?),
+            end: (reader.f64()?, reader.f64()?),
+            texture_id: reader.u8()?,
+            neighbour: if reader.bool()? { Some(reader.u32()? as usize) } else { None },
+        })
+    }
+}
+
+impl Persist for Sector {
+    fn save(&self, writer: &mut SaveWriter) {
+        writer.f64(self.floor);
+        writer.f64(self.ceiling);
+        self.walls.save(writer);
+    }
+
+    fn load(reader: &mut SaveReader) -> Result<Self, String> {
+        Ok(Sector { floor: reader.f64()?, ceiling: reader.f64()?, walls: Vec::load(reader)? })
+    }
+}
+
+impl Persist for SectorMap {
+    fn save(&self, writer: &mut SaveWriter) {
+        self.sectors.save(writer);
+    }
+
+    fn load(reader: &mut SaveReader) -> Result<Self, String> {
+        let sectors = Vec::<Sector>::load(reader)?;
+        let count = sectors.len();
+        if sectors.iter().flat_map(|sector| &sector.walls).any(|wall| wall.neighbour.is_some_and(|neighbour| neighbour >= count)) {
+            return Err("sector wall leads to a missing sector in save file".to_string());
+        }
+        Ok(SectorMap { sectors })
+    }
+}
+
+impl Persist for ProjectileKind {
+    fn save(&self, writer: &mut SaveWriter) {
+        writer.u8(match self {
+            ProjectileKind::Rocket => 0,
+            ProjectileKind::Fireball => 1,
+        });
+    }
+
+    fn load(reader: &mut SaveReader) -> Result<Self, String> {
+        match reader.u8()? {
+            0 => Ok(ProjectileKind::Rocket),
+            1 => Ok(ProjectileKind::Fireball),
+            tag => Err(invalid("projectile", tag)),
+        }
+    }
+}
+
+impl Persist for Projectile {
+    fn save(&self, writer: &mut SaveWriter) {
+        writer.f64(self.x);
+        writer.f64(self.y);
+        writer.u32(self.storey as u32);
+        writer.f64(self.direction);
+        self.kind.save(writer);
+    }
+
+    fn load(reader: &mut SaveReader) -> Result<Self, String> {
+        Ok(Projectile {
+            x: reader.f64()?,
+            y: reader.f64()?,
+            storey: reader.u32()? as usize,
+            direction: reader.f64()?,
+            kind: ProjectileKind::load(reader)?,
+        })
+    }
+}
+
+impl Persist for Entity {
+    fn save(&self, writer: &mut SaveWriter) {
+        writer.f64(self.x);
+        writer.f64(self.y);
+        writer.u32(self.storey as u32);
+        writer.f64(self.radius);
+        writer.f64(self.health);
+        writer.u32(self.texture_id as u32);
+        writer.bool(self.explosive);
+        writer.bool(self.counts_as_kill);
+    }
+
+    fn load(reader: &mut SaveReader) -> Result<Self, String> {
+        Ok(Entity {
+            x: reader.f64()?,
+            y: reader.f64()?,
+            storey: reader.u32()? as usize,
+            radius: reader.f64()?,
+            health: reader.f64()?,
+            texture_id: reader.u32()? as usize,
+            explosive: reader.bool()?,
+            counts_as_kill: reader.bool()?,
+        })
+    }
+}
+
+impl Persist for Explosion {
+    fn save(&self, writer: &mut SaveWriter) {
+        writer.f64(self.x);
+        writer.f64(self.y);
+        writer.u32(self.storey as u32);
+        writer.u32(self.ticks_left);
+    }
+
+    fn load(reader: &mut SaveReader) -> Result<Self, String> {
+        Ok(Explosion {
+            x: reader.f64()?,
+            y: reader.f64()?,
+            storey: reader.u32()? as usize,
+            ticks_left: reader.u32()?,
+        })
+    }
+}
+
+impl Persist for Pickup {
+    fn save(&self, writer: &mut SaveWriter) {
+        writer.f64(self.x);
+        writer.f64(self.y);
+        writer.u32(self.storey as u32);
+        self.item.save(writer);
+    }
+
+    fn load(reader: &mut SaveReader) -> Result<Self, String> {
+        Ok(Pickup {
+            x: reader.f64()?,
+            y: reader.f64()?,
+            storey: reader.u32()? as usize,
+            item: Item::load(reader)?,
+        })
+    }
+}
+
+impl Persist for Simulation {
+    fn save(&self, writer: &mut SaveWriter) {
+        self.episode.save(writer);
+        writer.u64(self.game_seed);
+        writer.u32(self.level_number as u32);
+        self.intermission.save(writer);
+        self.player.save(writer);
+        self.map.save(writer);
+        self.world.save(writer);
+        self.weapon.save(writer);
+        self.sprites.save(writer);
+    }
+
+    fn load(reader: &mut SaveReader) -> Result<Self, String> {
+        let episode = Episode::load(reader)?;
+        let game_seed = reader.u64()?;
+        let level_number = reader.u32()? as usize;
+        let intermission = Option::load(reader)?;
+        let player = Player::load(reader)?;
+        let map = Map::load(reader)?;
+        let world = World::load(reader)?;
+        let weapon = Weapon::load(reader)?;
+        let sprites = Vec::load(reader)?;
+        let missing = |storey: usize| storey > map.storeys.len();
+        if missing(player.storey) {
+            return Err("player is on a missing storey in save file".to_string());
+        }
+        let storeys = world.entities.iter().map(|entity| entity.storey)
+            .chain(world.projectiles.iter().map(|projectile| projectile.storey))
+            .chain(world.explosions.iter().map(|explosion| explosion.storey))
+            .chain(world.pickups.iter().map(|pickup| pickup.storey))
+            .chain(sprites.iter().map(|sprite: &Sprite| sprite.storey));
+        if storeys.into_iter().any(missing) {
+            return Err("something is on a missing storey in save file".to_string());
+        }
+        Ok(Simulation {
+            fov: player.fov,
+            player,
+            map,
+            world,
+            weapon,
+            sprites,
+            episode,
+            game_seed,
+            level_number,
+            intermission,
+            notices: Vec::new(),
+        })
+    }
+}
+
+impl Persist for World {
+    fn save(&self, writer: &mut SaveWriter) {
+        self.entities.save(writer);
+        self.projectiles.save(writer);
+        self.explosions.save(writer);
+        self.pickups.save(writer);
+        self.rng.save(writer);
+        self.stats.save(writer);
+    }
+
+    fn load(reader: &mut SaveReader) -> Result<Self, String> {
+        Ok(World {
+            entities: Vec::load(reader)?,
+            projectiles: Vec::load(reader)?,
+            explosions: Vec::load(reader)?,
+            pickups: Vec::load(reader)?,
+            rng: Rng::load(reader)?,
+            stats: LevelStats::load(reader)?,
+        })
+    }
+}
+
+impl Persist for LevelSource {
+    fn save(&self, writer: &mut SaveWriter) {
+        writer.u8(match self {
+            LevelSource::Classic => 0,
+            LevelSource::Dungeon(DungeonMode::Rooms) => 1,
+            LevelSource::Dungeon(DungeonMode::Caves) => 2,
+            LevelSource::Maze { algorithm, braided } => {
+                let algorithm = match algorithm {
+                    MazeAlgorithm::Backtracker => 0,
+                    MazeAlgorithm::Prim => 1,
+                    MazeAlgorithm::Wilson => 2,
+                };
+                3 + algorithm * 2 + *braided as u8
+            }
+            LevelSource::Courtyard => 9,
+        });
+    }
+
+    fn load(reader: &mut SaveReader) -> Result<Self, String> {
+        match reader.u8()? {
+            0 => Ok(LevelSource::Classic),
+            1 => Ok(LevelSource::Dungeon(DungeonMode::Rooms)),
+            2 => Ok(LevelSource::Dungeon(DungeonMode::Caves)),
+            tag @ 3..=8 => {
+                let algorithm = [MazeAlgorithm::Backtracker, MazeAlgorithm::Prim, MazeAlgorithm::Wilson][(tag as usize - 3) / 2];
+                Ok(LevelSource::Maze { algorithm, braided: (tag - 3) % 2 == 1 })
+            }
+            9 => Ok(LevelSource::Courtyard),
+            tag => Err(invalid("level", tag)),
+        }
+    }
+}
+
+impl Persist for Episode {
+    fn save(&self, writer: &mut SaveWriter) {
+        match self {
+            Episode::Campaign => writer.bool(false),
+            Episode::Endless(source) => {
+                writer.bool(true);
+                source.save(writer);
+            }
+        }
+    }
+
+    fn load(reader: &mut SaveReader) -> Result<Self, String> {
+        if reader.bool()? {
+            Ok(Episode::Endless(LevelSource::load(reader)?))
+        } else {
+            Ok(Episode::Campaign)
+        }
+    }
+}
+
+impl Persist for LevelStats {
+    fn save(&self, writer: &mut SaveWriter) {
+        for value in [self.ticks, self.kills, self.total_kills, self.items, self.total_items, self.secrets, self.total_secrets] {
+            writer.u32(value);
+        }
+    }
+
+    fn load(reader: &mut SaveReader) -> Result<Self, String> {
+        Ok(LevelStats {
+            ticks: reader.u32()?,
+            kills: reader.u32()?,
+            total_kills: reader.u32()?,
+            items: reader.u32()?,
+            total_items: reader.u32()?,
+            secrets: reader.u32()?,
+            total_secrets: reader.u32()?,
+        })
+    }
+}
+
+impl Persist for Intermission {
+    fn save(&self, writer: &mut SaveWriter) {
+        writer.u32(self.level_number as u32);
+        self.stats.save(writer);
+        writer.u32(self.ticks);
+        writer.bool(self.released);
+        writer.bool(self.last);
+    }
+
+    fn load(reader: &mut SaveReader) -> Result<Self, String> {
+        Ok(Intermission {
+            level_number: reader.u32()? as usize,
+            stats: LevelStats::load(reader)?,
+            ticks: reader.u32()?,
+            released: reader.bool()?,
+            last: reader.bool()?,
+        })
+    }
+}
+
+impl Persist for Rng {
+    fn save(&self, writer: &mut SaveWriter) {
+        writer.u64(self.state);
+        writer.u64(self.increment);
+    }
+
+    fn load(reader: &mut SaveReader) -> Result<Self, String> {
+        Ok(Rng {
+            state: reader.u64()?,
+            increment: reader.u64()?,
+        })
+    }
+}
+
+impl Persist for WeaponState {
+    fn save(&self, writer: &mut SaveWriter) {
+        let (tag, ticks) = match *self {
+            WeaponState::Raising(ticks) => (0, ticks),
+            WeaponState::Ready => (1, 0),
+            WeaponState::Firing(ticks) => (2, ticks),
+            WeaponState::Lowering(ticks) => (3, ticks),
+        };
+        writer.u8(tag);
+        writer.u32(ticks);
+    }
+
+    fn load(reader: &mut SaveReader) -> Result<Self, String> {
+        let tag = reader.u8()?;
+        let ticks = reader.u32()?;
+        match tag {
+            0 => Ok(WeaponState::Raising(ticks)),
+            1 => Ok(WeaponState::Ready),
+            2 => Ok(WeaponState::Firing(ticks)),
+            3 => Ok(WeaponState::Lowering(ticks)),
+            tag => Err(invalid("weapon state", tag)),
+        }
+    }
+}
+
+impl Persist for Weapon {
+    fn save(&self, writer: &mut SaveWriter) {
+        self.kind.save(writer);
+        self.state.save(writer);
+        self.pending.save(writer);
+        writer.f64(self.bob_phase);
+        writer.f64(self.bob_amount);
+    }
+
+    fn load(reader: &mut SaveReader) -> Result<Self, String> {
+        Ok(Weapon {
+            kind: WeaponKind::load(reader)?,
+            state: WeaponState::load(reader)?,
+            pending: Option::load(reader)?,
+            bob_phase: reader.f64()?,
+            bob_amount: reader.f64()?,
+        })
+    }
+}
+
+impl Persist for Sprite {
+    fn save(&self, writer: &mut SaveWriter) {
+        writer.f64(self.x);
+        writer.f64(self.y);
+        writer.u32(self.storey as u32);
+        writer.u32(self.texture_id as u32);
+    }
+
+    fn load(reader: &mut SaveReader) -> Result<Self, String> {
+        Ok(Sprite {
+            x: reader.f64()?,
+            y: reader.f64()?,
+            storey: reader.u32()? as usize,
+            texture_id: reader.u32()? as usize,
+            distance: 0.0,
+        })
+    }
+}
+
+/// Stores a save in the named slot: in localStorage when running in the browser,
+/// or as a file under `saves/` when running natively.
+pub fn write_slot(slot: &str, bytes: &[u8]) -> Result<(), String> {
+    if cfg!(target_arch = "wasm32") {
+        local_storage()?
+            .set_item(&storage_key(slot), &encode_base64(bytes))
+            .map_err(|_| "could not write to localStorage (is it full?)".to_string())
+    } else {
+        std::fs::create_dir_all("saves").map_err(|error| error.to_string())?;
+        std::fs::write(slot_path(slot), bytes).map_err(|error| error.to_string())
+    }
+}
+
+/// Reads back a save written by `write_slot`.
+pub fn read_slot(slot: &str) -> Result<Vec<u8>, String> {
+    if cfg!(target_arch = "wasm32") {
+        let text = local_storage()?
+            .get_item(&storage_key(slot))
+            .map_err(|_| "could not read from localStorage".to_string())?
+            .ok_or_else(|| format!("no game saved in slot `{}`", slot))?;
+        decode_base64(&text)
+    } else {
+        std::fs::read(slot_path(slot)).map_err(|error| error.to_string())
+    }
+}
+
+fn storage_key(slot: &str) -> String {
+    format!("raycaster-save-{}", slot)
+}
+
+fn slot_path(slot: &str) -> String {
+    format!("saves/{}.sav", slot)
+}
+
+fn local_storage() -> Result<web_sys::Storage, String> {
+    web_sys::window()
+        .and_then(|window| window.local_storage().ok().flatten())
+        .ok_or_else(|| "localStorage is not available".to_string())
+}
+
+const BASE64_ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+// localStorage only holds strings, so binary saves are stored as base64
+fn encode_base64(bytes: &[u8]) -> String {
+    let mut text = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for chunk in bytes.chunks(3) {
+        let value = chunk.iter().enumerate().fold(0u32, |value, (i, byte)| value | (*byte as u32) << (16 - i * 8));
+        for i in 0..4 {
+            if i <= chunk.len() {
+                text.push(BASE64_ALPHABET[(value >> (18 - i * 6)) as usize & 63] as char);
+            } else {
+                text.push('=');
+            }
+        }
+    }
+    text
+}
+
+fn decode_base64(text: &str) -> Result<Vec<u8>, String> {
+    let mut bytes = Vec::with_capacity(text.len() / 4 * 3);
+    let mut value = 0u32;
+    let mut bits = 0;
+    for character in text.bytes().take_while(|character| *character != b'=') {
+        let digit = BASE64_ALPHABET
+            .iter()
+            .position(|candidate| *candidate == character)
+            .ok_or("save data is corrupt")?;
+        value = (value << 6) | digit as u32;
+        bits += 6;
+        if bits >= 8 {
+            bits -= 8;
+            bytes.push((value >> bits) as u8);
+        }
+    }
+    Ok(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::replay::TickInput;
+    use crate::settings::Action;
+
+    fn state(game: &Simulation) -> Vec<u8> {
+        let mut writer = SaveWriter::new();
+        game.save(&mut writer);
+        writer.finish()
+    }
+
+    fn walk_and_fire(tick: usize) -> TickInput {
+        let mut input = TickInput::default();
+        input.set_action(Action::Forward, tick % 100 < 60);
+        input.set_action(Action::TurnRight, tick % 80 < 12);
+        input.set_action(Action::Fire, tick % 45 < 3);
+        input
+    }
+
+    #[test]
+    fn loaded_game_continues_identically() {
+        for episode in [Episode::Campaign, Episode::Endless(LevelSource::Dungeon(DungeonMode::Caves))] {
+            let mut original = Simulation::new(episode, 77);
+            for tick in 0..300 {
+                original.tick(walk_and_fire(tick), 1.0);
+            }
+
+            let bytes = state(&original);
+            let mut reader = SaveReader::new(&bytes).unwrap();
+            let mut loaded = Simulation::load(&mut reader).unwrap();
+            reader.finish().unwrap();
+            assert!(state(&loaded) == bytes);
+
+            for tick in 300..900 {
+                original.tick(walk_and_fire(tick), 1.0);
+                loaded.tick(walk_and_fire(tick), 1.0);
+            }
+            assert!(state(&loaded) == state(&original));
+        }
+    }
+
+    #[test]
+    fn oversized_map_is_rejected() {
+        let mut writer = SaveWriter::new();
+        writer.u32(u32::MAX);
+        writer.u32(u32::MAX);
+        let bytes = writer.finish();
+        let mut reader = SaveReader::new(&bytes).unwrap();
+        assert!(Map::load(&mut reader).is_err());
+    }
+
+    #[test]
+    fn broken_pushwalls_portals_and_lifts_are_rejected() {
+        let loads = |map: &Map| {
+            let mut writer = SaveWriter::new();
+            map.save(&mut writer);
+            let bytes = writer.finish();
+            Map::load(&mut SaveReader::new(&bytes).unwrap()).is_ok()
+        };
+        let pushwall = Pushwall { x: 3, y: 1, dx: -1, dy: 0, texture_id: 3, height: 1.0, distance: 2, ticks: 0 };
+        let with_pushwall = |pushwall: Pushwall| Map { pushwall: Some(pushwall), ..Map::new() };
+        assert!(loads(&with_pushwall(pushwall)));
+        assert!(!loads(&with_pushwall(Pushwall { x: -1, ..pushwall })));
+        assert!(!loads(&with_pushwall(Pushwall { x: 1, ..pushwall })));
+        assert!(!loads(&with_pushwall(Pushwall { dx: -1, dy: 1, ..pushwall })));
+        assert!(!loads(&with_pushwall(Pushwall { distance: 0, ..pushwall })));
+        assert!(!loads(&with_pushwall(Pushwall { distance: PUSH_DISTANCE + 1, x: 6, ..pushwall })));
+        assert!(!loads(&with_pushwall(Pushwall { texture_id: 0, ..pushwall })));
+
+        let mut map = Map::new();
+        map.portals[0].to.x = 8;
+        assert!(!loads(&map));
+        let mut map = Map::new();
+        map.portals[0].from.normal = (0, 0);
+        assert!(!loads(&map));
+
+        let mut map = Map::new();
+        map.lifts[0].y = 8;
+        assert!(!loads(&map));
+    }
+
+    #[test]
+    fn nested_storeys_are_rejected() {
+        let mut map = Map::new();
+        let mut writer = SaveWriter::new();
+        map.save(&mut writer);
+        let bytes = writer.finish();
+        assert!(Map::load(&mut SaveReader::new(&bytes).unwrap()).is_ok());
+
+        let gallery = map.storeys[0].clone();
+        map.storeys[0].storeys.push(gallery);
+        let mut writer = SaveWriter::new();
+        map.save(&mut writer);
+        let bytes = writer.finish();
+        assert!(Map::load(&mut SaveReader::new(&bytes).unwrap()).is_err());
+    }
+}
@@ -0,0 +1,371 @@
+use crate::dungeon::EXIT_TEXTURE;
+use crate::game::{Map, Player};
+use crate::raycasting::{draw_sprite, Camera};
+use crate::renderer::Renderer;
+use crate::sprites::{sort_back_to_front, Sprite};
+
+/// Highest step up from one sector's floor to the next that the player can walk up.
+pub const MAX_STEP: f64 = 0.25;
+/// Least room between floor and ceiling the player can fit through.
+pub const MIN_HEADROOM: f64 = 0.6;
+/// Most sectors a ray passes through before whatever is beyond is left undrawn.
+const MAX_SECTOR_DEPTH: usize = 32;
+
+/// One side of a sector: a solid wall, or an opening into a neighbouring sector.
+#[derive(Clone, Copy)]
+pub struct SectorWall {
+    pub start: (f64, f64),
+    pub end: (f64, f64),
+    /// Texture of the wall, or of the steps above and below an opening where the floors and
+    /// ceilings either side don't line up.
+    pub texture_id: u8,
+    /// The sector on the other side, if this is an opening rather than a wall.
+    pub neighbour: Option<usize>,
+}
+
+/// A convex room with a flat floor and ceiling at its own heights, in wall heights above the
+/// ground. Its walls can run at any angle.
+#[derive(Clone)]
+pub struct Sector {
+    pub floor: f64,
+    pub ceiling: f64,
+    /// The sides in order around the sector, each one's end being the next one's start.
+    pub walls: Vec<SectorWall>,
+}
+
+impl Sector {
+    /// A sector with corners at `corners` in order, each given with the texture and neighbour of
+    /// the side from it to the next corner.
+    pub fn new(floor: f64, ceiling: f64, corners: &[((f64, f64), u8, Option<usize>)]) -> Self {
+        let walls = corners
+            .iter()
+            .zip(corners.iter().cycle().skip(1))
+            .map(|(&(start, texture_id, neighbour), &(end, _, _))| SectorWall { start, end, texture_id, neighbour })
+            .collect();
+        Sector { floor, ceiling, walls }
+    }
+
+    /// True if the point is inside the sector or on its edge.
+    pub fn contains(&self, x: f64, y: f64) -> bool {
+        let sides = self.walls.iter().map(|wall| {
+            (wall.end.0 - wall.start.0) * (y - wall.start.1) - (wall.end.1 - wall.start.1) * (x - wall.start.0)
+        });
+        let (mut left, mut right) = (false, false);
+        for side in sides {
+            left |= side > 0.0;
+            right |= side < 0.0;
+        }
+        !(left && right)
+    }
+}
+
+/// Where a ray leaves a sector.
+struct SectorHit {
+    wall: SectorWall,
+    distance: f64,
+    /// How far along the wall from `start` the ray crossed it, in map units.
+    along: f64,
+}
+
+/// A level made of sectors rather than grid cells, for layouts the grid can't describe.
+#[derive(Clone)]
+pub struct SectorMap {
+    pub sectors: Vec<Sector>,
+}
+
+impl SectorMap {
+    /// The sector the point is in, if any.
+    pub fn sector_at(&self, x: f64, y: f64) -> Option<usize> {
+        self.sectors.iter().position(|sector| sector.contains(x, y))
+    }
+
+    /// Height of the floor at a point, or 0 outside every sector.
+    pub fn floor_at(&self, x: f64, y: f64) -> f64 {
+        self.sector_at(x, y).map_or(0.0, |index| self.sectors[index].floor)
+    }
+
+    /// True if something standing at (from_x, from_y) can move to (to_x, to_y): the destination
+    /// is inside a sector whose floor is no more than a step up and with room to stand.
+    pub fn can_step(&self, from_x: f64, from_y: f64, to_x: f64, to_y: f64) -> bool {
+        let Some(to) = self.sector_at(to_x, to_y) else {
+            return false;
+        };
+        let to = &self.sectors[to];
+        to.floor - self.floor_at(from_x, from_y) <= MAX_STEP && to.ceiling - to.floor >= MIN_HEADROOM
+    }
+
+    /// A grid covering the sectors roughly, for the minimap and anything else that works in cells.
+    /// Cells whose middle lies outside every sector are walls, and cells named in `exits` hold the
+    /// exit switch.
+    pub fn to_grid(&self, width: usize, height: usize, exits: &[(usize, usize)]) -> Map {
+        let mut map = Map::filled(width, height, 1);
+        for y in 0..height {
+            for x in 0..width {
+                if self.sector_at(x as f64 + 0.5, y as f64 + 0.5).is_some() {
+                    map.set(x, y, 0);
+                }
+            }
+        }
+        for &(x, y) in exits {
+            map.set(x, y, EXIT_TEXTURE);
+        }
+        map
+    }
+
+    /// Where a ray from (x, y) first leaves `sector` further than `after` along it.
+    fn exit(&self, sector: usize, x: f64, y: f64, cos_angle: f64, sin_angle: f64, after: f64) -> Option<SectorHit> {
+        let mut nearest: Option<SectorHit> = None;
+        for wall in &self.sectors[sector].walls {
+            let (along_x, along_y) = (wall.end.0 - wall.start.0, wall.end.1 - wall.start.1);
+            // Solve origin + distance * ray = start + t * along for distance and t
+            let denominator = cos_angle * along_y - sin_angle * along_x;
+            if denominator.abs() < 1e-9 {
+                continue;
+            }
+            let (to_x, to_y) = (wall.start.0 - x, wall.start.1 - y);
+            let distance = (to_x * along_y - to_y * along_x) / denominator;
+            let t = (to_x * sin_angle - to_y * cos_angle) / denominator;
+            if distance <= after + 1e-9 || !(0.0..=1.0).contains(&t) {
+                continue;
+            }
+            if nearest.as_ref().is_none_or(|hit| distance < hit.distance) {
+                let length = (along_x * along_x + along_y * along_y).sqrt();
+                nearest = Some(SectorHit { wall: *wall, distance, along: t * length });
+            }
+        }
+        nearest
+    }
+}
+
+/// Draws the view from the player's sector through the openings into its neighbours, one column
+/// at a time: each sector's floor and ceiling, then its far wall, or the steps around an opening
+/// and whatever is seen through it.
+pub fn render_sectors(sectors: &SectorMap, player: &Player, renderer: &mut Renderer, sprites: &mut [Sprite]) {
+    renderer.clear_framebuffer();
+
+    let num_rays = renderer.screen_width / 6;
+    let screen_height = renderer.screen_height as f64;
+    let horizon = player.horizon(screen_height) as i32;
+    let eye = sectors.floor_at(player.x, player.y) + player.eye_height();
+    let mut buffers = std::mem::take(&mut renderer.scene_buffers);
+    buffers.clear();
+
+    // The screen row a height in the world appears at, at `distance` along a ray
+    let row = |height: f64, distance: f64| (horizon as f64 + (eye - height) * screen_height / distance) as i32;
+
+    for x in 0..num_rays {
+        let angle = player.direction - player.fov / 2.0 + (x as f64 / num_rays as f64) * player.fov;
+        let cos_angle = angle.cos();
+        let sin_angle = angle.sin();
+        let column = x * 6;
+        let columns = column..(column + 6).min(renderer.screen_width);
+        let correction = (player.direction - angle).cos();
+
+        // The rows still to be drawn in this column, narrowed by each opening looked through
+        let (mut top, mut bottom) = (0, renderer.screen_height as i32);
+        let mut sector = sectors.sector_at(player.x, player.y);
+        let mut travelled = 0.0;
+
+        for _ in 0..MAX_SECTOR_DEPTH {
+            let Some(index) = sector else {
+                break;
+            };
+            let Some(hit) = sectors.exit(index, player.x, player.y, cos_angle, sin_angle, travelled) else {
+                break;
+            };
+            let current = &sectors.sectors[index];
+            let ceiling_row = row(current.ceiling, hit.distance).clamp(top, bottom);
+            let floor_row = row(current.floor, hit.distance).clamp(top, bottom);
+
+            // Floor and ceiling, with the depth of each row worked out from its height
+            let mut flat = |renderer: &mut Renderer, rows: std::ops::Range<i32>, height: f64, color: (u8, u8, u8)| {
+                for y in rows {
+                    let depth = ((eye - height) * screen_height / (y - horizon) as f64).abs() * correction;
+                    let row = y as usize * renderer.screen_width;
+                    for pixel in columns.clone().map(|i| row + i) {
+                        let index = pixel * 4;
+                        renderer.framebuffer[index] = color.0;
+                        renderer.framebuffer[index + 1] = color.1;
+                        renderer.framebuffer[index + 2] = color.2;
+                        renderer.framebuffer[index + 3] = 255;
+                        buffers.set_opaque(pixel, depth);
+                    }
+                }
+            };
+            flat(renderer, top..ceiling_row, current.ceiling, (50, 50, 50));
+            flat(renderer, floor_row..bottom, current.floor, (100, 100, 100));
+
+            // A stretch of wall between two heights, textured once per map unit along and up it
+            let depth = hit.distance * correction;
+            let mut wall = |renderer: &mut Renderer, rows: std::ops::Range<i32>| {
+                let filter = renderer.texture_filter;
+                let Some(texture) = renderer.textures.get(hit.wall.texture_id as usize - 1) else {
+                    return;
+                };
+                let texture = texture.filtered(filter, screen_height / hit.distance);
+                for y in rows {
+                    let height = eye - (y - horizon) as f64 * hit.distance / screen_height;
+                    let v = 1.0 - height.rem_euclid(1.0);
+                    let (r, g, b, _) = texture.sample(hit.along.fract(), v, filter);
+                    let row = y as usize * renderer.screen_width;
+                    for pixel in columns.clone().map(|i| row + i) {
+                        let index = pixel * 4;
+                        renderer.framebuffer[index] = r;
+                        renderer.framebuffer[index + 1] = g;
+                        renderer.framebuffer[index + 2] = b;
+                        renderer.framebuffer[index + 3] = 255;
+                        buffers.set_opaque(pixel, depth);
+                    }
+                }
+            };
+
+            let Some(neighbour) = hit.wall.neighbour else {
+                wall(renderer, ceiling_row..floor_row);
+                break;
+            };
+
+            // Where the sector beyond has a lower ceiling or higher floor, the difference is wall
+            let next = &sectors.sectors[neighbour];
+            let upper_row = row(next.ceiling, hit.distance).clamp(ceiling_row, floor_row);
+            let lower_row = row(next.floor, hit.distance).clamp(upper_row, floor_row);
+            wall(renderer, ceiling_row..upper_row);
+            wall(renderer, lower_row..floor_row);
+
+            (top, bottom) = (upper_row, lower_row);
+            if top >= bottom {
+                break;
+            }
+            sector = Some(neighbour);
+            travelled = hit.distance;
+        }
+    }
+
+    sort_back_to_front(sprites, player.x, player.y);
+
+    // Sprites stand on the floor of whichever sector they are in
+    let camera = Camera::new(player, horizon, eye);
+    for sprite in sprites.iter() {
+        let camera = Camera { eye: eye - sectors.floor_at(sprite.x, sprite.y), ..camera };
+        draw_sprite(renderer, &camera, &buffers, sprite.x, sprite.y, sprite.texture_id, None);
+    }
+    renderer.scene_buffers = buffers;
+}
+
+/// The sectors of the courtyard level: a hall with a corner cut off, a step up through a doorway
+/// into a tall seven-sided chamber, and a low passage south to the exit.
+pub fn courtyard() -> SectorMap {
+    const HALL: usize = 0;
+    const STEP: usize = 1;
+    const CHAMBER: usize = 2;
+    const PASSAGE: usize = 3;
+
+    SectorMap {
+        sectors: vec![
+            Sector::new(0.0, 1.2, &[
+                ((1.0, 2.0), 3, None),
+                ((2.0, 1.0), 1, None),
+                ((5.0, 1.0), 1, None),
+                ((5.0, 2.0), 2, Some(STEP)),
+                ((5.0, 4.0), 1, None),
+                ((5.0, 5.0), 1, None),
+                ((4.0, 5.0), 1, Some(PASSAGE)),
+                ((2.0, 5.0), 1, None),
+                ((1.0, 5.0), 1, None),
+            ]),
+            Sector::new(0.2, 1.0, &[
+                ((5.0, 2.0), 2, None),
+                ((6.0, 2.0), 2, Some(CHAMBER)),
+                ((6.0, 4.0), 2, None),
+                ((5.0, 4.0), 2, Some(HALL)),
+            ]),
+            Sector::new(0.4, 2.0, &[
+                ((6.0, 2.0), 3, None),
+                ((7.0, 1.0), 3, None),
+                ((9.0, 1.0), 3, None),
+                ((10.0, 3.0), 3, None),
+                ((9.0, 5.0), 3, None),
+                ((7.0, 5.0), 3, None),
+                ((6.0, 4.0), 2, Some(STEP)),
+            ]),
+            Sector::new(0.0, 0.9, &[
+                ((2.0, 5.0), 1, Some(HALL)),
+                ((4.0, 5.0), 1, None),
+                ((4.0, 8.0), EXIT_TEXTURE, None),
+                ((2.0, 8.0), 1, None),
+            ]),
+        ],
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn square(floor: f64, ceiling: f64, corners: [(f64, f64); 4]) -> Sector {
+        Sector::new(floor, ceiling, &corners.map(|corner| (corner, 1, None)))
+    }
+
+    #[test]
+    fn contains_covers_the_inside_and_edges_only() {
+        let clockwise = square(0.0, 1.0, [(0.0, 0.0), (2.0, 0.0), (2.0, 2.0), (0.0, 2.0)]);
+        let anticlockwise = square(0.0, 1.0, [(0.0, 0.0), (0.0, 2.0), (2.0, 2.0), (2.0, 0.0)]);
+        for sector in [clockwise, anticlockwise] {
+            assert!(sector.contains(1.0, 1.0));
+            // Edges and corners count as inside
+            assert!(sector.contains(2.0, 1.0));
+            assert!(sector.contains(0.0, 0.0));
+            assert!(sector.contains(2.0, 2.0));
+            // In line with an edge but past its end
+            assert!(!sector.contains(3.0, 0.0));
+            assert!(!sector.contains(0.0, -1.0));
+            assert!(!sector.contains(2.0 + 1e-9, 1.0));
+        }
+
+        // A sliver of a triangle, where a point can be just outside the long edge
+        let triangle = Sector::new(0.0, 1.0, &[((0.0, 0.0), 1, None), ((10.0, 0.0), 1, None), ((10.0, 0.1), 1, None)]);
+        assert!(triangle.contains(9.0, 0.05));
+        assert!(!triangle.contains(5.0, 0.06));
+    }
+
+    #[test]
+    fn exit_finds_the_nearest_wall_past_the_start() {
+        let sectors = SectorMap { sectors: vec![square(0.0, 1.0, [(0.0, 0.0), (2.0, 0.0), (2.0, 2.0), (0.0, 2.0)])] };
+
+        let hit = sectors.exit(0, 1.0, 0.5, 1.0, 0.0, 0.0).unwrap();
+        assert!((hit.distance - 1.0).abs() < 1e-9);
+        assert_eq!((hit.wall.start, hit.wall.end), ((2.0, 0.0), (2.0, 2.0)));
+        assert!((hit.along - 0.5).abs() < 1e-9);
+
+        // Diagonally into a corner, where two walls meet
+        let (cos, sin) = (std::f64::consts::FRAC_1_SQRT_2, std::f64::consts::FRAC_1_SQRT_2);
+        let hit = sectors.exit(0, 1.0, 1.0, cos, sin, 0.0).unwrap();
+        assert!((hit.distance - 2.0_f64.sqrt()).abs() < 1e-9);
+
+        // Nothing further on once the ray has passed the only wall ahead
+        assert!(sectors.exit(0, 1.0, 0.5, 1.0, 0.0, 1.0).is_none());
+    }
+
+    #[test]
+    fn can_step_allows_small_steps_with_headroom() {
+        let courtyard = courtyard();
+        // Up the step from the hall, and on up into the chamber
+        assert!(courtyard.can_step(4.5, 3.0, 5.5, 3.0));
+        assert!(courtyard.can_step(5.5, 3.0, 6.5, 3.0));
+        // Straight from the hall floor to the chamber's is too high, but dropping down is fine
+        assert!(!courtyard.can_step(4.5, 3.0, 6.5, 3.0));
+        assert!(courtyard.can_step(6.5, 3.0, 4.5, 3.0));
+        // Outside every sector
+        assert!(!courtyard.can_step(4.5, 3.0, 0.5, 0.5));
+
+        let crawlspace = SectorMap {
+            sectors: vec![
+                square(0.0, 1.0, [(0.0, 0.0), (1.0, 0.0), (1.0, 1.0), (0.0, 1.0)]),
+                square(MAX_STEP, MAX_STEP + MIN_HEADROOM, [(1.0, 0.0), (2.0, 0.0), (2.0, 1.0), (1.0, 1.0)]),
+                square(0.0, MIN_HEADROOM - 0.01, [(2.0, 0.0), (3.0, 0.0), (3.0, 1.0), (2.0, 1.0)]),
+            ],
+        };
+        // Exactly the highest step and the least headroom is allowed, but any less room isn't
+        assert!(crawlspace.can_step(0.5, 0.5, 1.5, 0.5));
+        assert!(!crawlspace.can_step(0.5, 0.5, 2.5, 0.5));
+    }
+}
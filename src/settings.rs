@@ -0,0 +1,129 @@
+use crate::renderer::TextureFilter;
+
+pub const KEY_ENTER: usize = 13;
+pub const KEY_CTRL: usize = 17;
+pub const KEY_ESCAPE: usize = 27;
+pub const KEY_SPACE: usize = 32;
+pub const KEY_PAGE_UP: usize = 33;
+pub const KEY_PAGE_DOWN: usize = 34;
+pub const KEY_LEFT: usize = 37;
+pub const KEY_UP: usize = 38;
+pub const KEY_RIGHT: usize = 39;
+pub const KEY_DOWN: usize = 40;
+
+/// Player actions that can be rebound from the options menu.
+#[derive(Clone, Copy, PartialEq)]
+pub enum Action {
+    Forward,
+    Backward,
+    TurnLeft,
+    TurnRight,
+    Fire,
+    Use,
+    LookUp,
+    LookDown,
+    Jump,
+    Crouch,
+}
+
+impl Action {
+    pub const ALL: [Action; 10] = [
+        Action::Forward,
+        Action::Backward,
+        Action::TurnLeft,
+        Action::TurnRight,
+        Action::Fire,
+        Action::Use,
+        Action::LookUp,
+        Action::LookDown,
+        Action::Jump,
+        Action::Crouch,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Action::Forward => "Forward",
+            Action::Backward => "Backward",
+            Action::TurnLeft => "Turn left",
+            Action::TurnRight => "Turn right",
+            Action::Fire => "Fire",
+            Action::Use => "Use",
+            Action::LookUp => "Look up",
+            Action::LookDown => "Look down",
+            Action::Jump => "Jump",
+            Action::Crouch => "Crouch",
+        }
+    }
+}
+
+/// Key code bound to each action, indexed in the order of `Action::ALL`.
+#[derive(Clone, Copy)]
+pub struct KeyBindings {
+    keys: [usize; 10],
+}
+
+impl KeyBindings {
+    pub fn key(&self, action: Action) -> usize {
+        self.keys[action as usize]
+    }
+
+    pub fn bind(&mut self, action: Action, key_code: usize) {
+        self.keys[action as usize] = key_code;
+    }
+}
+
+impl Default for KeyBindings {
+    fn default() -> Self {
+        KeyBindings {
+            keys: [b'W' as usize, b'S' as usize, b'A' as usize, b'D' as usize, KEY_CTRL, KEY_SPACE, KEY_PAGE_UP, KEY_PAGE_DOWN, b'Q' as usize, b'C' as usize],
+        }
+    }
+}
+
+/// A human readable name for a key code, as shown in the bindings menu.
+pub fn key_name(key_code: usize) -> String {
+    match key_code {
+        KEY_ENTER => "Enter".to_string(),
+        16 => "Shift".to_string(),
+        KEY_CTRL => "Ctrl".to_string(),
+        18 => "Alt".to_string(),
+        KEY_SPACE => "Space".to_string(),
+        KEY_PAGE_UP => "Page Up".to_string(),
+        KEY_PAGE_DOWN => "Page Down".to_string(),
+        KEY_LEFT => "Left".to_string(),
+        KEY_UP => "Up".to_string(),
+        KEY_RIGHT => "Right".to_string(),
+        KEY_DOWN => "Down".to_string(),
+        48..=57 | 65..=90 => (key_code as u8 as char).to_string(),
+        _ => format!("Key {}", key_code),
+    }
+}
+
+pub const MIN_SENSITIVITY: f64 = 0.25;
+pub const MAX_SENSITIVITY: f64 = 3.0;
+pub const MIN_FOV_DEGREES: f64 = 45.0;
+pub const MAX_FOV_DEGREES: f64 = 110.0;
+pub const MIN_RESOLUTION_SCALE: f64 = 0.25;
+pub const MAX_RESOLUTION_SCALE: f64 = 1.0;
+
+pub struct Settings {
+    /// Multiplier on the turning speed.
+    pub sensitivity: f64,
+    pub fov_degrees: f64,
+    /// Fraction of the canvas resolution the scene is rendered at before being stretched to fit.
+    pub resolution_scale: f64,
+    pub texture_filter: TextureFilter,
+    pub bindings: KeyBindings,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            sensitivity: 1.0,
+            fov_degrees: 60.0,
+            resolution_scale: 1.0,
+            texture_filter: TextureFilter::Mipmapped,
+            bindings: KeyBindings::default(),
+        }
+    }
+}
@@ -0,0 +1,313 @@
+use crate::dungeon::EXIT_TEXTURE;
+use crate::entities::World;
+use crate::game::{Map, Player};
+use crate::intermission::Intermission;
+use crate::inventory::{InventoryEvent, Item};
+use crate::level::{level_seed, Episode, LevelSource};
+use crate::replay::{TickInput, WEAPON_SLOTS};
+use crate::rng::{Rng, Stream};
+use crate::settings::Action;
+use crate::sprites::Sprite;
+use crate::weapon::{Attack, Weapon, WeaponKind};
+
+/// Simulation ticks per second. The game advances at this rate whatever the display's refresh rate.
+pub const TICKS_PER_SECOND: u32 = 60;
+/// The weapon each number key selects, in slot order.
+const SLOT_WEAPONS: [WeaponKind; WEAPON_SLOTS] = [WeaponKind::Sword, WeaponKind::RocketLauncher, WeaponKind::FireStaff];
+/// Fraction of screen height the horizon moves per tick of looking up or down.
+const LOOK_SPEED: f64 = 0.015;
+
+/// Something that happened during a tick that the player should be shown.
+pub enum Notice {
+    Message(String),
+    Inventory(InventoryEvent),
+    /// The last level's intermission was dismissed, so the game is over.
+    EpisodeOver,
+}
+
+/// Everything that makes up a game in progress, apart from how it is shown and controlled. It runs
+/// without a browser, so whole games can be played through in native tests.
+pub struct Simulation {
+    pub player: Player,
+    pub map: Map,
+    pub world: World,
+    pub weapon: Weapon,
+    pub sprites: Vec<Sprite>,
+    pub episode: Episode,
+    /// Seed the whole game follows from. Each level's layout is derived from it and the level number.
+    pub game_seed: u64,
+    pub level_number: usize,
+    pub intermission: Option<Intermission>,
+    /// Field of view the player is given at the start of each level, from the settings.
+    pub fov: f64,
+    /// Notices from the ticks so far that haven't been collected by `drain_notices` yet.
+    pub notices: Vec<Notice>,
+}
+
+impl Simulation {
+    /// A fresh game from `seed`, at the start of the episode's first level.
+    pub fn new(episode: Episode, seed: u64) -> Self {
+        let player = Player::new();
+        let mut simulation = Simulation {
+            fov: player.fov,
+            player,
+            map: Map::new(),
+            world: World::new(Vec::new(), Vec::new(), Rng::new(seed, Stream::Gameplay)),
+            weapon: Weapon::new(WeaponKind::Sword),
+            sprites: Vec::new(),
+            episode,
+            game_seed: seed,
+            level_number: 0,
+            intermission: None,
+            notices: Vec::new(),
+        };
+        simulation.reset_level(Rng::new(seed, Stream::Gameplay));
+        simulation
+    }
+
+    pub fn drain_notices(&mut self) -> Vec<Notice> {
+        std::mem::take(&mut self.notices)
+    }
+
+    /// Where the current level comes from.
+    pub fn level_source(&self) -> LevelSource {
+        // Only an episode's last level can be finished with no next one, and that ends the game
+        self.episode.level(self.level_number).unwrap_or(LevelSource::Classic)
+    }
+
+    /// Advances the simulation by one tick. Everything it does depends only on the game state and
+    /// its arguments, so a recorded run plays back identically.
+    pub fn tick(&mut self, input: TickInput, sensitivity: f64) {
+        if let Some(intermission) = &mut self.intermission {
+            if intermission.tick(input.action(Action::Use) || input.action(Action::Fire)) {
+                self.end_intermission();
+            }
+            return;
+        }
+
+        self.player.tick();
+        self.map.update();
+
+        if self.player.is_dead() {
+            // Input is ignored while dead, apart from asking to respawn once the camera has settled
+            if self.player.can_respawn() && (input.action(Action::Use) || input.action(Action::Fire)) {
+                self.restart();
+            }
+            self.player.update_camera(false, false, 0.0);
+            self.world.update(&mut self.player, &self.map);
+            return;
+        }
+
+        let (mut old_x, mut old_y) = (self.player.x, self.player.y);
+        let turn_speed = 0.05 * sensitivity;
+
+        // Player movement controls
+        let storey = self.map.storey(self.player.storey);
+        let mut portals = Vec::new();
+        if input.action(Action::Forward) {
+            portals.extend(self.player.move_forward(0.1, storey));
+        }
+        if input.action(Action::Backward) {
+            portals.extend(self.player.move_backward(0.1, storey));
+        }
+        // Measure the step from where the player started as seen through any portal they took, so
+        // going through one doesn't count as a huge stride
+        for portal in portals {
+            (old_x, old_y) = portal.transform_point(old_x, old_y);
+        }
+        // Stepping onto a lift rides it to the other storey it stops at. Only stepping on counts,
+        // so arriving on one doesn't send the player straight back.
+        let cell = (self.player.x.floor() as i32, self.player.y.floor() as i32);
+        if cell != (old_x.floor() as i32, old_y.floor() as i32) {
+            if let Some(storey) = self.map.lift(cell.0, cell.1, self.player.storey) {
+                self.player.ride(storey);
+            }
+        }
+        if input.action(Action::TurnLeft) {
+            self.player.turn_left(turn_speed);
+        }
+        if input.action(Action::TurnRight) {
+            self.player.turn_right(turn_speed);
+        }
+        if input.action(Action::LookUp) {
+            self.player.look(LOOK_SPEED * sensitivity);
+        }
+        if input.action(Action::LookDown) {
+            self.player.look(-LOOK_SPEED * sensitivity);
+        }
+
+        let storey = self.map.storey_mut(self.player.storey);
+        if let Some((x, y)) = input.action(Action::Use).then(|| self.player.facing_wall(storey)).flatten() {
+            if storey.get(x, y) == Some(EXIT_TEXTURE) {
+                self.finish_level();
+                return;
+            }
+
+            // Secret walls slide straight away from the player, along whichever axis they face most
+            let (cos, sin) = (self.player.direction.cos(), self.player.direction.sin());
+            let (dx, dy) = if cos.abs() > sin.abs() { (cos.signum() as i32, 0) } else { (0, sin.signum() as i32) };
+            let (player, world) = (&self.player, &self.world);
+            if storey.push(x, y, dx, dy, |x, y| world.occupies(player, x, y)) {
+                self.world.stats.secrets += 1;
+                self.notices.push(Notice::Message("You found a secret!".to_string()));
+            }
+        }
+
+        for (slot, kind) in SLOT_WEAPONS.into_iter().enumerate() {
+            if input.weapon_slot(slot) && self.player.inventory.has_weapon(kind) {
+                self.weapon.select(kind);
+            }
+        }
+        if input.action(Action::Fire) && self.weapon.is_ready() {
+            let loaded = match self.weapon.kind.ammo() {
+                Some(ammo) => self.player.inventory.use_ammo(ammo, 1),
+                None => true,
+            };
+            if loaded {
+                self.weapon.trigger();
+            }
+        }
+
+        let speed = ((self.player.x - old_x).powi(2) + (self.player.y - old_y).powi(2)).sqrt();
+        self.player.update_camera(input.action(Action::Jump), input.action(Action::Crouch), speed);
+        if let Some(attack) = self.weapon.update(speed) {
+            self.attack(attack);
+        }
+
+        self.world.update(&mut self.player, &self.map);
+        for event in self.player.inventory.drain_events() {
+            if let InventoryEvent::Collected(Item::Weapon(kind)) = event {
+                self.weapon.select(kind);
+            }
+            self.notices.push(Notice::Inventory(event));
+        }
+    }
+
+    fn attack(&mut self, attack: Attack) {
+        let direction = self.player.direction;
+        match attack {
+            Attack::Melee { range, damage } => {
+                self.world.melee(self.player.x, self.player.y, self.player.storey, direction, range, damage);
+            }
+            Attack::Projectile(kind) => {
+                // Spawn slightly ahead of the player so the projectile doesn't start inside the camera
+                let x = self.player.x + 0.3 * direction.cos();
+                let y = self.player.y + 0.3 * direction.sin();
+                self.world.spawn_projectile(kind, x, y, self.player.storey, direction);
+            }
+        }
+    }
+
+    /// Shows the intermission screen for the level just finished.
+    fn finish_level(&mut self) {
+        let last = self.episode.level(self.level_number + 1).is_none();
+        self.intermission = Some(Intermission::new(self.level_number, self.world.stats, last));
+    }
+
+    /// Moves on from the intermission: into the next level, carrying over the player's health,
+    /// armor, weapons and ammo, or ends the game once the episode is over.
+    fn end_intermission(&mut self) {
+        let Some(intermission) = self.intermission.take() else {
+            return;
+        };
+        if intermission.last {
+            self.notices.push(Notice::EpisodeOver);
+            return;
+        }
+
+        let carried = std::mem::replace(&mut self.player, Player::new());
+        let weapon = self.weapon.kind;
+        self.level_number += 1;
+        let rng = self.world.rng.clone();
+        self.reset_level(rng);
+
+        self.player.health = carried.health;
+        self.player.armor = carried.armor;
+        self.player.inventory = carried.inventory;
+        // Keys only open doors in the level they were found in
+        self.player.inventory.keys.clear();
+        self.weapon = Weapon::new(weapon);
+
+        let text = format!("Level {}: {}", self.level_number + 1, self.level_source().name());
+        self.notices.push(Notice::Message(text));
+    }
+
+    /// Puts the player back at the start of the level after dying. The random sequence carries on
+    /// rather than restarting so the run stays reproducible from its original seed.
+    fn restart(&mut self) {
+        let rng = self.world.rng.clone();
+        self.reset_level(rng);
+    }
+
+    /// Resets the player, entities and weapon to the start of the level.
+    fn reset_level(&mut self, rng: Rng) {
+        let level = self.level_source().build(level_seed(self.game_seed, self.level_number));
+        self.player = Player::new();
+        self.player.x = level.spawn_x;
+        self.player.y = level.spawn_y;
+        self.player.direction = level.spawn_direction;
+        self.player.fov = self.fov;
+        self.map = level.map;
+        self.sprites = level.sprites;
+        self.world = World::new(level.entities, level.pickups, rng);
+        self.world.stats.total_secrets = self.map.secrets.len() as u32;
+        self.weapon = Weapon::new(WeaponKind::Sword);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::game::MAX_HEALTH;
+    use crate::inventory::AmmoKind;
+    use crate::weapon::WeaponState;
+
+    fn pressing(action: Action) -> TickInput {
+        let mut input = TickInput::default();
+        input.set_action(action, true);
+        input
+    }
+
+    #[test]
+    fn firing_spends_ammo_until_there_is_none() {
+        let mut game = Simulation::new(Episode::Campaign, 1);
+        game.player.inventory.add(Item::Weapon(WeaponKind::RocketLauncher));
+        game.player.inventory.rockets = 1;
+        game.weapon = Weapon { state: WeaponState::Ready, ..Weapon::new(WeaponKind::RocketLauncher) };
+
+        game.tick(pressing(Action::Fire), 1.0);
+        assert_eq!(game.player.inventory.rockets, 0);
+        assert!(!game.weapon.is_ready());
+        // The launcher fires on the first frame of its sequence
+        assert_eq!(game.world.projectiles.len(), 1);
+        while !game.weapon.is_ready() {
+            game.tick(TickInput::default(), 1.0);
+        }
+        game.drain_notices();
+
+        game.tick(pressing(Action::Fire), 1.0);
+        assert!(game.weapon.is_ready());
+        let notices = game.drain_notices();
+        assert!(matches!(notices[..], [Notice::Inventory(InventoryEvent::OutOfAmmo(AmmoKind::Rockets))]));
+    }
+
+    #[test]
+    fn dying_and_pressing_use_restarts_the_level() {
+        let mut game = Simulation::new(Episode::Campaign, 1);
+        let start = (game.player.x, game.player.y);
+        game.player.inventory.rockets = 3;
+        game.tick(pressing(Action::Forward), 1.0);
+        game.player.damage(MAX_HEALTH);
+
+        // Use does nothing until the death tilt has played out
+        game.tick(pressing(Action::Use), 1.0);
+        assert!(game.player.is_dead());
+        while !game.player.can_respawn() {
+            game.tick(TickInput::default(), 1.0);
+        }
+        game.tick(pressing(Action::Use), 1.0);
+        assert_eq!(game.player.health, MAX_HEALTH);
+        assert_eq!((game.player.x, game.player.y), start);
+        assert_eq!(game.player.inventory.rockets, 0);
+    }
+}
@@ -1,6 +1,31 @@
+#[derive(Clone, Copy)]
 pub struct Sprite {
     pub x: f64,
     pub y: f64,
+    /// The storey it stands on.
+    pub storey: usize,
     pub texture_id: usize,
     pub distance: f64
-}
\ No newline at end of file
+}
+
+/// Measures each sprite's distance from (x, y) and orders them farthest first, the order they are
+/// drawn in so nearer ones cover those behind.
+pub fn sort_back_to_front(sprites: &mut [Sprite], x: f64, y: f64) {
+    for sprite in sprites.iter_mut() {
+        sprite.distance = ((sprite.x - x).powi(2) + (sprite.y - y).powi(2)).sqrt();
+    }
+    sprites.sort_by(|a, b| b.distance.total_cmp(&a.distance));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sprites_are_sorted_farthest_first() {
+        let sprite = |x: f64| Sprite { x, y: 0.0, storey: 0, texture_id: 1, distance: 0.0 };
+        let mut sprites = vec![sprite(1.0), sprite(-4.0), sprite(2.5)];
+        sort_back_to_front(&mut sprites, 0.5, 0.0);
+        assert_eq!(sprites.iter().map(|sprite| sprite.distance).collect::<Vec<_>>(), vec![4.5, 2.0, 0.5]);
+    }
+}
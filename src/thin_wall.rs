@@ -0,0 +1,99 @@
+/// How close to a thin wall's line anything can get, in map units. Walls are drawn with no
+/// thickness, but collision treats them as this far through on either side.
+pub const THIN_WALL_THICKNESS: f64 = 0.1;
+
+/// A wall that is a single line segment across its cell rather than the whole block, such as a
+/// fence, a window or the angled wall of a diagonal corridor. Endpoints are relative to the cell's
+/// top-left corner, so (0, 0) to (1, 1) runs corner to corner.
+#[derive(Clone, Copy)]
+pub struct ThinWall {
+    pub start: (f64, f64),
+    pub end: (f64, f64),
+}
+
+/// Where a ray crosses a thin wall.
+pub struct ThinWallHit {
+    pub distance: f64,
+    /// How far along the wall from `start` to `end` the ray crossed it, from 0 to 1.
+    pub texture_x: f64,
+}
+
+impl ThinWall {
+    /// Across the middle of the cell from west to east.
+    pub const HORIZONTAL: ThinWall = ThinWall { start: (0.0, 0.5), end: (1.0, 0.5) };
+    /// Down the middle of the cell from north to south.
+    pub const VERTICAL: ThinWall = ThinWall { start: (0.5, 0.0), end: (0.5, 1.0) };
+    /// From the north-west corner to the south-east one.
+    pub const DIAGONAL: ThinWall = ThinWall { start: (0.0, 0.0), end: (1.0, 1.0) };
+    /// From the north-east corner to the south-west one.
+    pub const ANTI_DIAGONAL: ThinWall = ThinWall { start: (1.0, 0.0), end: (0.0, 1.0) };
+
+    /// Intersects a ray from (origin_x, origin_y) with the wall as it sits in cell (cell_x, cell_y).
+    pub fn intersect(&self, cell_x: i32, cell_y: i32, origin_x: f64, origin_y: f64, cos_angle: f64, sin_angle: f64) -> Option<ThinWallHit> {
+        let (start_x, start_y) = (cell_x as f64 + self.start.0, cell_y as f64 + self.start.1);
+        let (along_x, along_y) = (self.end.0 - self.start.0, self.end.1 - self.start.1);
+
+        // Solve origin + distance * ray = start + t * along for distance and t
+        let denominator = cos_angle * along_y - sin_angle * along_x;
+        if denominator.abs() < 1e-9 {
+            return None;
+        }
+        let (to_x, to_y) = (start_x - origin_x, start_y - origin_y);
+        let distance = (to_x * along_y - to_y * along_x) / denominator;
+        let t = (to_x * sin_angle - to_y * cos_angle) / denominator;
+        if distance <= 0.0 || !(0.0..=1.0).contains(&t) {
+            return None;
+        }
+        Some(ThinWallHit { distance, texture_x: t })
+    }
+
+    /// Distance from a point, relative to the cell's top-left corner, to the nearest point on the wall.
+    pub fn distance_to(&self, x: f64, y: f64) -> f64 {
+        let (along_x, along_y) = (self.end.0 - self.start.0, self.end.1 - self.start.1);
+        let length_squared = along_x * along_x + along_y * along_y;
+        let t = if length_squared > 0.0 {
+            (((x - self.start.0) * along_x + (y - self.start.1) * along_y) / length_squared).clamp(0.0, 1.0)
+        } else {
+            0.0
+        };
+        let (nearest_x, nearest_y) = (self.start.0 + t * along_x, self.start.1 + t * along_y);
+        ((x - nearest_x).powi(2) + (y - nearest_y).powi(2)).sqrt()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn close(a: f64, b: f64) -> bool {
+        (a - b).abs() < 1e-9
+    }
+
+    #[test]
+    fn rays_cross_at_the_right_distance_and_texture_coordinate() {
+        // Heading east from the west edge of cell (2, 3), a quarter of the way down
+        let hit = ThinWall::VERTICAL.intersect(2, 3, 2.0, 3.25, 1.0, 0.0).unwrap();
+        assert!(close(hit.distance, 0.5));
+        assert!(close(hit.texture_x, 0.25));
+
+        // Heading north up the middle of the cell meets the diagonal halfway along it
+        let hit = ThinWall::DIAGONAL.intersect(0, 0, 0.5, 2.0, 0.0, -1.0).unwrap();
+        assert!(close(hit.distance, 1.5));
+        assert!(close(hit.texture_x, 0.5));
+    }
+
+    #[test]
+    fn parallel_rays_and_walls_behind_miss() {
+        assert!(ThinWall::HORIZONTAL.intersect(0, 0, 0.0, 0.5, 1.0, 0.0).is_none());
+        assert!(ThinWall::VERTICAL.intersect(0, 0, 0.8, 0.5, 1.0, 0.0).is_none());
+        // Passing beside the end of the segment
+        assert!(ThinWall::HORIZONTAL.intersect(0, 0, 1.5, 0.0, 0.0, 1.0).is_none());
+    }
+
+    #[test]
+    fn distance_is_measured_to_the_nearest_point_of_the_segment() {
+        assert!(close(ThinWall::HORIZONTAL.distance_to(0.5, 0.2), 0.3));
+        assert!(close(ThinWall::HORIZONTAL.distance_to(1.3, 0.9), 0.5));
+        assert!(close(ThinWall::ANTI_DIAGONAL.distance_to(0.0, 0.0), 0.5f64.sqrt()));
+    }
+}
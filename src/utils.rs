@@ -1,16 +1,4 @@
-use wasm_bindgen::prelude::*;
-use wasm_bindgen::JsValue;
-use web_sys::{window, Performance, console};
-use js_sys::Math;
-
-/// Generate a random floating-point number between 0 and 1.
-pub fn js_random() -> f64 {
-    Math::random()
-}
-
-pub fn random_range(min: f64, max: f64) -> f64 {
-    min + (js_random() * (max - min))
-}
+use web_sys::{window, Performance};
 
 pub fn get_performance() -> Performance {
     window()
@@ -0,0 +1,310 @@
+use crate::entities::{ProjectileKind, EXPLOSION_TEXTURE};
+use crate::inventory::AmmoKind;
+use crate::renderer::Renderer;
+use crate::rng::Rng;
+
+// Texture ids as loaded by `Engine::new` (1-based, like the map)
+pub const SWORD_TEXTURE: usize = 4;
+pub const LAUNCHER_TEXTURE: usize = 9;
+pub const STAFF_TEXTURE: usize = 10;
+
+/// Ticks each frame of a fire sequence is held for.
+const FRAME_TICKS: u32 = 3;
+/// Ticks taken to lower or raise a weapon when switching.
+const SWITCH_TICKS: u32 = 10;
+/// Bob phase advanced per map unit travelled.
+const BOB_FREQUENCY: f64 = 12.0;
+/// Maximum bob displacement, in texture pixels.
+const BOB_AMPLITUDE: f64 = 5.0;
+/// Movement speed (map units per tick) at which the bob reaches full amplitude.
+const BOB_FULL_SPEED: f64 = 0.1;
+/// Furthest the muzzle flash wanders from its spot, in texture pixels.
+const FLASH_JITTER: f64 = 2.0;
+/// Fraction of the horizon's movement the weapon follows when the player looks up or down.
+const PITCH_FOLLOW: f64 = 0.25;
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum WeaponKind {
+    Sword,
+    RocketLauncher,
+    FireStaff,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Attack {
+    Melee { range: f64, damage: f64 },
+    Projectile(ProjectileKind),
+}
+
+impl WeaponKind {
+    pub fn texture_id(self) -> usize {
+        match self {
+            WeaponKind::Sword => SWORD_TEXTURE,
+            WeaponKind::RocketLauncher => LAUNCHER_TEXTURE,
+            WeaponKind::FireStaff => STAFF_TEXTURE,
+        }
+    }
+
+    pub fn attack(self) -> Attack {
+        match self {
+            WeaponKind::Sword => Attack::Melee { range: 1.0, damage: 25.0 },
+            WeaponKind::RocketLauncher => Attack::Projectile(ProjectileKind::Rocket),
+            WeaponKind::FireStaff => Attack::Projectile(ProjectileKind::Fireball),
+        }
+    }
+
+    /// The ammo spent per attack, if any.
+    pub fn ammo(self) -> Option<AmmoKind> {
+        match self {
+            WeaponKind::Sword => None,
+            WeaponKind::RocketLauncher => Some(AmmoKind::Rockets),
+            WeaponKind::FireStaff => Some(AmmoKind::Mana),
+        }
+    }
+
+    /// Ammo that comes with the weapon when it is picked up.
+    pub fn starting_ammo(self) -> u32 {
+        match self {
+            WeaponKind::Sword => 0,
+            WeaponKind::RocketLauncher => 5,
+            WeaponKind::FireStaff => 20,
+        }
+    }
+
+    /// Offset of the weapon from the bottom centre of the screen when idle, in texture pixels.
+    fn rest_offset(self) -> (i32, i32) {
+        match self {
+            WeaponKind::Sword => (24, 10),
+            WeaponKind::RocketLauncher => (14, 4),
+            WeaponKind::FireStaff => (18, 6),
+        }
+    }
+
+    /// Offsets applied on top of the rest position for each frame of the fire animation.
+    fn fire_sequence(self) -> &'static [(i32, i32)] {
+        match self {
+            WeaponKind::Sword => &[(6, -6), (0, 0), (-12, 4), (-24, 10), (-12, 6), (0, 2)],
+            WeaponKind::RocketLauncher => &[(0, 6), (0, 4), (0, 2), (0, 1)],
+            WeaponKind::FireStaff => &[(-2, -4), (-4, -8), (-2, -4), (0, 0)],
+        }
+    }
+
+    /// The frame of the fire sequence on which the attack actually happens.
+    fn attack_frame(self) -> usize {
+        match self {
+            WeaponKind::Sword => 2,
+            WeaponKind::RocketLauncher => 0,
+            WeaponKind::FireStaff => 1,
+        }
+    }
+
+    /// Where the muzzle flash is drawn on the attack frame, in texture pixels.
+    fn muzzle_flash(self) -> Option<(i32, i32)> {
+        match self {
+            WeaponKind::Sword => None,
+            WeaponKind::RocketLauncher => Some((32, 26)),
+            WeaponKind::FireStaff => Some((30, 16)),
+        }
+    }
+}
+
+/// Each state carries the number of ticks spent in it so far.
+#[derive(Clone, Copy, PartialEq)]
+pub enum WeaponState {
+    Raising(u32),
+    Ready,
+    Firing(u32),
+    Lowering(u32),
+}
+
+/// The first-person weapon held by the player, drawn over the scene.
+pub struct Weapon {
+    pub kind: WeaponKind,
+    pub state: WeaponState,
+    pub pending: Option<WeaponKind>,
+    pub bob_phase: f64,
+    pub bob_amount: f64,
+}
+
+impl Weapon {
+    pub fn new(kind: WeaponKind) -> Self {
+        Weapon {
+            kind,
+            state: WeaponState::Raising(0),
+            pending: None,
+            bob_phase: 0.0,
+            bob_amount: 0.0,
+        }
+    }
+
+    /// Switches to another weapon, lowering the current one first.
+    pub fn select(&mut self, kind: WeaponKind) {
+        if kind == self.kind && self.pending.is_none() {
+            return;
+        }
+
+        self.pending = Some(kind);
+        if let WeaponState::Raising(ticks) = self.state {
+            // Reverse a half-finished raise from where it currently is
+            self.state = WeaponState::Lowering(SWITCH_TICKS - ticks);
+        }
+    }
+
+    /// True when the weapon is up and not busy firing or switching.
+    pub fn is_ready(&self) -> bool {
+        self.state == WeaponState::Ready && self.pending.is_none()
+    }
+
+    /// Starts the fire animation if the weapon is ready.
+    pub fn trigger(&mut self) {
+        if self.is_ready() {
+            self.state = WeaponState::Firing(0);
+        }
+    }
+
+    /// Advances the weapon by one tick. `speed` is the distance the player moved this tick.
+    /// Returns the attack to perform if this tick is the attack frame of the fire sequence.
+    pub fn update(&mut self, speed: f64) -> Option<Attack> {
+        self.bob_phase += speed * BOB_FREQUENCY;
+        let target = (speed / BOB_FULL_SPEED).min(1.0);
+        self.bob_amount += (target - self.bob_amount) * 0.2;
+
+        let mut attack = None;
+        self.state = match self.state {
+            WeaponState::Raising(ticks) if ticks + 1 >= SWITCH_TICKS => WeaponState::Ready,
+            WeaponState::Raising(ticks) => WeaponState::Raising(ticks + 1),
+            WeaponState::Ready if self.pending.is_some() => WeaponState::Lowering(0),
+            WeaponState::Ready => WeaponState::Ready,
+            WeaponState::Firing(ticks) => {
+                if ticks % FRAME_TICKS == 0 && (ticks / FRAME_TICKS) as usize == self.kind.attack_frame() {
+                    attack = Some(self.kind.attack());
+                }
+
+                if ((ticks + 1) / FRAME_TICKS) as usize >= self.kind.fire_sequence().len() {
+                    WeaponState::Ready
+                } else {
+                    WeaponState::Firing(ticks + 1)
+                }
+            }
+            WeaponState::Lowering(ticks) if ticks + 1 >= SWITCH_TICKS => {
+                self.kind = self.pending.take().unwrap_or(self.kind);
+                WeaponState::Raising(0)
+            }
+            WeaponState::Lowering(ticks) => WeaponState::Lowering(ticks + 1),
+        };
+
+        attack
+    }
+
+    /// Draws the weapon over the scene. `horizon_shift` is how far the horizon has moved down the
+    /// screen from its middle, which the weapon partly follows.
+    pub fn draw(&self, renderer: &mut Renderer, rng: &mut Rng, horizon_shift: f64) {
+        let scale = (renderer.screen_height / 150).max(1);
+        let Some(texture) = renderer.textures.get(self.kind.texture_id() - 1) else {
+            return;
+        };
+        let (width, height) = ((texture.width * scale) as i32, (texture.height * scale) as i32);
+
+        let (rest_x, rest_y) = self.kind.rest_offset();
+        let sequence = self.kind.fire_sequence();
+        let (frame, (anim_x, anim_y)) = match self.state {
+            WeaponState::Firing(ticks) => {
+                let frame = ((ticks / FRAME_TICKS) as usize).min(sequence.len() - 1);
+                (Some(frame), sequence[frame])
+            }
+            _ => (None, (0, 0)),
+        };
+
+        let switch_progress = match self.state {
+            WeaponState::Raising(ticks) => 1.0 - ticks as f64 / SWITCH_TICKS as f64,
+            WeaponState::Lowering(ticks) => ticks as f64 / SWITCH_TICKS as f64,
+            _ => 0.0,
+        };
+
+        let bob_x = self.bob_phase.sin() * BOB_AMPLITUDE * self.bob_amount;
+        let bob_y = self.bob_phase.cos().abs() * BOB_AMPLITUDE * self.bob_amount;
+        let offset_x = (rest_x + anim_x) as f64 + bob_x;
+        let offset_y = (rest_y + anim_y) as f64 + bob_y + switch_progress * texture.height as f64;
+
+        let x = renderer.screen_width as i32 / 2 - width / 2 + (offset_x * scale as f64) as i32;
+        // Sink the weapon a little when looking up, but never lift it off the bottom of the screen
+        let pitch_y = (horizon_shift * PITCH_FOLLOW).max(0.0) as i32;
+        let y = renderer.screen_height as i32 - height + (offset_y * scale as f64) as i32 + pitch_y;
+        renderer.blit_texture(self.kind.texture_id() - 1, x, y, scale);
+
+        let flash = renderer.textures.get(EXPLOSION_TEXTURE - 1);
+        if let (Some(frame), Some((flash_x, flash_y)), Some(flash)) = (frame, self.kind.muzzle_flash(), flash) {
+            if frame == self.kind.attack_frame() {
+                let flash_scale = (scale / 2).max(1);
+                let (flash_width, flash_height) = ((flash.width * flash_scale) as i32, (flash.height * flash_scale) as i32);
+                // Jitter the flash a little so repeated shots don't look stamped on
+                let jitter = scale as f64 * FLASH_JITTER;
+                let fx = x + flash_x * scale as i32 - flash_width / 2 + rng.range(-jitter, jitter) as i32;
+                let fy = y + flash_y * scale as i32 - flash_height / 2 + rng.range(-jitter, jitter) as i32;
+                renderer.blit_texture(EXPLOSION_TEXTURE - 1, fx, fy, flash_scale);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ready(kind: WeaponKind) -> Weapon {
+        Weapon { state: WeaponState::Ready, ..Weapon::new(kind) }
+    }
+
+    #[test]
+    fn switching_lowers_then_raises_the_new_weapon() {
+        let mut weapon = ready(WeaponKind::Sword);
+        weapon.select(WeaponKind::FireStaff);
+        assert!(weapon.pending == Some(WeaponKind::FireStaff));
+
+        weapon.update(0.0);
+        for _ in 0..SWITCH_TICKS {
+            assert!(weapon.kind == WeaponKind::Sword);
+            weapon.update(0.0);
+        }
+        assert!(weapon.kind == WeaponKind::FireStaff);
+        assert!(weapon.state == WeaponState::Raising(0));
+
+        for _ in 0..SWITCH_TICKS {
+            weapon.update(0.0);
+        }
+        assert!(weapon.state == WeaponState::Ready);
+    }
+
+    #[test]
+    fn switching_mid_raise_lowers_from_where_it_got_to() {
+        let mut weapon = Weapon::new(WeaponKind::Sword);
+        for _ in 0..4 {
+            weapon.update(0.0);
+        }
+        weapon.select(WeaponKind::RocketLauncher);
+        assert!(weapon.state == WeaponState::Lowering(SWITCH_TICKS - 4));
+    }
+
+    #[test]
+    fn firing_attacks_once_on_the_attack_frame() {
+        for kind in [WeaponKind::Sword, WeaponKind::RocketLauncher, WeaponKind::FireStaff] {
+            let mut weapon = ready(kind);
+            weapon.trigger();
+            let length = kind.fire_sequence().len() as u32 * FRAME_TICKS;
+
+            let mut attacks = Vec::new();
+            for tick in 0..length {
+                assert!(weapon.state != WeaponState::Ready);
+                // Pulling the trigger again mid-swing does nothing until the sequence is over
+                weapon.trigger();
+                if let Some(attack) = weapon.update(0.0) {
+                    attacks.push((tick, attack));
+                }
+            }
+            assert!(weapon.state == WeaponState::Ready);
+            assert_eq!(attacks.len(), 1);
+            assert_eq!(attacks[0].0, kind.attack_frame() as u32 * FRAME_TICKS);
+            assert!(attacks[0].1 == kind.attack());
+        }
+    }
+}